@@ -5,8 +5,8 @@
 #![feature(ptr_internals)]
 #![no_std]
 
-extern crate spin;
 extern crate alloc;
+extern crate sync_opsys;
 
 mod block;
 mod allocator;
@@ -1,11 +1,12 @@
-use spin::Mutex;
+use sync_opsys::IrqSpinLock;
 
 use super::allocator::Allocator;
 use alloc::allocator::{Alloc, Layout, AllocErr};
 use core::slice;
 
+/// An `Allocator` behind a lock that disables interrupts, as interrupt handlers may allocate
 pub struct LockedAllocator {
-    allocator: Mutex<Allocator>,
+    allocator: IrqSpinLock<Allocator>,
 }
 
 impl LockedAllocator {
@@ -14,7 +15,7 @@ impl LockedAllocator {
     /// Creates a `LockedAllocator` with an underlying empty memory buffer. This should not be
     /// used for allocations.
     pub const fn empty() -> LockedAllocator {
-        LockedAllocator { allocator: Mutex::new(Allocator::empty()) }
+        LockedAllocator { allocator: IrqSpinLock::new(Allocator::empty()) }
     }
 
     /// Initialize an `Allocator`
@@ -1,8 +1,12 @@
-use multiboot2::ElfSection;
-use memory::Frame;
+use frame::Frame;
 
 pub struct Entry(u64);
 
+/// Bits 52-61 are ignored by the CPU, they hold the number of used entries in a table (see
+/// `Table::entry_count`)
+const COUNTER_MASK: u64 = 0x3ff0_0000_0000_0000;
+const COUNTER_SHIFT: u64 = 52;
+
 bitflags! {
     pub flags EntryFlags: u64 {
         const PRESENT =         1 << 0,
@@ -14,38 +18,19 @@ bitflags! {
         const DIRTY =           1 << 6,
         const HUGE_PAGE =       1 << 7,
         const GLOBAL =          1 << 8,
+        /// Ignored by the CPU, marks a read only page shared until it is written to
+        const COPY_ON_WRITE =   1 << 9,
         const NO_EXECUTE =      1 << 63,
     }
 }
 
-impl EntryFlags {
-    pub fn from_elf_section_flags(section: &ElfSection) -> EntryFlags {
-        use multiboot2::{ELF_SECTION_ALLOCATED, ELF_SECTION_EXECUTABLE, ELF_SECTION_WRITABLE};
-
-        let mut flags = EntryFlags::empty();
-
-        if section.flags().contains(ELF_SECTION_ALLOCATED) {
-            // section is loaded to memory
-            flags = flags | PRESENT;
-        }
-        if section.flags().contains(ELF_SECTION_WRITABLE) {
-            flags = flags | WRITABLE;
-        }
-        if !section.flags().contains(ELF_SECTION_EXECUTABLE) {
-            flags = flags | NO_EXECUTE;
-        }
-
-        flags
-    }
-}
-
 impl Entry {
     pub fn is_unused(&self) -> bool {
-        self.0 == 0
+        self.0 & !COUNTER_MASK == 0
     }
 
     pub fn set_unused(&mut self) {
-        self.0 = 0;
+        self.0 &= COUNTER_MASK;
     }
 
     pub fn flags(&self) -> EntryFlags {
@@ -64,6 +49,17 @@ impl Entry {
 
     pub fn set(&mut self, frame: Frame, flags: EntryFlags) {
         assert!(frame.start_address() & !0x000fffff_fffff000 == 0);
-        self.0 = (frame.start_address() as u64) | flags.bits();
+        self.0 = (self.0 & COUNTER_MASK) | (frame.start_address() as u64) | flags.bits();
+    }
+
+    /// Returns the value stored in the unused counter bits
+    pub fn counter_bits(&self) -> usize {
+        ((self.0 & COUNTER_MASK) >> COUNTER_SHIFT) as usize
+    }
+
+    /// Store `value` in the unused counter bits, leaving the rest of the entry unchanged
+    pub fn set_counter_bits(&mut self, value: usize) {
+        assert!(value as u64 <= COUNTER_MASK >> COUNTER_SHIFT);
+        self.0 = (self.0 & !COUNTER_MASK) | ((value as u64) << COUNTER_SHIFT);
     }
 }
@@ -0,0 +1,59 @@
+pub type PhysicalAddress = usize;
+
+pub const PAGE_SIZE: usize = 4096;
+
+/// A physical memory frame
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Frame {
+    pub number: usize,
+}
+
+impl Frame {
+    // TODO change to from_address?
+    pub fn containing_address(address: usize) -> Frame {
+        Frame {
+            number: address / PAGE_SIZE,
+        }
+    }
+
+    pub fn start_address(&self) -> PhysicalAddress {
+        self.number * PAGE_SIZE
+    }
+
+    pub fn clone(&self) -> Frame {
+        Frame {
+            number: self.number,
+        }
+    }
+
+    pub fn range_inclusive(start: Frame, end: Frame) -> FrameIter {
+        FrameIter {
+            start: start,
+            end: end,
+        }
+    }
+}
+
+pub trait FrameAllocator {
+    fn allocate_frame(&mut self) -> Option<Frame>;
+    fn deallocate_frame(&mut self, frame: Frame);
+}
+
+pub struct FrameIter {
+    start: Frame,
+    end: Frame,
+}
+
+impl Iterator for FrameIter {
+    type Item = Frame;
+
+    fn next(&mut self) -> Option<Frame> {
+        if self.start <= self.end {
+            let frame = self.start.clone();
+            self.start.number += 1;
+            Some(frame)
+        } else {
+            None
+        }
+    }
+}
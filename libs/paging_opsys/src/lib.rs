@@ -0,0 +1,26 @@
+#![no_std]
+
+#[macro_use]
+extern crate bitflags;
+
+#[cfg(test)]
+#[macro_use]
+extern crate std;
+
+mod entry;
+mod frame;
+mod page;
+mod table;
+mod memory;
+mod mapper;
+
+pub use entry::*;
+pub use frame::{Frame, FrameAllocator, FrameIter, PhysicalAddress, PAGE_SIZE};
+pub use page::{Page, PageIter, VirtualAddress, ENTRY_COUNT, KERNEL_P4_START};
+pub use page::{PAGES_PER_1G, PAGES_PER_2M};
+pub use table::{HierarchicalLevel, Level1, Level2, Level3, Level4, Table, TableLevel};
+pub use memory::PageTableMemory;
+pub use mapper::Mapper;
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,457 @@
+use entry::*;
+use frame::{Frame, FrameAllocator, PhysicalAddress, PAGE_SIZE};
+use memory::PageTableMemory;
+use page::{Page, VirtualAddress, ENTRY_COUNT, KERNEL_P4_START, PAGES_PER_1G, PAGES_PER_2M};
+use table::{HierarchicalLevel, Level2, Level3, Level4, Table};
+
+/// Maps virtual pages to physical frames in the page tables reachable through `M`
+pub struct Mapper<M: PageTableMemory> {
+    memory: M,
+}
+
+impl<M> Mapper<M>
+where
+    M: PageTableMemory,
+{
+    /// Construct a new `Mapper` for the page tables in `memory`.
+    ///
+    /// # Safety
+    /// `memory` must give access to a valid P4 table, and only one `Mapper` may exist for it.
+    pub unsafe fn new(memory: M) -> Mapper<M> {
+        Mapper { memory: memory }
+    }
+
+    pub fn translate(&self, virtual_address: VirtualAddress) -> Option<PhysicalAddress> {
+        let offset = virtual_address % PAGE_SIZE;
+        self.translate_page(Page::containing_address(virtual_address))
+            .map(|frame| frame.number * PAGE_SIZE + offset)
+    }
+
+    pub fn map<A>(&mut self, page: Page, flags: EntryFlags, allocator: &mut A)
+    where
+        A: FrameAllocator,
+    {
+        let frame = allocator.allocate_frame().expect("out of memory");
+        self.map_to(page, frame, flags, allocator)
+    }
+
+    pub fn map_to<A>(&mut self, page: Page, frame: Frame, flags: EntryFlags, allocator: &mut A)
+    where
+        A: FrameAllocator,
+    {
+        let memory = &self.memory;
+        let p4 = unsafe { &mut *memory.p4() };
+
+        let p3 = p4.next_table_create(page.p4_index(), allocator, memory);
+        let p2 = p3.next_table_create(page.p3_index(), allocator, memory);
+        let p1 = p2.next_table_create(page.p2_index(), allocator, memory);
+
+        assert!(p1[page.p1_index()].is_unused());
+        p1[page.p1_index()].set(frame, flags | PRESENT);
+        p1.increment_entry_count();
+    }
+
+    pub fn identity_map<A>(&mut self, frame: Frame, flags: EntryFlags, allocator: &mut A)
+    where
+        A: FrameAllocator,
+    {
+        let page = Page::containing_address(frame.start_address());
+        self.map_to(page, frame, flags, allocator)
+    }
+
+    /// Map the 2MiB huge page starting at `page` to the 2MiB of frames starting at `frame`.
+    ///
+    /// Both `page` and `frame` must be 2MiB aligned.
+    pub fn map_huge_2m<A>(&mut self, page: Page, frame: Frame, flags: EntryFlags, allocator: &mut A)
+    where
+        A: FrameAllocator,
+    {
+        assert!(page.number % PAGES_PER_2M == 0, "page is not 2MiB aligned");
+        assert!(frame.number % PAGES_PER_2M == 0, "frame is not 2MiB aligned");
+
+        let memory = &self.memory;
+        let p4 = unsafe { &mut *memory.p4() };
+
+        let p3 = p4.next_table_create(page.p4_index(), allocator, memory);
+        let p2 = p3.next_table_create(page.p3_index(), allocator, memory);
+
+        assert!(p2[page.p2_index()].is_unused());
+        p2[page.p2_index()].set(frame, flags | PRESENT | HUGE_PAGE);
+        p2.increment_entry_count();
+    }
+
+    /// Map the 1GiB huge page starting at `page` to the 1GiB of frames starting at `frame`.
+    ///
+    /// Both `page` and `frame` must be 1GiB aligned. Not all CPUs support 1GiB pages, the caller
+    /// must check the `pdpe1gb` CPUID feature first.
+    pub fn map_huge_1g<A>(&mut self, page: Page, frame: Frame, flags: EntryFlags, allocator: &mut A)
+    where
+        A: FrameAllocator,
+    {
+        assert!(page.number % PAGES_PER_1G == 0, "page is not 1GiB aligned");
+        assert!(frame.number % PAGES_PER_1G == 0, "frame is not 1GiB aligned");
+
+        let memory = &self.memory;
+        let p4 = unsafe { &mut *memory.p4() };
+
+        let p3 = p4.next_table_create(page.p4_index(), allocator, memory);
+
+        assert!(p3[page.p3_index()].is_unused());
+        p3[page.p3_index()].set(frame, flags | PRESENT | HUGE_PAGE);
+        p3.increment_entry_count();
+    }
+
+    /// Map `count` consecutive frames starting at `frame` to consecutive pages starting at `page`.
+    ///
+    /// The largest page size allowed by the alignment of each part of the range is used, 1GiB
+    /// pages only if `huge_1g` is set (see `map_huge_1g`).
+    pub fn map_linear<A>(
+        &mut self,
+        page: Page,
+        frame: Frame,
+        count: usize,
+        flags: EntryFlags,
+        huge_1g: bool,
+        allocator: &mut A,
+    ) where
+        A: FrameAllocator,
+    {
+        let mut page = page;
+        let mut frame = frame;
+        let mut remaining = count;
+
+        while remaining > 0 {
+            let size = {
+                let fits = |size: usize| {
+                    page.number % size == 0 && frame.number % size == 0 && remaining >= size
+                };
+
+                if huge_1g && fits(PAGES_PER_1G) {
+                    PAGES_PER_1G
+                } else if fits(PAGES_PER_2M) {
+                    PAGES_PER_2M
+                } else {
+                    1
+                }
+            };
+
+            if size == PAGES_PER_1G {
+                self.map_huge_1g(page, frame.clone(), flags, allocator);
+            } else if size == PAGES_PER_2M {
+                self.map_huge_2m(page, frame.clone(), flags, allocator);
+            } else {
+                self.map_to(page, frame.clone(), flags, allocator);
+            }
+
+            page.number += size;
+            frame.number += size;
+            remaining -= size;
+        }
+    }
+
+    pub fn translate_page(&self, page: Page) -> Option<Frame> {
+        let memory = &self.memory;
+        let p3 = self.p4().next_table(page.p4_index(), memory);
+
+        let huge_page = || {
+            p3.and_then(|p3| {
+                let p3_entry = &p3[page.p3_index()];
+                // 1GiB page?
+                if let Some(start_frame) = p3_entry.pointed_frame() {
+                    if p3_entry.flags().contains(HUGE_PAGE) {
+                        // address must be 1GiB aligned
+                        assert!(start_frame.number % (ENTRY_COUNT * ENTRY_COUNT) == 0);
+                        return Some(Frame {
+                            number: start_frame.number + page.p2_index() * ENTRY_COUNT
+                                + page.p1_index(),
+                        });
+                    }
+                }
+                if let Some(p2) = p3.next_table(page.p3_index(), memory) {
+                    let p2_entry = &p2[page.p2_index()];
+                    // 2MiB page?
+                    if let Some(start_frame) = p2_entry.pointed_frame() {
+                        if p2_entry.flags().contains(HUGE_PAGE) {
+                            // address must be 2MiB aligned
+                            assert!(start_frame.number % ENTRY_COUNT == 0);
+                            return Some(Frame {
+                                number: start_frame.number + page.p1_index(),
+                            });
+                        }
+                    }
+                }
+                None
+            })
+        };
+
+        p3.and_then(|p3| p3.next_table(page.p3_index(), memory))
+            .and_then(|p2| p2.next_table(page.p2_index(), memory))
+            .and_then(|p1| p1[page.p1_index()].pointed_frame())
+            .or_else(huge_page)
+    }
+
+    /// Returns the flags of the entry mapping `page`, which may be a huge page entry
+    pub fn page_flags(&self, page: Page) -> Option<EntryFlags> {
+        let memory = &self.memory;
+        let p4 = self.p4();
+
+        let p3 = match p4.next_table(page.p4_index(), memory) {
+            Some(p3) => p3,
+            None => return None,
+        };
+        if p3[page.p3_index()].flags().contains(HUGE_PAGE) {
+            return Some(p3[page.p3_index()].flags());
+        }
+
+        let p2 = match p3.next_table(page.p3_index(), memory) {
+            Some(p2) => p2,
+            None => return None,
+        };
+        if p2[page.p2_index()].flags().contains(HUGE_PAGE) {
+            return Some(p2[page.p2_index()].flags());
+        }
+
+        p2.next_table(page.p2_index(), memory)
+            .map(|p1| &p1[page.p1_index()])
+            .and_then(|entry| entry.pointed_frame().map(|_| entry.flags()))
+    }
+
+    /// Unmap `page`, returning the frame it was mapped to.
+    ///
+    /// If `page` is part of a huge page, the huge page is first split into smaller pages so the
+    /// rest of it stays mapped. Page tables left empty are returned to `allocator`, apart from the
+    /// kernel half P3 tables (see `KERNEL_P4_START`). The frame itself is not freed as it may
+    /// still be in use elsewhere.
+    pub fn unmap<A>(&mut self, page: Page, allocator: &mut A) -> Frame
+    where
+        A: FrameAllocator,
+    {
+        assert!(self.translate(page.start_address()).is_some());
+
+        self.split_huge_pages(page, allocator);
+
+        let memory = &self.memory;
+        let p4 = unsafe { &mut *memory.p4() };
+
+        // Raw pointers as the tables are modified at every level after the P1 table is found
+        let p3 = p4.next_table_mut(page.p4_index(), memory)
+            .expect("mapping code does not support huge pages") as *mut Table<Level3>;
+        let p2 = unsafe { &mut *p3 }
+            .next_table_mut(page.p3_index(), memory)
+            .expect("mapping code does not support huge pages") as *mut Table<Level2>;
+        let p1 = unsafe { &mut *p2 }
+            .next_table_mut(page.p2_index(), memory)
+            .expect("mapping code does not support huge pages");
+
+        let frame = p1[page.p1_index()].pointed_frame().unwrap();
+        p1[page.p1_index()].set_unused();
+        p1.decrement_entry_count();
+
+        // Reset the Translation Lookaside Buffer (cpu cache)
+        memory.flush(page.start_address());
+
+        // Free each table left empty, working up towards the P4 table which is never freed
+        unsafe {
+            if p1.entry_count() == 0 {
+                free_table(&mut *p2, page.p2_index(), allocator, memory);
+
+                if (*p2).entry_count() == 0 {
+                    free_table(&mut *p3, page.p3_index(), allocator, memory);
+
+                    if (*p3).entry_count() == 0 && page.p4_index() < KERNEL_P4_START {
+                        free_table(p4, page.p4_index(), allocator, memory);
+                    }
+                }
+            }
+        }
+
+        frame
+    }
+
+    /// Unmap the 2MiB huge page starting at `page`, returning its first frame.
+    ///
+    /// Page tables left empty are returned to `allocator`, as with `unmap`.
+    pub fn unmap_huge_2m<A>(&mut self, page: Page, allocator: &mut A) -> Frame
+    where
+        A: FrameAllocator,
+    {
+        assert!(page.number % PAGES_PER_2M == 0, "page is not 2MiB aligned");
+
+        let memory = &self.memory;
+        let p4 = unsafe { &mut *memory.p4() };
+
+        let p3 = p4.next_table_mut(page.p4_index(), memory)
+            .expect("page is not mapped") as *mut Table<Level3>;
+        let p2 = unsafe { &mut *p3 }
+            .next_table_mut(page.p3_index(), memory)
+            .expect("page is not a 2MiB page");
+
+        assert!(
+            p2[page.p2_index()].flags().contains(HUGE_PAGE),
+            "page is not a 2MiB page"
+        );
+
+        let frame = p2[page.p2_index()].pointed_frame().unwrap();
+        p2[page.p2_index()].set_unused();
+        p2.decrement_entry_count();
+
+        memory.flush(page.start_address());
+
+        unsafe {
+            if p2.entry_count() == 0 {
+                free_table(&mut *p3, page.p3_index(), allocator, memory);
+
+                if (*p3).entry_count() == 0 && page.p4_index() < KERNEL_P4_START {
+                    free_table(p4, page.p4_index(), allocator, memory);
+                }
+            }
+        }
+
+        frame
+    }
+
+    /// Unmap the 1GiB huge page starting at `page`, returning its first frame.
+    ///
+    /// Page tables left empty are returned to `allocator`, as with `unmap`.
+    pub fn unmap_huge_1g<A>(&mut self, page: Page, allocator: &mut A) -> Frame
+    where
+        A: FrameAllocator,
+    {
+        assert!(page.number % PAGES_PER_1G == 0, "page is not 1GiB aligned");
+
+        let memory = &self.memory;
+        let p4 = unsafe { &mut *memory.p4() };
+
+        let p3 = p4.next_table_mut(page.p4_index(), memory)
+            .expect("page is not mapped") as *mut Table<Level3>;
+        let p3 = unsafe { &mut *p3 };
+
+        assert!(
+            p3[page.p3_index()].flags().contains(HUGE_PAGE),
+            "page is not a 1GiB page"
+        );
+
+        let frame = p3[page.p3_index()].pointed_frame().unwrap();
+        p3[page.p3_index()].set_unused();
+        p3.decrement_entry_count();
+
+        memory.flush(page.start_address());
+
+        if p3.entry_count() == 0 && page.p4_index() < KERNEL_P4_START {
+            free_table(p4, page.p4_index(), allocator, memory);
+        }
+
+        frame
+    }
+
+    /// Replace the flags of the mapping of `page` with `flags`, returning the frame it maps.
+    ///
+    /// If `page` is part of a huge page, the huge page is first split so only `page` changes.
+    pub fn update_flags<A>(&mut self, page: Page, flags: EntryFlags, allocator: &mut A) -> Frame
+    where
+        A: FrameAllocator,
+    {
+        assert!(self.translate(page.start_address()).is_some());
+
+        self.split_huge_pages(page, allocator);
+
+        let memory = &self.memory;
+        let p4 = unsafe { &mut *memory.p4() };
+
+        let p1 = p4.next_table_mut(page.p4_index(), memory)
+            .and_then(|p3| p3.next_table_mut(page.p3_index(), memory))
+            .and_then(|p2| p2.next_table_mut(page.p2_index(), memory))
+            .expect("page is not mapped");
+
+        let frame = p1[page.p1_index()].pointed_frame().unwrap();
+        p1[page.p1_index()].set(frame.clone(), flags | PRESENT);
+
+        memory.flush(page.start_address());
+
+        frame
+    }
+
+    /// Split any huge page containing `page` so it is mapped by a P1 table entry
+    fn split_huge_pages<A>(&mut self, page: Page, allocator: &mut A)
+    where
+        A: FrameAllocator,
+    {
+        let memory = &self.memory;
+        let p4 = unsafe { &mut *memory.p4() };
+
+        let p3 = p4.next_table_mut(page.p4_index(), memory)
+            .expect("page is not mapped");
+        if p3[page.p3_index()].flags().contains(HUGE_PAGE) {
+            split_huge_page(p3, page.p3_index(), PAGES_PER_2M, allocator, memory);
+        }
+
+        let p2 = p3.next_table_mut(page.p3_index(), memory)
+            .expect("page is not mapped");
+        if p2[page.p2_index()].flags().contains(HUGE_PAGE) {
+            split_huge_page(p2, page.p2_index(), 1, allocator, memory);
+        }
+    }
+
+    pub fn p4(&self) -> &Table<Level4> {
+        unsafe { &*self.memory.p4() }
+    }
+
+    pub fn p4_mut(&mut self) -> &mut Table<Level4> {
+        unsafe { &mut *self.memory.p4() }
+    }
+}
+
+/// Clear entry `index` of `table`, returning the next level table it points to to `allocator`
+fn free_table<L, A, M>(table: &mut Table<L>, index: usize, allocator: &mut A, memory: &M)
+where
+    L: HierarchicalLevel,
+    A: FrameAllocator,
+    M: PageTableMemory,
+{
+    let next_table_address = table.next_table(index, memory).unwrap() as *const _ as usize;
+    let frame = table[index].pointed_frame().unwrap();
+
+    table[index].set_unused();
+    table.decrement_entry_count();
+
+    // The table may have been accessed through a mapping that is now stale
+    memory.flush(next_table_address);
+
+    allocator.deallocate_frame(frame);
+}
+
+/// Replace the huge page in entry `index` of `table` with a next level table mapping the same
+/// frames using pages of `frames_per_page` frames each.
+fn split_huge_page<L, A, M>(
+    table: &mut Table<L>,
+    index: usize,
+    frames_per_page: usize,
+    allocator: &mut A,
+    memory: &M,
+) where
+    L: HierarchicalLevel,
+    A: FrameAllocator,
+    M: PageTableMemory,
+{
+    let start_frame = table[index].pointed_frame().unwrap();
+    let mut flags = table[index].flags();
+    if frames_per_page == 1 {
+        // The pages of a P1 table are never huge, bit 7 selects the memory type instead
+        flags.remove(HUGE_PAGE);
+    }
+
+    let frame = allocator.allocate_frame().expect("no frames available");
+    table[index].set(frame, PRESENT | WRITABLE);
+
+    let next_table = table.next_table_mut(index, memory).unwrap();
+
+    // The address of the new table may be cached as part of the huge page
+    memory.flush(next_table as *const _ as usize);
+
+    next_table.zero();
+    for i in 0..ENTRY_COUNT {
+        let number = start_frame.number + i * frames_per_page;
+        next_table[i].set(Frame { number: number }, flags);
+        next_table.increment_entry_count();
+    }
+}
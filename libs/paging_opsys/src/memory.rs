@@ -0,0 +1,37 @@
+use frame::Frame;
+use page::VirtualAddress;
+use table::{Level4, Table};
+
+/// Access to the memory holding the page tables.
+///
+/// The kernel reaches page tables through the recursive mapping, while the host tests use a
+/// simulated memory array. Abstracting over this lets the same `Mapper` code run on both.
+pub trait PageTableMemory {
+    /// Returns a pointer to the P4 table
+    fn p4(&self) -> *mut Table<Level4>;
+
+    /// Returns the address through which the next level table in `frame` can be accessed.
+    ///
+    /// `frame` is pointed to by entry `index` of the table at `table_address`.
+    fn next_table_address(&self, table_address: usize, index: usize, frame: &Frame) -> usize;
+
+    /// Invalidate any cached translation for the page containing `address`
+    fn flush(&self, address: VirtualAddress);
+}
+
+impl<'a, M> PageTableMemory for &'a M
+where
+    M: PageTableMemory,
+{
+    fn p4(&self) -> *mut Table<Level4> {
+        (**self).p4()
+    }
+
+    fn next_table_address(&self, table_address: usize, index: usize, frame: &Frame) -> usize {
+        (**self).next_table_address(table_address, index, frame)
+    }
+
+    fn flush(&self, address: VirtualAddress) {
+        (**self).flush(address)
+    }
+}
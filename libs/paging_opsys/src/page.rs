@@ -0,0 +1,87 @@
+use frame::PAGE_SIZE;
+
+pub type VirtualAddress = usize;
+
+pub const ENTRY_COUNT: usize = 512;
+
+/// Index of the first P4 entry in the kernel half of the address space.
+///
+/// The P3 tables of the kernel half are shared by every address space, so are never freed.
+pub const KERNEL_P4_START: usize = ENTRY_COUNT / 2;
+
+/// Number of 4KiB pages (or frames) covered by a 2MiB huge page
+pub const PAGES_PER_2M: usize = ENTRY_COUNT;
+
+/// Number of 4KiB pages (or frames) covered by a 1GiB huge page
+pub const PAGES_PER_1G: usize = ENTRY_COUNT * ENTRY_COUNT;
+
+/// A virtual memory page
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Page {
+    pub number: usize,
+}
+
+impl Page {
+    pub fn containing_address(address: VirtualAddress) -> Page {
+        assert!(
+            address < 0x0000_8000_0000_0000 || address >= 0xffff_8000_0000_0000,
+            "invalid address: 0x{:x}",
+            address
+        );
+        Page {
+            number: address / PAGE_SIZE,
+        }
+    }
+
+    pub fn range_inclusive(start: Page, end: Page) -> PageIter {
+        PageIter {
+            start: start,
+            end: end,
+        }
+    }
+
+    pub fn next_page(&self) -> Page {
+        Page {
+            number: self.number + 1,
+        }
+    }
+
+    pub fn start_address(&self) -> usize {
+        self.number * PAGE_SIZE
+    }
+
+    pub fn p4_index(&self) -> usize {
+        (self.number >> 27) & 0o777
+    }
+
+    pub fn p3_index(&self) -> usize {
+        (self.number >> 18) & 0o777
+    }
+
+    pub fn p2_index(&self) -> usize {
+        (self.number >> 9) & 0o777
+    }
+
+    pub fn p1_index(&self) -> usize {
+        (self.number >> 0) & 0o777
+    }
+}
+
+pub struct PageIter {
+    start: Page,
+    end: Page,
+}
+
+impl Iterator for PageIter {
+    type Item = Page;
+
+    fn next(&mut self) -> Option<Page> {
+        if self.start <= self.end {
+            let page = self.start;
+            self.start.number += 1;
+            Some(page)
+        } else {
+            None
+        }
+    }
+}
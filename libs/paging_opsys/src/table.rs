@@ -0,0 +1,158 @@
+use entry::*;
+use frame::FrameAllocator;
+use memory::PageTableMemory;
+use page::ENTRY_COUNT;
+
+use core::ops::{Index, IndexMut};
+use core::marker::PhantomData;
+
+pub trait TableLevel {}
+
+pub enum Level4 {}
+pub enum Level3 {}
+pub enum Level2 {}
+pub enum Level1 {}
+
+impl TableLevel for Level4 {}
+impl TableLevel for Level3 {}
+impl TableLevel for Level2 {}
+impl TableLevel for Level1 {}
+
+pub trait HierarchicalLevel: TableLevel {
+    type NextLevel: TableLevel;
+}
+
+impl HierarchicalLevel for Level4 {
+    type NextLevel = Level3;
+}
+
+impl HierarchicalLevel for Level3 {
+    type NextLevel = Level2;
+}
+
+impl HierarchicalLevel for Level2 {
+    type NextLevel = Level1;
+}
+
+pub struct Table<L: TableLevel> {
+    entries: [Entry; ENTRY_COUNT],
+    level: PhantomData<L>,
+}
+
+impl<L> Table<L>
+where
+    L: TableLevel,
+{
+    pub fn zero(&mut self) {
+        for entry in self.entries.iter_mut() {
+            entry.set_unused();
+        }
+        self.set_entry_count(0);
+    }
+
+    /// Returns the number of used entries, as maintained by `Mapper`.
+    ///
+    /// The count is kept in the unused bits of the first entry so the table stays page sized.
+    pub fn entry_count(&self) -> usize {
+        self.entries[0].counter_bits()
+    }
+
+    pub fn increment_entry_count(&mut self) {
+        let count = self.entry_count() + 1;
+        assert!(count <= ENTRY_COUNT, "page table entry count overflow");
+        self.set_entry_count(count);
+    }
+
+    pub fn decrement_entry_count(&mut self) {
+        let count = self.entry_count();
+        assert!(count > 0, "page table entry count underflow");
+        self.set_entry_count(count - 1);
+    }
+
+    fn set_entry_count(&mut self, count: usize) {
+        self.entries[0].set_counter_bits(count);
+    }
+}
+
+impl<L> Table<L>
+where
+    L: HierarchicalLevel,
+{
+    pub fn next_table<M>(&self, index: usize, memory: &M) -> Option<&Table<L::NextLevel>>
+    where
+        M: PageTableMemory,
+    {
+        self.next_table_address(index, memory)
+            .map(|address| unsafe { &*(address as *const _) })
+    }
+
+    pub fn next_table_mut<M>(
+        &mut self,
+        index: usize,
+        memory: &M,
+    ) -> Option<&mut Table<L::NextLevel>>
+    where
+        M: PageTableMemory,
+    {
+        self.next_table_address(index, memory)
+            .map(|address| unsafe { &mut *(address as *mut _) })
+    }
+
+    pub fn next_table_create<A, M>(
+        &mut self,
+        index: usize,
+        allocator: &mut A,
+        memory: &M,
+    ) -> &mut Table<L::NextLevel>
+    where
+        A: FrameAllocator,
+        M: PageTableMemory,
+    {
+        if self.next_table(index, memory).is_none() {
+            assert!(
+                !self.entries[index].flags().contains(HUGE_PAGE),
+                "mapping code does not support huge pages"
+            );
+            let frame = allocator.allocate_frame().expect("no frames available");
+            self.entries[index].set(frame, PRESENT | WRITABLE);
+            self.increment_entry_count();
+            self.next_table_mut(index, memory).unwrap().zero();
+        }
+        self.next_table_mut(index, memory).unwrap()
+    }
+
+    fn next_table_address<M>(&self, index: usize, memory: &M) -> Option<usize>
+    where
+        M: PageTableMemory,
+    {
+        let entry = &self[index];
+        if entry.flags().contains(HUGE_PAGE) {
+            return None;
+        }
+
+        entry.pointed_frame().map(|frame| {
+            let table_address = self as *const _ as usize;
+            memory.next_table_address(table_address, index, &frame)
+        })
+    }
+}
+
+impl<L> Index<usize> for Table<L>
+where
+    L: TableLevel,
+{
+    type Output = Entry;
+
+    fn index(&self, index: usize) -> &Entry {
+        &self.entries[index]
+    }
+}
+
+impl<L> IndexMut<usize> for Table<L>
+where
+    L: TableLevel,
+{
+    fn index_mut(&mut self, index: usize) -> &mut Entry {
+        &mut self.entries[index]
+    }
+}
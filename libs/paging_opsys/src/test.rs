@@ -0,0 +1,694 @@
+use std::cell::{Cell, RefCell};
+use std::vec::Vec;
+
+use entry::*;
+use frame::{Frame, FrameAllocator, PAGE_SIZE};
+use mapper::Mapper;
+use memory::PageTableMemory;
+use page::{Page, VirtualAddress, ENTRY_COUNT, KERNEL_P4_START, PAGES_PER_1G, PAGES_PER_2M};
+use table::{Level4, Table};
+
+// Number of simulated frames available to hold page tables
+const TABLE_FRAMES: usize = 16;
+
+// Frames mapped by the tests, well above the simulated table frames so they are never written
+const DATA_FRAME: usize = 0x1000;
+
+/// Simulated physical memory holding page tables.
+///
+/// Frame `n` is stored at `tables[n]`, frame 0 holds the P4 table.
+struct TestMemory {
+    tables: Vec<[u64; ENTRY_COUNT]>,
+    flushed: RefCell<Vec<VirtualAddress>>,
+}
+
+impl TestMemory {
+    fn new() -> TestMemory {
+        TestMemory {
+            tables: vec![[0; ENTRY_COUNT]; TABLE_FRAMES],
+            flushed: RefCell::new(Vec::new()),
+        }
+    }
+
+    fn table_address(&self, frame: &Frame) -> usize {
+        assert!(frame.number < TABLE_FRAMES, "frame is not a page table");
+        self.tables.as_ptr() as usize + frame.number * PAGE_SIZE
+    }
+
+    /// Write `frame` and `flags` into entry `index` of the table in frame `table`
+    fn set_entry(&self, table: usize, index: usize, frame: Frame, flags: EntryFlags) {
+        let address = self.table_address(&Frame { number: table });
+        let table = unsafe { &mut *(address as *mut Table<Level4>) };
+        table[index].set(frame, flags);
+    }
+}
+
+impl PageTableMemory for TestMemory {
+    fn p4(&self) -> *mut Table<Level4> {
+        self.table_address(&Frame { number: 0 }) as *mut _
+    }
+
+    fn next_table_address(&self, _: usize, _: usize, frame: &Frame) -> usize {
+        self.table_address(frame)
+    }
+
+    fn flush(&self, address: VirtualAddress) {
+        self.flushed.borrow_mut().push(address);
+    }
+}
+
+/// Hands out the simulated table frames, recording those returned
+struct TestAllocator {
+    next: Cell<usize>,
+    freed: Vec<Frame>,
+}
+
+impl TestAllocator {
+    fn new() -> TestAllocator {
+        TestAllocator {
+            next: Cell::new(1),
+            freed: Vec::new(),
+        }
+    }
+}
+
+impl FrameAllocator for TestAllocator {
+    fn allocate_frame(&mut self) -> Option<Frame> {
+        let number = self.next.get();
+        if number < TABLE_FRAMES {
+            self.next.set(number + 1);
+            Some(Frame { number: number })
+        } else {
+            None
+        }
+    }
+
+    fn deallocate_frame(&mut self, frame: Frame) {
+        self.freed.push(frame);
+    }
+}
+
+// Page in the third P4 entry, with non zero indexes at every level
+const ADDRESS: VirtualAddress = 0o_002_003_004_005_0000;
+
+#[test]
+fn map_to_creates_tables() {
+    let memory = TestMemory::new();
+    let mut allocator = TestAllocator::new();
+
+    unsafe {
+        let mut mapper = Mapper::new(&memory);
+        let page = Page::containing_address(ADDRESS);
+        mapper.map_to(page, Frame { number: DATA_FRAME }, WRITABLE, &mut allocator);
+    }
+
+    // P3, P2 and P1 tables allocated
+    assert_eq!(allocator.next.get(), 4);
+
+    let p4 = unsafe { &*memory.p4() };
+    assert_eq!(p4[2].pointed_frame(), Some(Frame { number: 1 }));
+    assert!(p4[2].flags().contains(PRESENT | WRITABLE));
+}
+
+#[test]
+fn map_to_reuses_tables() {
+    let memory = TestMemory::new();
+    let mut allocator = TestAllocator::new();
+
+    unsafe {
+        let mut mapper = Mapper::new(&memory);
+        let page = Page::containing_address(ADDRESS);
+        mapper.map_to(page, Frame { number: DATA_FRAME }, WRITABLE, &mut allocator);
+        mapper.map_to(page.next_page(), Frame { number: DATA_FRAME + 1 }, WRITABLE, &mut allocator);
+    }
+
+    assert_eq!(allocator.next.get(), 4);
+}
+
+#[test]
+#[should_panic]
+fn map_to_mapped_page_panics() {
+    let memory = TestMemory::new();
+    let mut allocator = TestAllocator::new();
+
+    unsafe {
+        let mut mapper = Mapper::new(&memory);
+        let page = Page::containing_address(ADDRESS);
+        mapper.map_to(page, Frame { number: DATA_FRAME }, WRITABLE, &mut allocator);
+        mapper.map_to(page, Frame { number: DATA_FRAME + 1 }, WRITABLE, &mut allocator);
+    }
+}
+
+#[test]
+fn translate() {
+    let memory = TestMemory::new();
+    let mut allocator = TestAllocator::new();
+
+    unsafe {
+        let mut mapper = Mapper::new(&memory);
+        let page = Page::containing_address(ADDRESS);
+        mapper.map_to(page, Frame { number: DATA_FRAME }, WRITABLE, &mut allocator);
+
+        assert_eq!(mapper.translate(ADDRESS), Some(DATA_FRAME * PAGE_SIZE));
+        assert_eq!(mapper.translate(ADDRESS + 0x123), Some(DATA_FRAME * PAGE_SIZE + 0x123));
+        assert_eq!(
+            mapper.translate_page(page),
+            Some(Frame { number: DATA_FRAME })
+        );
+    }
+}
+
+#[test]
+fn translate_unmapped() {
+    let memory = TestMemory::new();
+    let mut allocator = TestAllocator::new();
+
+    unsafe {
+        let mut mapper = Mapper::new(&memory);
+        assert_eq!(mapper.translate(ADDRESS), None);
+
+        let page = Page::containing_address(ADDRESS);
+        mapper.map_to(page, Frame { number: DATA_FRAME }, WRITABLE, &mut allocator);
+
+        // Same P1 table, different entry
+        assert_eq!(mapper.translate(ADDRESS + PAGE_SIZE), None);
+        // Different P4 entry
+        assert_eq!(mapper.translate(0o_003_000_000_000_0000), None);
+    }
+}
+
+#[test]
+fn translate_huge_2m() {
+    let memory = TestMemory::new();
+
+    // P4[2] -> P3 in frame 1, P3[3] -> P2 in frame 2, P2[4] 2MiB page
+    memory.set_entry(0, 2, Frame { number: 1 }, PRESENT | WRITABLE);
+    memory.set_entry(1, 3, Frame { number: 2 }, PRESENT | WRITABLE);
+    memory.set_entry(2, 4, Frame { number: 0x200 }, PRESENT | WRITABLE | HUGE_PAGE);
+
+    unsafe {
+        let mapper = Mapper::new(&memory);
+
+        assert_eq!(mapper.translate(0o_002_003_004_000_0000), Some(0x200 * PAGE_SIZE));
+        assert_eq!(
+            mapper.translate(ADDRESS + 0x123),
+            Some((0x200 + 5) * PAGE_SIZE + 0x123)
+        );
+        assert_eq!(mapper.translate(0o_002_003_005_000_0000), None);
+    }
+}
+
+#[test]
+fn translate_huge_1g() {
+    let memory = TestMemory::new();
+
+    // P4[2] -> P3 in frame 1, P3[3] 1GiB page
+    memory.set_entry(0, 2, Frame { number: 1 }, PRESENT | WRITABLE);
+    memory.set_entry(1, 3, Frame { number: 0x40000 }, PRESENT | WRITABLE | HUGE_PAGE);
+
+    unsafe {
+        let mapper = Mapper::new(&memory);
+
+        assert_eq!(
+            mapper.translate(ADDRESS + 0x123),
+            Some((0x40000 + 4 * ENTRY_COUNT + 5) * PAGE_SIZE + 0x123)
+        );
+        assert_eq!(mapper.translate(0o_002_004_000_000_0000), None);
+    }
+}
+
+#[test]
+fn unmap() {
+    let memory = TestMemory::new();
+    let mut allocator = TestAllocator::new();
+
+    unsafe {
+        let mut mapper = Mapper::new(&memory);
+        let page = Page::containing_address(ADDRESS);
+        mapper.map_to(page, Frame { number: DATA_FRAME }, WRITABLE, &mut allocator);
+        mapper.map_to(page.next_page(), Frame { number: DATA_FRAME + 1 }, WRITABLE, &mut allocator);
+
+        mapper.unmap(page, &mut allocator);
+
+        assert_eq!(mapper.translate_page(page), None);
+        assert_eq!(
+            mapper.translate_page(page.next_page()),
+            Some(Frame { number: DATA_FRAME + 1 })
+        );
+    }
+
+    assert_eq!(*memory.flushed.borrow(), vec![ADDRESS]);
+}
+
+#[test]
+#[should_panic]
+fn unmap_unmapped_page_panics() {
+    let memory = TestMemory::new();
+    let mut allocator = TestAllocator::new();
+
+    unsafe {
+        let mut mapper = Mapper::new(&memory);
+        mapper.unmap(Page::containing_address(ADDRESS), &mut allocator);
+    }
+}
+
+#[test]
+fn unmap_returns_frame() {
+    let memory = TestMemory::new();
+    let mut allocator = TestAllocator::new();
+
+    unsafe {
+        let mut mapper = Mapper::new(&memory);
+        let page = Page::containing_address(ADDRESS);
+        mapper.map_to(page, Frame { number: DATA_FRAME }, WRITABLE, &mut allocator);
+
+        assert_eq!(mapper.unmap(page, &mut allocator), Frame { number: DATA_FRAME });
+    }
+}
+
+#[test]
+fn unmap_frees_empty_tables() {
+    let memory = TestMemory::new();
+    let mut allocator = TestAllocator::new();
+
+    unsafe {
+        let mut mapper = Mapper::new(&memory);
+        let page = Page::containing_address(ADDRESS);
+        mapper.map_to(page, Frame { number: DATA_FRAME }, WRITABLE, &mut allocator);
+        mapper.unmap(page, &mut allocator);
+
+        assert!(mapper.p4()[page.p4_index()].is_unused());
+        assert_eq!(mapper.p4().entry_count(), 0);
+    }
+
+    // P1, P2 then P3
+    assert_eq!(
+        allocator.freed,
+        vec![Frame { number: 3 }, Frame { number: 2 }, Frame { number: 1 }]
+    );
+}
+
+// Page in the first kernel half P4 entry
+const KERNEL_ADDRESS: VirtualAddress = 0xffff_8000_0000_0000 | 0o_003_004_005_0000;
+
+#[test]
+fn unmap_keeps_kernel_p3_tables() {
+    let memory = TestMemory::new();
+    let mut allocator = TestAllocator::new();
+
+    unsafe {
+        let mut mapper = Mapper::new(&memory);
+        let page = Page::containing_address(KERNEL_ADDRESS);
+        assert_eq!(page.p4_index(), KERNEL_P4_START);
+
+        mapper.map_to(page, Frame { number: DATA_FRAME }, WRITABLE, &mut allocator);
+        mapper.unmap(page, &mut allocator);
+
+        assert_eq!(mapper.p4()[page.p4_index()].pointed_frame(), Some(Frame { number: 1 }));
+        assert_eq!(mapper.p4().entry_count(), 1);
+    }
+
+    // P1 then P2
+    assert_eq!(allocator.freed, vec![Frame { number: 3 }, Frame { number: 2 }]);
+}
+
+#[test]
+fn unmap_keeps_used_tables() {
+    let memory = TestMemory::new();
+    let mut allocator = TestAllocator::new();
+
+    // Second page shares the P3 and P2 tables but has its own P1 table
+    let page = Page::containing_address(ADDRESS);
+    let other = Page::containing_address(ADDRESS + ENTRY_COUNT * PAGE_SIZE);
+
+    unsafe {
+        let mut mapper = Mapper::new(&memory);
+        mapper.map_to(page, Frame { number: DATA_FRAME }, WRITABLE, &mut allocator);
+        mapper.map_to(other, Frame { number: DATA_FRAME + 1 }, WRITABLE, &mut allocator);
+
+        mapper.unmap(page, &mut allocator);
+        assert_eq!(allocator.freed, vec![Frame { number: 3 }]);
+        assert_eq!(
+            mapper.translate_page(other),
+            Some(Frame { number: DATA_FRAME + 1 })
+        );
+
+        mapper.unmap(other, &mut allocator);
+    }
+
+    assert_eq!(
+        allocator.freed,
+        vec![
+            Frame { number: 3 },
+            Frame { number: 4 },
+            Frame { number: 2 },
+            Frame { number: 1 },
+        ]
+    );
+}
+
+#[test]
+fn entry_count_preserved_by_entry_changes() {
+    let memory = TestMemory::new();
+    let mut allocator = TestAllocator::new();
+
+    unsafe {
+        let mut mapper = Mapper::new(&memory);
+
+        // The first entry of a table holds its count, map and unmap the page using it
+        let page = Page::containing_address(0o_002_003_004_000_0000);
+        mapper.map_to(page, Frame { number: DATA_FRAME }, WRITABLE, &mut allocator);
+        mapper.map_to(page.next_page(), Frame { number: DATA_FRAME + 1 }, WRITABLE, &mut allocator);
+        mapper.unmap(page, &mut allocator);
+
+        assert!(allocator.freed.is_empty());
+        assert_eq!(
+            mapper.translate_page(page.next_page()),
+            Some(Frame { number: DATA_FRAME + 1 })
+        );
+        assert_eq!(mapper.translate_page(page), None);
+    }
+}
+
+// 1GiB aligned page with P4 index 2, P3 index 3
+const HUGE_ADDRESS: VirtualAddress = 0o_002_003_000_000_0000;
+
+// 1GiB aligned frame
+const HUGE_FRAME: usize = 0x40000;
+
+#[test]
+fn map_huge_2m() {
+    let memory = TestMemory::new();
+    let mut allocator = TestAllocator::new();
+
+    unsafe {
+        let mut mapper = Mapper::new(&memory);
+        let page = Page::containing_address(HUGE_ADDRESS);
+        mapper.map_huge_2m(page, Frame { number: HUGE_FRAME }, WRITABLE, &mut allocator);
+
+        assert_eq!(
+            mapper.translate(HUGE_ADDRESS + 0x1f_f123),
+            Some(HUGE_FRAME * PAGE_SIZE + 0x1f_f123)
+        );
+        assert_eq!(mapper.translate(HUGE_ADDRESS + 0x20_0000), None);
+    }
+
+    // Only P3 and P2 tables allocated
+    assert_eq!(allocator.next.get(), 3);
+}
+
+#[test]
+fn map_huge_1g() {
+    let memory = TestMemory::new();
+    let mut allocator = TestAllocator::new();
+
+    unsafe {
+        let mut mapper = Mapper::new(&memory);
+        let page = Page::containing_address(HUGE_ADDRESS);
+        mapper.map_huge_1g(page, Frame { number: HUGE_FRAME }, WRITABLE, &mut allocator);
+
+        assert_eq!(
+            mapper.translate(HUGE_ADDRESS + 0x3fff_f123),
+            Some(HUGE_FRAME * PAGE_SIZE + 0x3fff_f123)
+        );
+        assert_eq!(mapper.translate(HUGE_ADDRESS + 0x4000_0000), None);
+    }
+
+    // Only a P3 table allocated
+    assert_eq!(allocator.next.get(), 2);
+}
+
+#[test]
+#[should_panic]
+fn map_huge_2m_unaligned_panics() {
+    let memory = TestMemory::new();
+    let mut allocator = TestAllocator::new();
+
+    unsafe {
+        let mut mapper = Mapper::new(&memory);
+        let page = Page::containing_address(HUGE_ADDRESS + PAGE_SIZE);
+        mapper.map_huge_2m(page, Frame { number: HUGE_FRAME }, WRITABLE, &mut allocator);
+    }
+}
+
+#[test]
+#[should_panic]
+fn map_to_in_huge_page_panics() {
+    let memory = TestMemory::new();
+    let mut allocator = TestAllocator::new();
+
+    unsafe {
+        let mut mapper = Mapper::new(&memory);
+        let page = Page::containing_address(HUGE_ADDRESS);
+        mapper.map_huge_2m(page, Frame { number: HUGE_FRAME }, WRITABLE, &mut allocator);
+        mapper.map_to(page.next_page(), Frame { number: DATA_FRAME }, WRITABLE, &mut allocator);
+    }
+}
+
+#[test]
+fn map_linear_uses_largest_pages() {
+    let memory = TestMemory::new();
+    let mut allocator = TestAllocator::new();
+
+    // One 4KiB page up to a 1GiB boundary, one 1GiB page, then one 2MiB page and one 4KiB page
+    let start = HUGE_ADDRESS - PAGES_PER_1G * PAGE_SIZE - PAGE_SIZE;
+    let count = 1 + PAGES_PER_1G + PAGES_PER_2M + 1;
+
+    unsafe {
+        let mut mapper = Mapper::new(&memory);
+        let page = Page::containing_address(start);
+        let frame = Frame::containing_address(start);
+        mapper.map_linear(page, frame, count, WRITABLE, true, &mut allocator);
+
+        let p4 = mapper.p4();
+        let p3 = p4.next_table(2, &memory).unwrap();
+        assert!(p3[2].flags().contains(HUGE_PAGE));
+        assert_eq!(p3.entry_count(), 3);
+
+        let p2 = p3.next_table(3, &memory).unwrap();
+        assert!(p2[0].flags().contains(HUGE_PAGE));
+
+        for &address in &[start, start + PAGE_SIZE, HUGE_ADDRESS, start + count * PAGE_SIZE - 1] {
+            assert_eq!(mapper.translate(address), Some(address));
+        }
+        assert_eq!(mapper.translate(start + count * PAGE_SIZE), None);
+    }
+
+    // P3, two P2 and two P1 tables
+    assert_eq!(allocator.next.get(), 6);
+}
+
+#[test]
+fn map_linear_without_1g_pages() {
+    let memory = TestMemory::new();
+    let mut allocator = TestAllocator::new();
+
+    unsafe {
+        let mut mapper = Mapper::new(&memory);
+        let page = Page::containing_address(HUGE_ADDRESS);
+        let frame = Frame { number: HUGE_FRAME };
+        mapper.map_linear(page, frame, PAGES_PER_1G, WRITABLE, false, &mut allocator);
+
+        let p2 = mapper.p4()
+            .next_table(2, &memory)
+            .and_then(|p3| p3.next_table(3, &memory))
+            .unwrap();
+        assert_eq!(p2.entry_count(), ENTRY_COUNT);
+        assert_eq!(
+            mapper.translate(HUGE_ADDRESS + 0x3fff_f123),
+            Some(HUGE_FRAME * PAGE_SIZE + 0x3fff_f123)
+        );
+    }
+}
+
+#[test]
+fn unmap_splits_2m_page() {
+    let memory = TestMemory::new();
+    let mut allocator = TestAllocator::new();
+
+    unsafe {
+        let mut mapper = Mapper::new(&memory);
+        let page = Page::containing_address(HUGE_ADDRESS);
+        mapper.map_huge_2m(page, Frame { number: HUGE_FRAME }, WRITABLE, &mut allocator);
+
+        let unmapped = Page { number: page.number + 5 };
+        assert_eq!(
+            mapper.unmap(unmapped, &mut allocator),
+            Frame { number: HUGE_FRAME + 5 }
+        );
+
+        assert_eq!(mapper.translate_page(unmapped), None);
+        assert_eq!(
+            mapper.translate_page(unmapped.next_page()),
+            Some(Frame { number: HUGE_FRAME + 6 })
+        );
+        assert_eq!(mapper.translate_page(page), Some(Frame { number: HUGE_FRAME }));
+
+        let p1 = mapper.p4()
+            .next_table(2, &memory)
+            .and_then(|p3| p3.next_table(3, &memory))
+            .and_then(|p2| p2.next_table(0, &memory))
+            .unwrap();
+        assert!(!p1[6].flags().contains(HUGE_PAGE));
+        assert!(p1[6].flags().contains(WRITABLE));
+        assert_eq!(p1.entry_count(), ENTRY_COUNT - 1);
+    }
+}
+
+#[test]
+fn unmap_splits_1g_page() {
+    let memory = TestMemory::new();
+    let mut allocator = TestAllocator::new();
+
+    unsafe {
+        let mut mapper = Mapper::new(&memory);
+        let page = Page::containing_address(HUGE_ADDRESS);
+        mapper.map_huge_1g(page, Frame { number: HUGE_FRAME }, WRITABLE, &mut allocator);
+
+        let unmapped = Page { number: page.number + 3 * PAGES_PER_2M + 5 };
+        mapper.unmap(unmapped, &mut allocator);
+
+        assert_eq!(mapper.translate_page(unmapped), None);
+        for &number in &[0, PAGES_PER_2M, 3 * PAGES_PER_2M + 6, PAGES_PER_1G - 1] {
+            assert_eq!(
+                mapper.translate_page(Page { number: page.number + number }),
+                Some(Frame { number: HUGE_FRAME + number })
+            );
+        }
+
+        let p2 = mapper.p4()
+            .next_table(2, &memory)
+            .and_then(|p3| p3.next_table(3, &memory))
+            .unwrap();
+        assert!(p2[0].flags().contains(HUGE_PAGE));
+        assert!(!p2[3].flags().contains(HUGE_PAGE));
+    }
+
+    // P3 when mapping, then P2 and P1 when splitting
+    assert_eq!(allocator.next.get(), 4);
+}
+
+#[test]
+fn unmap_huge_2m_frees_empty_tables() {
+    let memory = TestMemory::new();
+    let mut allocator = TestAllocator::new();
+
+    unsafe {
+        let mut mapper = Mapper::new(&memory);
+        let page = Page::containing_address(HUGE_ADDRESS);
+        mapper.map_huge_2m(page, Frame { number: HUGE_FRAME }, WRITABLE, &mut allocator);
+
+        assert_eq!(
+            mapper.unmap_huge_2m(page, &mut allocator),
+            Frame { number: HUGE_FRAME }
+        );
+        assert_eq!(mapper.translate_page(page), None);
+    }
+
+    assert_eq!(allocator.freed, vec![Frame { number: 2 }, Frame { number: 1 }]);
+}
+
+#[test]
+fn unmap_huge_1g_frees_empty_tables() {
+    let memory = TestMemory::new();
+    let mut allocator = TestAllocator::new();
+
+    unsafe {
+        let mut mapper = Mapper::new(&memory);
+        let page = Page::containing_address(HUGE_ADDRESS);
+        mapper.map_huge_1g(page, Frame { number: HUGE_FRAME }, WRITABLE, &mut allocator);
+
+        assert_eq!(
+            mapper.unmap_huge_1g(page, &mut allocator),
+            Frame { number: HUGE_FRAME }
+        );
+        assert_eq!(mapper.translate_page(page), None);
+    }
+
+    assert_eq!(allocator.freed, vec![Frame { number: 1 }]);
+}
+
+#[test]
+fn update_flags() {
+    let memory = TestMemory::new();
+    let mut allocator = TestAllocator::new();
+
+    unsafe {
+        let mut mapper = Mapper::new(&memory);
+        let page = Page::containing_address(ADDRESS);
+        mapper.map_to(page, Frame { number: DATA_FRAME }, WRITABLE, &mut allocator);
+
+        assert_eq!(
+            mapper.update_flags(page, NO_EXECUTE, &mut allocator),
+            Frame { number: DATA_FRAME }
+        );
+        assert_eq!(mapper.translate_page(page), Some(Frame { number: DATA_FRAME }));
+
+        let p1 = mapper.p4()
+            .next_table(2, &memory)
+            .and_then(|p3| p3.next_table(3, &memory))
+            .and_then(|p2| p2.next_table(4, &memory))
+            .unwrap();
+        assert_eq!(p1[5].flags(), PRESENT | NO_EXECUTE);
+        assert_eq!(p1.entry_count(), 1);
+    }
+
+    assert_eq!(*memory.flushed.borrow(), vec![ADDRESS]);
+}
+
+#[test]
+fn update_flags_splits_2m_page() {
+    let memory = TestMemory::new();
+    let mut allocator = TestAllocator::new();
+
+    unsafe {
+        let mut mapper = Mapper::new(&memory);
+        let page = Page::containing_address(HUGE_ADDRESS);
+        mapper.map_huge_2m(page, Frame { number: HUGE_FRAME }, WRITABLE, &mut allocator);
+
+        let updated = Page { number: page.number + 5 };
+        mapper.update_flags(updated, EntryFlags::empty(), &mut allocator);
+
+        let p1 = mapper.p4()
+            .next_table(2, &memory)
+            .and_then(|p3| p3.next_table(3, &memory))
+            .and_then(|p2| p2.next_table(0, &memory))
+            .unwrap();
+        assert_eq!(p1[5].flags(), PRESENT);
+        assert!(p1[6].flags().contains(WRITABLE));
+        assert_eq!(mapper.translate_page(updated), Some(Frame { number: HUGE_FRAME + 5 }));
+    }
+}
+
+#[test]
+fn page_flags() {
+    let memory = TestMemory::new();
+    let mut allocator = TestAllocator::new();
+
+    unsafe {
+        let mut mapper = Mapper::new(&memory);
+        let page = Page::containing_address(ADDRESS);
+        mapper.map_to(page, Frame { number: DATA_FRAME }, COPY_ON_WRITE, &mut allocator);
+
+        assert_eq!(mapper.page_flags(page), Some(PRESENT | COPY_ON_WRITE));
+        assert_eq!(mapper.page_flags(page.next_page()), None);
+        assert_eq!(mapper.page_flags(Page::containing_address(0)), None);
+    }
+}
+
+#[test]
+fn page_flags_huge_2m() {
+    let memory = TestMemory::new();
+    let mut allocator = TestAllocator::new();
+
+    unsafe {
+        let mut mapper = Mapper::new(&memory);
+        let page = Page::containing_address(HUGE_ADDRESS);
+        mapper.map_huge_2m(page, Frame { number: HUGE_FRAME }, WRITABLE, &mut allocator);
+
+        assert_eq!(
+            mapper.page_flags(Page { number: page.number + 5 }),
+            Some(PRESENT | WRITABLE | HUGE_PAGE)
+        );
+    }
+}
@@ -0,0 +1,48 @@
+/// Interrupt enable flag in RFLAGS
+#[cfg(not(test))]
+const RFLAGS_IF: u64 = 1 << 9;
+
+/// Disable interrupts, returning true if they were enabled
+#[cfg(not(test))]
+pub fn save_and_disable() -> bool {
+    let rflags: u64;
+    unsafe {
+        asm!("pushfq
+              pop $0
+              cli" : "=r"(rflags) ::: "intel", "volatile");
+    }
+    rflags & RFLAGS_IF != 0
+}
+
+/// Enable interrupts if `enabled`, as returned by `save_and_disable`
+#[cfg(not(test))]
+pub fn restore(enabled: bool) {
+    if enabled {
+        unsafe {
+            asm!("sti" :::: "intel", "volatile");
+        }
+    }
+}
+
+// The tests run as a normal process, which can't change the interrupt flag, so simulate it.
+
+#[cfg(test)]
+thread_local!(static ENABLED: ::std::cell::Cell<bool> = ::std::cell::Cell::new(true));
+
+#[cfg(test)]
+pub fn save_and_disable() -> bool {
+    ENABLED.with(|enabled| enabled.replace(false))
+}
+
+#[cfg(test)]
+pub fn restore(enabled: bool) {
+    if enabled {
+        ENABLED.with(|e| e.set(true));
+    }
+}
+
+/// Returns true if the simulated interrupts are enabled
+#[cfg(test)]
+pub fn enabled() -> bool {
+    ENABLED.with(|enabled| enabled.get())
+}
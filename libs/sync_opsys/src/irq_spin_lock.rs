@@ -0,0 +1,108 @@
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use interrupts;
+
+/// A spinlock that disables interrupts while it is held.
+///
+/// Locks that are also taken by interrupt handlers must disable interrupts, otherwise a handler
+/// interrupting the holder would spin forever waiting for it. The interrupt flag is saved when
+/// the lock is taken and restored when the `IrqSpinLockGuard` is dropped, so nested locks must be
+/// released in the reverse order they were taken.
+///
+/// With interrupts disabled the holder can't be interrupted, so on a single CPU finding the lock
+/// held means it is being taken again by its holder, which panics rather than deadlock.
+pub struct IrqSpinLock<T> {
+    locked: AtomicBool,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for IrqSpinLock<T> {}
+unsafe impl<T: Send> Sync for IrqSpinLock<T> {}
+
+impl<T> IrqSpinLock<T> {
+    /// Construct an unlocked `IrqSpinLock` protecting `data`
+    pub const fn new(data: T) -> IrqSpinLock<T> {
+        IrqSpinLock {
+            locked: AtomicBool::new(false),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Disable interrupts and take the lock, spinning until it is available.
+    ///
+    /// Panics if the lock is already held.
+    pub fn lock(&self) -> IrqSpinLockGuard<T> {
+        let interrupts_enabled = interrupts::save_and_disable();
+
+        if self.locked.load(Ordering::Relaxed) {
+            panic!("IrqSpinLock taken again while held");
+        }
+
+        while self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {}
+
+        IrqSpinLockGuard {
+            lock: self,
+            interrupts_enabled: interrupts_enabled,
+        }
+    }
+
+    /// Disable interrupts and take the lock if it is available, without spinning.
+    ///
+    /// Interrupts are restored if the lock is not available.
+    pub fn try_lock(&self) -> Option<IrqSpinLockGuard<T>> {
+        let interrupts_enabled = interrupts::save_and_disable();
+
+        if self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            interrupts::restore(interrupts_enabled);
+            return None;
+        }
+
+        Some(IrqSpinLockGuard {
+            lock: self,
+            interrupts_enabled: interrupts_enabled,
+        })
+    }
+
+    /// Returns true if the lock is held
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+}
+
+/// Access to the data protected by an `IrqSpinLock`.
+///
+/// Dropping the guard releases the lock, then enables interrupts if they were enabled when the
+/// lock was taken.
+pub struct IrqSpinLockGuard<'a, T: 'a> {
+    lock: &'a IrqSpinLock<T>,
+    interrupts_enabled: bool,
+}
+
+impl<'a, T> Deref for IrqSpinLockGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for IrqSpinLockGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<'a, T> Drop for IrqSpinLockGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.locked.store(false, Ordering::Release);
+        interrupts::restore(self.interrupts_enabled);
+    }
+}
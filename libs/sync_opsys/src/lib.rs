@@ -0,0 +1,16 @@
+#![cfg_attr(not(test), feature(asm))]
+#![cfg_attr(not(test), feature(const_fn))]
+#![cfg_attr(not(test), feature(const_unsafe_cell_new))]
+#![no_std]
+
+#[cfg(test)]
+#[macro_use]
+extern crate std;
+
+mod interrupts;
+mod irq_spin_lock;
+
+pub use irq_spin_lock::{IrqSpinLock, IrqSpinLockGuard};
+
+#[cfg(test)]
+mod test;
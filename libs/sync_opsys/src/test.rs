@@ -0,0 +1,70 @@
+use interrupts;
+use irq_spin_lock::IrqSpinLock;
+
+#[test]
+fn lock_disables_interrupts() {
+    let lock = IrqSpinLock::new(0);
+
+    {
+        let mut guard = lock.lock();
+        *guard = 1;
+        assert!(lock.is_locked());
+        assert!(!interrupts::enabled());
+    }
+
+    assert!(!lock.is_locked());
+    assert!(interrupts::enabled());
+    assert_eq!(*lock.lock(), 1);
+}
+
+#[test]
+fn nested_locks_restore_interrupts() {
+    let outer = IrqSpinLock::new(());
+    let inner = IrqSpinLock::new(());
+
+    let outer_guard = outer.lock();
+    {
+        let _inner_guard = inner.lock();
+    }
+
+    // Interrupts were already disabled when the inner lock was taken
+    assert!(!interrupts::enabled());
+
+    drop(outer_guard);
+    assert!(interrupts::enabled());
+}
+
+#[test]
+fn try_lock_held() {
+    let lock = IrqSpinLock::new(());
+
+    let guard = lock.try_lock();
+    assert!(guard.is_some());
+    assert!(lock.try_lock().is_none());
+    assert!(!interrupts::enabled());
+
+    drop(guard);
+    assert!(interrupts::enabled());
+    assert!(lock.try_lock().is_some());
+}
+
+#[test]
+fn try_lock_restores_interrupts_when_held() {
+    let lock = IrqSpinLock::new(());
+
+    let guard = lock.lock();
+    interrupts::restore(true);
+    assert!(lock.try_lock().is_none());
+    assert!(interrupts::enabled());
+
+    drop(guard);
+}
+
+#[test]
+#[should_panic(expected = "IrqSpinLock taken again while held")]
+fn relock_panics() {
+    let lock = IrqSpinLock::new(());
+
+    let _guard = lock.lock();
+    let _again = lock.lock();
+}
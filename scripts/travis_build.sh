@@ -10,3 +10,8 @@ make xargo
 pushd libs/alloc_opsys
 cargo test
 popd
+
+# TEST: paging_opsys
+pushd libs/paging_opsys
+cargo test
+popd
@@ -1,26 +1,40 @@
 global start
+global p4_table
+global stack_top
+global gdt64_high_pointer
 
-section .text
+; Offset of the kernel's virtual addresses from its physical load address, see linker.ld
+KERNEL_OFFSET equ 0xffffff0000000000
+
+; Index of the P4 entry mapping KERNEL_OFFSET
+KERNEL_P4_INDEX equ 510
+
+section .boot
 
 ; Currently the CPU is in protected mode so only 32 bits available
 bits 32 
 
+; Paging is disabled so everything outside the .boot section must be accessed by its physical
+; address, the linked address less KERNEL_OFFSET.
 start:
     ; Move Multiboot info pointer to edi to be passed into kernel_main
-    mov esp, stack_top
+    mov esp, stack_top - KERNEL_OFFSET
     mov edi, ebx       
 
 	; Point the first entry of the level 4 page table to the first entry in the
-    ; p3 table
-    mov eax, p3_table
+    ; p3 table. This identity map is only used until we jump to the higher half.
+    mov eax, p3_table - KERNEL_OFFSET
     or eax, 0b11
-    mov dword [p4_table + 0], eax
+    mov dword [p4_table - KERNEL_OFFSET + 0], eax
+
+    ; Map the same p3 table at KERNEL_OFFSET for the higher half
+    mov dword [p4_table - KERNEL_OFFSET + KERNEL_P4_INDEX * 8], eax
 
     ; Point the first entry of the level 3 page table to the first entry in the
     ; p2 table
-    mov eax, p2_table
+    mov eax, p2_table - KERNEL_OFFSET
     or eax, 0b11
-    mov dword [p3_table + 0], eax
+    mov dword [p3_table - KERNEL_OFFSET + 0], eax
 
     ; point each page table level two entry to a page
     mov ecx, 0         ; counter variable
@@ -29,7 +43,7 @@ start:
     mov eax, 0x200000  ; 2MiB
     mul ecx
     or eax, 0b10000011
-    mov [p2_table + ecx * 8], eax
+    mov [p2_table - KERNEL_OFFSET + ecx * 8], eax
 
     inc ecx
     cmp ecx, 512
@@ -38,7 +52,7 @@ start:
     ; Enable the paging
 
     ; move page table address to cr3
-    mov eax, p4_table
+    mov eax, p4_table - KERNEL_OFFSET
     mov cr3, eax
 
     ; enable PAE
@@ -60,12 +74,12 @@ start:
 
     ; Point the last entry in the P4 table back to itself
     ; AKA recursive table mapping.
-    mov eax, p4_table
+    mov eax, p4_table - KERNEL_OFFSET
     or eax, 0b11 ; present + writable
-    mov [p4_table + 511 * 8], eax
+    mov [p4_table - KERNEL_OFFSET + 511 * 8], eax
 
     ; load Global Descriptor Table
-    lgdt [gdt64.pointer]
+    lgdt [gdt64.pointer - KERNEL_OFFSET]
 
     ; update selectors
 	mov ax, gdt64.data
@@ -103,5 +117,9 @@ gdt64:
     dq (1<<44) | (1<<47) | (1<<41)
 .pointer:
     dw .pointer - gdt64 - 1
-    dq gdt64
+    dq gdt64 - KERNEL_OFFSET
 
+; The GDT is reloaded through its higher half address before the identity map is removed
+gdt64_high_pointer:
+    dw gdt64.pointer - gdt64 - 1
+    dq gdt64
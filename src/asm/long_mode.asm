@@ -1,23 +1,56 @@
 global long_mode_start
 
+; Offset of the kernel's virtual addresses from its physical load address, see linker.ld
+KERNEL_OFFSET equ 0xffffff0000000000
+
+extern p4_table
+extern stack_top
+extern gdt64_high_pointer
+
 ; Finally into 64 bit mode :)
 
-section .text
+section .boot
 bits 64
 long_mode_start:
-	
+
+	; Still running from the identity map, jump to the higher half
+    mov rax, higher_half_start
+    jmp rax
+
+section .text
+bits 64
+higher_half_start:
+
+	; Switch to the higher half addresses of the stack and GDT
+    mov rsp, stack_top
+    lgdt [rel gdt64_high_pointer]
+
+	; Remove the identity map, leaving the lower half free
+    mov qword [rel p4_table], 0
+    mov rax, cr3
+    mov cr3, rax
+
+	; Pass the higher half address of the Multiboot info to kernel_main
+    mov edi, edi
+    mov rax, KERNEL_OFFSET
+    add rdi, rax
+
+	; Clear the frame pointer so backtraces terminate at kernel_main
+    xor rbp, rbp
+
 	; Call the kernel_main function 
     extern kernel_main
     call kernel_main
 
     ; kernel_main returned, print `OS returned!`
+    mov rdx, KERNEL_OFFSET + 0xb8000
     mov rax, 0x4f724f204f534f4f
-    mov [0xb8000], rax
+    mov [rdx], rax
     mov rax, 0x4f724f754f744f65
-    mov [0xb8008], rax
+    mov [rdx + 8], rax
     mov rax, 0x4f214f644f654f6e
-    mov [0xb8010], rax
+    mov [rdx + 16], rax
     hlt
 
     cli
-    hlt
\ No newline at end of file
+    hlt
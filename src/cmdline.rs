@@ -0,0 +1,50 @@
+use alloc::String;
+
+use multiboot2;
+
+/// Copy of the kernel command line passed by the bootloader
+static mut CMDLINE: Option<String> = None;
+
+/// Copy the kernel command line out of the multiboot information structure.
+///
+/// The command line is copied onto the heap so it remains available after the multiboot
+/// information structure is no longer mapped. Must be called after the heap is initialized.
+pub fn init(multiboot_info_address: usize) {
+    let boot_info = unsafe { multiboot2::load(multiboot_info_address) };
+
+    let cmdline = boot_info
+        .command_line_tag()
+        .map(|tag| String::from(tag.command_line()))
+        .unwrap_or(String::new());
+
+    unsafe {
+        CMDLINE = Some(cmdline);
+    }
+}
+
+/// Returns the full kernel command line
+///
+/// Returns an empty string if `init` has not yet been called.
+pub fn cmdline() -> &'static str {
+    unsafe {
+        match CMDLINE {
+            Some(ref s) => s.as_str(),
+            None => "",
+        }
+    }
+}
+
+/// Returns the value of the command line option `key`.
+///
+/// Options are whitespace separated and take the form `key=value`. An option given without a value
+/// returns an empty string, an option that is not present returns `None`.
+pub fn get(key: &str) -> Option<&'static str> {
+    for option in cmdline().split_whitespace() {
+        let mut parts = option.splitn(2, '=');
+        if parts.next() == Some(key) {
+            return Some(parts.next().unwrap_or(""));
+        }
+    }
+
+    None
+}
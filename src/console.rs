@@ -0,0 +1,25 @@
+use core::fmt;
+use core::fmt::Write;
+
+use vga_buffer;
+use drivers::serial;
+
+macro_rules! kprint {
+    ($($arg:tt)*) => ({
+            $crate::console::print(format_args!($($arg)*));
+    });
+}
+
+macro_rules! kprintln {
+    ($fmt:expr) => (kprint!(concat!($fmt, "\n")));
+    ($fmt:expr, $($arg:tt)*) => (kprint!(concat!($fmt, "\n"), $($arg)*));
+}
+
+/// Print formatted output to every console device.
+///
+/// Output is written to both the VGA text buffer and the COM1 serial port so that it can still be
+/// captured once it has scrolled off the screen, or when running without a display.
+pub fn print(args: fmt::Arguments) {
+    vga_buffer::WRITER.lock().write_fmt(args).unwrap();
+    serial::COM1.lock().write_fmt(args).unwrap();
+}
@@ -0,0 +1,228 @@
+//! Saving and restoring the FPU, SSE and AVX registers of tasks.
+//!
+//! The registers are switched lazily. The scheduler sets CR0.TS when switching to a task other
+//! than the one whose state the registers hold, so the task's first FPU instruction raises a
+//! device not available exception. The registers are then saved for their previous owner and
+//! loaded for the task, see `Scheduler::switch_fpu`. Tasks that never use the FPU never pay for
+//! saving it.
+//!
+//! The kernel is built without SSE (see `x86_64-opsys.json`), so only code that uses the FPU
+//! explicitly, such as user programs, traps.
+
+use alloc::Vec;
+
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering, ATOMIC_BOOL_INIT, ATOMIC_USIZE_INIT};
+
+use x86::controlregs::{cr0, cr0_write, cr4, cr4_write};
+
+use super::cpuid;
+
+/// Monitor coprocessor, makes WAIT/FWAIT trap while TS is set
+const CR0_MP: usize = 1 << 1;
+/// Emulation, makes every FPU instruction trap
+const CR0_EM: usize = 1 << 2;
+/// Task switched, makes the next FPU instruction trap
+const CR0_TS: usize = 1 << 3;
+/// Report x87 errors with an exception rather than the legacy external interrupt
+const CR0_NE: usize = 1 << 5;
+
+/// Enable FXSAVE, FXRSTOR and SSE instructions
+const CR4_OSFXSR: usize = 1 << 9;
+/// Report unmasked SSE errors with an exception
+const CR4_OSXMMEXCPT: usize = 1 << 10;
+/// Enable XSAVE, XRSTOR and XSETBV
+const CR4_OSXSAVE: usize = 1 << 18;
+
+/// Feature bits in ECX of CPUID leaf 1
+const CPUID_XSAVE: u32 = 1 << 26;
+const CPUID_AVX: u32 = 1 << 28;
+
+/// State components enabled in XCR0
+const XCR0_X87: u64 = 1 << 0;
+const XCR0_SSE: u64 = 1 << 1;
+const XCR0_AVX: u64 = 1 << 2;
+
+/// Size of the area saved by FXSAVE
+const FXSAVE_AREA_SIZE: usize = 512;
+
+/// Alignment of the save area, FXSAVE needs 16 bytes and XSAVE 64
+const AREA_ALIGN: usize = 64;
+
+/// Value of MXCSR after reset, with every SSE exception masked
+pub const MXCSR_DEFAULT: u32 = 0x1f80;
+
+/// Set if XSAVE is used rather than FXSAVE
+static USE_XSAVE: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// Size of the area saved for each task
+static AREA_SIZE: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// The registers given to a task when it first uses the FPU
+static mut INITIAL_STATE: Option<FpuState> = None;
+
+/// Enable the FPU, SSE and, if supported, AVX, and capture the initial state given to tasks.
+///
+/// XSAVE is used to save the registers if the CPU supports it, otherwise FXSAVE. The heap must be
+/// initialised. CR0.TS is left set so the first task to use the FPU traps.
+pub fn init() {
+    let (_, _, features, _) = cpuid(1);
+    let xsave = features & CPUID_XSAVE != 0;
+
+    unsafe {
+        cr0_write((cr0() & !(CR0_EM | CR0_TS)) | CR0_MP | CR0_NE);
+
+        let mut flags = cr4() | CR4_OSFXSR | CR4_OSXMMEXCPT;
+        if xsave {
+            flags |= CR4_OSXSAVE;
+        }
+        cr4_write(flags);
+    }
+
+    let size = if xsave {
+        let mut components = XCR0_X87 | XCR0_SSE;
+        if features & CPUID_AVX != 0 {
+            components |= XCR0_AVX;
+        }
+        unsafe { xsetbv(0, components) };
+
+        // EBX of leaf 0xD is the size of the area for the components enabled in XCR0
+        let (_, size, _, _) = cpuid(0xd);
+        size as usize
+    } else {
+        FXSAVE_AREA_SIZE
+    };
+
+    USE_XSAVE.store(xsave, Ordering::SeqCst);
+    AREA_SIZE.store(size, Ordering::SeqCst);
+
+    unsafe {
+        asm!("fninit" :::: "intel", "volatile");
+        write_mxcsr(MXCSR_DEFAULT);
+
+        let mut initial = FpuState::zeroed();
+        initial.save();
+        INITIAL_STATE = Some(initial);
+    }
+
+    set_task_switched(true);
+
+    kinfo!(
+        "FPU state saved with {}, {} bytes per task",
+        if xsave { "XSAVE" } else { "FXSAVE" },
+        size
+    );
+}
+
+/// Set or clear CR0.TS. While it is set the next FPU instruction raises a device not available
+/// exception.
+pub fn set_task_switched(switched: bool) {
+    unsafe {
+        let value = cr0();
+        let new_value = if switched {
+            value | CR0_TS
+        } else {
+            value & !CR0_TS
+        };
+
+        // Writing CR0 serializes the CPU, so avoid it when nothing changes
+        if new_value != value {
+            cr0_write(new_value);
+        }
+    }
+}
+
+/// Returns the SSE control and status register
+pub fn read_mxcsr() -> u32 {
+    let mut mxcsr: u32 = 0;
+    unsafe {
+        asm!("stmxcsr [$0]" :: "r"(&mut mxcsr) : "memory" : "intel", "volatile");
+    }
+    mxcsr
+}
+
+/// Set the SSE control and status register
+pub unsafe fn write_mxcsr(mxcsr: u32) {
+    asm!("ldmxcsr [$0]" :: "r"(&mxcsr) :: "intel", "volatile");
+}
+
+/// Set the extended control register `register`
+unsafe fn xsetbv(register: u32, value: u64) {
+    asm!("xsetbv"
+         :: "{ecx}"(register), "{eax}"(value as u32), "{edx}"((value >> 32) as u32)
+         :: "volatile");
+}
+
+/// The saved FPU, SSE and AVX registers of a task
+pub struct FpuState {
+    /// Holds the save area, which starts at the first `AREA_ALIGN` aligned address
+    buffer: Vec<u8>,
+}
+
+impl FpuState {
+    /// Construct the state of a task that has not yet used the FPU
+    pub fn new() -> FpuState {
+        let initial = unsafe { INITIAL_STATE.as_ref() }.expect("FPU not initialised");
+
+        let mut state = FpuState::zeroed();
+        state.area_mut().copy_from_slice(initial.area());
+        state
+    }
+
+    /// Construct a state with a zeroed save area
+    fn zeroed() -> FpuState {
+        let mut buffer = Vec::new();
+        buffer.resize(AREA_SIZE.load(Ordering::SeqCst) + AREA_ALIGN - 1, 0);
+
+        FpuState { buffer: buffer }
+    }
+
+    /// Save the FPU registers into this state
+    pub unsafe fn save(&mut self) {
+        let area = self.area_mut().as_mut_ptr();
+
+        // Save every component enabled in XCR0
+        if USE_XSAVE.load(Ordering::Relaxed) {
+            asm!("xsave64 [$0]"
+                 :: "r"(area), "{eax}"(0xffff_ffffu32), "{edx}"(0xffff_ffffu32)
+                 : "memory" : "intel", "volatile");
+        } else {
+            asm!("fxsave64 [$0]" :: "r"(area) : "memory" : "intel", "volatile");
+        }
+    }
+
+    /// Load the FPU registers from this state
+    pub unsafe fn restore(&self) {
+        let area = self.area().as_ptr();
+
+        if USE_XSAVE.load(Ordering::Relaxed) {
+            asm!("xrstor64 [$0]"
+                 :: "r"(area), "{eax}"(0xffff_ffffu32), "{edx}"(0xffff_ffffu32)
+                 :: "intel", "volatile");
+        } else {
+            asm!("fxrstor64 [$0]" :: "r"(area) :: "intel", "volatile");
+        }
+    }
+
+    fn area(&self) -> &[u8] {
+        let offset = self.area_offset();
+        &self.buffer[offset..offset + AREA_SIZE.load(Ordering::Relaxed)]
+    }
+
+    fn area_mut(&mut self) -> &mut [u8] {
+        let offset = self.area_offset();
+        &mut self.buffer[offset..offset + AREA_SIZE.load(Ordering::Relaxed)]
+    }
+
+    /// Offset of the first aligned address in `buffer`
+    fn area_offset(&self) -> usize {
+        let address = self.buffer.as_ptr() as usize;
+        (AREA_ALIGN - address % AREA_ALIGN) % AREA_ALIGN
+    }
+}
+
+impl fmt::Debug for FpuState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "FpuState")
+    }
+}
@@ -1,3 +1,7 @@
+use io::Port;
+
+pub mod fpu;
+
 /// Sleep the CPU untill the next interrupt
 macro_rules! halt {
     () => {
@@ -11,3 +15,93 @@ macro_rules! hang {
         loop { halt!(); }
     }
 }
+
+/// Interrupt enable flag in RFLAGS
+const RFLAGS_IF: u64 = 1 << 9;
+
+/// Returns the RFLAGS register
+pub fn rflags() -> u64 {
+    let rflags: u64;
+    unsafe {
+        asm!("pushfq
+              pop $0" : "=r"(rflags) ::: "intel", "volatile");
+    }
+    rflags
+}
+
+/// Returns true if interrupts are enabled
+pub fn interrupts_enabled() -> bool {
+    rflags() & RFLAGS_IF != 0
+}
+
+/// Run `f` with interrupts disabled, then enable them again if they were enabled before.
+pub fn without_interrupts<F: FnOnce() -> R, R>(f: F) -> R {
+    let enabled = interrupts_enabled();
+    if enabled {
+        unsafe { ::x86::irq::disable() };
+    }
+
+    let result = f();
+
+    if enabled {
+        unsafe { ::x86::irq::enable() };
+    }
+    result
+}
+
+/// Enable interrupts and sleep until the next one.
+///
+/// `sti` only takes effect after the following instruction, so an interrupt can't be handled
+/// between enabling interrupts and the `hlt`, where it would be missed.
+pub fn enable_interrupts_and_halt() {
+    unsafe {
+        asm!("sti
+              hlt" :::: "intel", "volatile");
+    }
+}
+
+/// Returns true if the CPU supports 1GiB pages
+pub fn has_1g_pages() -> bool {
+    // Extended leaf 0x80000001, EDX bit 26 (pdpe1gb)
+    let (max_extended_leaf, _, _, _) = cpuid(0x8000_0000);
+    if max_extended_leaf < 0x8000_0001 {
+        return false;
+    }
+
+    let (_, _, _, edx) = cpuid(0x8000_0001);
+    edx & (1 << 26) != 0
+}
+
+/// Execute CPUID for `leaf`, returning `(eax, ebx, ecx, edx)`
+fn cpuid(leaf: u32) -> (u32, u32, u32, u32) {
+    let (eax, ebx, ecx, edx): (u32, u32, u32, u32);
+
+    unsafe {
+        asm!("cpuid"
+             : "={eax}"(eax), "={ebx}"(ebx), "={ecx}"(ecx), "={edx}"(edx)
+             : "{eax}"(leaf), "{ecx}"(0)
+             :: "volatile");
+    }
+
+    (eax, ebx, ecx, edx)
+}
+
+/// Reset the machine.
+///
+/// Pulses the CPU reset line through the keyboard controller. If that fails a triple fault is
+/// forced by loading an empty IDT and raising an exception.
+pub fn reboot() -> ! {
+    let status = Port::new(0x64);
+
+    unsafe {
+        // Wait for the controller input buffer to empty then send the reset command
+        while status.read() & 0x02 != 0 {}
+        status.write(0xFE);
+
+        let empty_idt: [u16; 5] = [0; 5];
+        asm!("lidt [$0]
+              int3" :: "r"(&empty_idt) : "memory" : "intel", "volatile");
+    }
+
+    hang!();
+}
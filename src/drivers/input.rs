@@ -0,0 +1,7 @@
+/// Handle a character of console input.
+///
+/// Every input device (keyboard, serial) feeds its characters through here. For now input is
+/// simply echoed back to the console.
+pub fn handle_char(c: char) {
+    kprint!("{}", c);
+}
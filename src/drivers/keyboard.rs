@@ -1,5 +1,8 @@
 use io::Port;
 
+use drivers::input;
+use gdb;
+use log;
 use schedule::bottom_half::BottomHalf;
 
 use kernel::kget;
@@ -68,9 +71,12 @@ impl Keyboard {
             0xB8 => self.alt -= 1,
             0x3A => self.caps = !self.caps,
             _ => {
-                // Print the char to the console if valid
+                // Pass the char on to the console input if valid. Ctrl+Alt+G breaks into the
+                // debugger instead, and Ctrl+Alt+D prints the kernel log.
                 match self.get_char(code) {
-                    Some(c) => kprint!("{}", c),
+                    Some('g') if self.ctrl > 0 && self.alt > 0 => gdb::breakpoint(),
+                    Some('d') if self.ctrl > 0 && self.alt > 0 => log::dmesg(),
+                    Some(c) => input::handle_char(c),
                     None => (),
                 }
             }
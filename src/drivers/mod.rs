@@ -1,9 +1,13 @@
 mod rtc;
 mod keyboard;
 
+pub mod input;
+pub mod serial;
+
 // Drivers
 pub use self::rtc::Clock;
 pub use self::keyboard::Keyboard;
 
 // Bottom Halves
 pub use self::keyboard::KeyboardBottomHalf;
+pub use self::serial::SerialBottomHalf;
@@ -0,0 +1,148 @@
+use core::fmt;
+
+use sync_opsys::IrqSpinLock;
+
+use io::Port;
+
+use drivers::input;
+use schedule::bottom_half::BottomHalf;
+
+/// Base I/O port of the first serial port
+pub const COM1_PORT: u16 = 0x3F8;
+
+/// Base I/O port of the second serial port
+pub const COM2_PORT: u16 = 0x2F8;
+
+/// Divisor of the 115200 baud UART clock, 3 gives 38400 baud
+const BAUD_DIVISOR: u16 = 3;
+
+// Line status register bits
+const LSR_DATA_READY: u8 = 0x01;
+const LSR_TRANSMIT_EMPTY: u8 = 0x20;
+
+/// The COM1 serial port, shared by the console and the IRQ4 bottom half.
+///
+/// Console output may come from interrupt handlers, so the lock disables interrupts while held.
+pub static COM1: IrqSpinLock<SerialPort> = IrqSpinLock::new(SerialPort::new(COM1_PORT));
+
+/// Driver for a 16550 UART
+pub struct SerialPort {
+    data: Port,
+    int_enable: Port,
+    fifo_ctrl: Port,
+    line_ctrl: Port,
+    modem_ctrl: Port,
+    line_status: Port,
+}
+
+impl SerialPort {
+    /// Construct a new `SerialPort` with registers starting at port `base`.
+    ///
+    /// The UART is not configured until `init` is called.
+    pub const fn new(base: u16) -> SerialPort {
+        SerialPort {
+            data: Port::new(base),
+            int_enable: Port::new(base + 1),
+            fifo_ctrl: Port::new(base + 2),
+            line_ctrl: Port::new(base + 3),
+            modem_ctrl: Port::new(base + 4),
+            line_status: Port::new(base + 5),
+        }
+    }
+
+    /// Configure the UART for 38400 baud, 8 data bits, no parity and one stop bit (8N1).
+    ///
+    /// FIFOs are enabled and the 'received data available' interrupt is switched on so input is
+    /// delivered through IRQ4.
+    pub fn init(&mut self) {
+        unsafe {
+            // Disable all interrupts while configuring
+            self.int_enable.write(0x00);
+
+            // Set DLAB to program the baud rate divisor
+            self.line_ctrl.write(0x80);
+            self.data.write((BAUD_DIVISOR & 0xFF) as u8);
+            self.int_enable.write((BAUD_DIVISOR >> 8) as u8);
+
+            // Clear DLAB, 8 bits, no parity, one stop bit
+            self.line_ctrl.write(0x03);
+
+            // Enable and clear the FIFOs with a 14 byte threshold
+            self.fifo_ctrl.write(0xC7);
+
+            // Assert DTR and RTS and set OUT2, which gates the UART interrupt line
+            self.modem_ctrl.write(0x0B);
+
+            // Enable the 'received data available' interrupt
+            self.int_enable.write(0x01);
+        }
+    }
+
+    /// Disable all UART interrupts, the port must then be polled for input.
+    pub fn disable_interrupts(&mut self) {
+        unsafe {
+            self.int_enable.write(0x00);
+        }
+    }
+
+    /// Write a single byte, blocking until the transmit buffer is empty.
+    pub fn write_byte(&mut self, byte: u8) {
+        unsafe {
+            while self.line_status.read() & LSR_TRANSMIT_EMPTY == 0 {}
+            self.data.write(byte);
+        }
+    }
+
+    /// Read a single byte from the receive buffer.
+    ///
+    /// Returns `None` if no data is waiting.
+    pub fn read_byte(&mut self) -> Option<u8> {
+        unsafe {
+            if self.line_status.read() & LSR_DATA_READY == 0 {
+                None
+            } else {
+                Some(self.data.read())
+            }
+        }
+    }
+}
+
+impl fmt::Write for SerialPort {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            // Terminals expect a carriage return before each line feed
+            if byte == b'\n' {
+                self.write_byte(b'\r');
+            }
+            self.write_byte(byte)
+        }
+        Ok(())
+    }
+}
+
+pub struct SerialBottomHalf {}
+
+impl SerialBottomHalf {
+    pub fn new() -> SerialBottomHalf {
+        SerialBottomHalf {}
+    }
+}
+
+impl BottomHalf for SerialBottomHalf {
+    fn execute(&mut self) {
+        // Drain everything in the FIFO, more than one byte may have arrived since the interrupt
+        // was raised. The lock must be released before handling the input as it will be echoed
+        // back out through the console.
+        loop {
+            let byte = COM1.lock().read_byte();
+
+            match byte {
+                // Terminals send carriage return on enter and DEL on backspace
+                Some(b'\r') => input::handle_char('\n'),
+                Some(0x7F) => input::handle_char('\x08'),
+                Some(b) => input::handle_char(b as char),
+                None => break,
+            }
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use core::mem::size_of;
+use core::slice;
+use core::str;
+
+use multiboot2::BootInformation;
+
+use memory::KERNEL_OFFSET;
+
+/// Section type of a symbol table
+pub const SHT_SYMTAB: u32 = 2;
+
+/// Section type of a string table
+pub const SHT_STRTAB: u32 = 3;
+
+/// Symbol type of a function
+const STT_FUNC: u8 = 2;
+
+/// An ELF64 section header
+#[repr(C)]
+pub struct SectionHeader {
+    pub name: u32,
+    pub typ: u32,
+    pub flags: u64,
+    pub addr: u64,
+    pub offset: u64,
+    pub size: u64,
+    pub link: u32,
+    pub info: u32,
+    pub addralign: u64,
+    pub entry_size: u64,
+}
+
+impl SectionHeader {
+    pub fn start_address(&self) -> usize {
+        self.addr as usize
+    }
+
+    pub fn end_address(&self) -> usize {
+        (self.addr + self.size) as usize
+    }
+
+    /// Returns true if this is a symbol or string table loaded by the bootloader
+    pub fn is_symbol_data(&self) -> bool {
+        self.addr != 0 && (self.typ == SHT_SYMTAB || self.typ == SHT_STRTAB)
+    }
+}
+
+/// An ELF64 symbol table entry
+#[repr(C)]
+pub struct Symbol {
+    pub name: u32,
+    pub info: u8,
+    pub other: u8,
+    pub shndx: u16,
+    pub value: u64,
+    pub size: u64,
+}
+
+impl Symbol {
+    /// Returns true if this symbol names a function
+    pub fn is_function(&self) -> bool {
+        self.info & 0xf == STT_FUNC
+    }
+
+    /// Returns true if `addr` falls within this symbol
+    pub fn contains(&self, addr: usize) -> bool {
+        let start = self.value as usize;
+        addr >= start && addr < start + self.size as usize
+    }
+}
+
+/// Layout of the multiboot ELF sections tag as written by GRUB
+#[repr(C)]
+struct ElfSectionsTag {
+    typ: u32,
+    size: u32,
+    number_of_sections: u32,
+    entry_size: u32,
+    shndx: u32,
+}
+
+/// Returns all the kernel's ELF section headers, including the null section at index 0.
+///
+/// The multiboot2 section iterator skips unused sections, which breaks the indices used by the
+/// `link` field, so the headers are read directly from the tag instead.
+pub fn section_headers(boot_info: &BootInformation) -> &'static [SectionHeader] {
+    let tag = boot_info
+        .elf_sections_tag()
+        .expect("Elf sections tag required");
+
+    unsafe {
+        let tag = &*(tag as *const _ as *const ElfSectionsTag);
+        assert!(tag.entry_size as usize == size_of::<SectionHeader>());
+
+        let first = (tag as *const ElfSectionsTag).offset(1) as *const SectionHeader;
+        slice::from_raw_parts(first, tag.number_of_sections as usize)
+    }
+}
+
+/// Returns the symbol table and its linked string table
+///
+/// Returns `None` if the kernel was loaded without symbols.
+pub fn symbol_tables(boot_info: &BootInformation) -> Option<(&'static [Symbol], &'static [u8])> {
+    let sections = section_headers(boot_info);
+
+    let symtab = match sections.iter().find(|s| s.typ == SHT_SYMTAB && s.addr != 0) {
+        Some(s) => s,
+        None => return None,
+    };
+
+    let strtab = match sections.get(symtab.link as usize) {
+        Some(s) if s.typ == SHT_STRTAB && s.addr != 0 => s,
+        _ => return None,
+    };
+
+    unsafe {
+        // The tables are loaded at physical addresses, use their higher half mapping
+        let symbols = slice::from_raw_parts(
+            (KERNEL_OFFSET + symtab.addr as usize) as *const Symbol,
+            symtab.size as usize / size_of::<Symbol>(),
+        );
+        let strings = slice::from_raw_parts(
+            (KERNEL_OFFSET + strtab.addr as usize) as *const u8,
+            strtab.size as usize,
+        );
+
+        Some((symbols, strings))
+    }
+}
+
+/// Returns the NUL terminated string starting at `offset` within string table `strings`
+pub fn string_at(strings: &'static [u8], offset: u32) -> &'static str {
+    let bytes = match strings.get(offset as usize..) {
+        Some(b) => b,
+        None => return "",
+    };
+
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    str::from_utf8(&bytes[..len]).unwrap_or("<invalid>")
+}
@@ -0,0 +1,523 @@
+mod packet;
+
+use core::fmt;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+
+use x86::controlregs::{cr0, cr0_write};
+
+use drivers::serial::COM2_PORT;
+use kernel;
+use memory;
+use memory::PAGE_SIZE;
+use schedule::Scheduler;
+use schedule::task::TaskContext;
+use vga_buffer;
+
+use self::packet::{Connection, Response};
+
+/// Maximum size of a packet in either direction
+const PACKET_SIZE: usize = 2048;
+
+/// Number of registers in GDB's amd64 general register set
+const NUM_REGISTERS: usize = 24;
+
+/// Trap flag in RFLAGS, raises a debug exception after each instruction
+const RFLAGS_TF: u64 = 1 << 8;
+
+/// Write protect bit in CR0
+const CR0_WP: usize = 1 << 16;
+
+/// Signal reported to GDB for breakpoints and single steps
+const SIGTRAP: u8 = 5;
+
+static mut CONNECTION: Connection = Connection::new(COM2_PORT);
+static mut INPUT: [u8; PACKET_SIZE] = [0; PACKET_SIZE];
+static mut OUTPUT: [u8; PACKET_SIZE] = [0; PACKET_SIZE];
+
+/// Set while a debugger is attached, so stop replies are sent as soon as the stub is entered
+static ATTACHED: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// Initialise the GDB stub's UART (COM2).
+pub fn init() {
+    unsafe {
+        CONNECTION.init();
+    }
+}
+
+/// Trap into the debugger.
+pub fn breakpoint() {
+    unsafe {
+        asm!("int3" :::: "intel", "volatile");
+    }
+}
+
+/// Breakpoint exception handler
+pub fn handle_breakpoint(context: &mut TaskContext) {
+    handle_exception(context, SIGTRAP);
+}
+
+/// Debug exception handler, raised after each single step
+pub fn handle_debug(context: &mut TaskContext) {
+    handle_exception(context, SIGTRAP);
+}
+
+/// Run the stub until GDB resumes execution.
+///
+/// Called with interrupts disabled from an exception handler. `context` holds the registers of the
+/// interrupted code, changes to it take effect on return.
+fn handle_exception(context: &mut TaskContext, signal: u8) {
+    let connection = unsafe { &mut CONNECTION };
+    let mut session = Session::new(context, signal);
+
+    if ATTACHED.load(Ordering::SeqCst) {
+        let mut response = Response::new(unsafe { &mut OUTPUT });
+        session.stop_reply(&mut response);
+        connection.send(response.as_bytes());
+    } else {
+        // The console lock may be held by the interrupted code, write to VGA directly
+        unsafe {
+            vga_buffer::print_error(format_args!("gdb: waiting for debugger on COM2"));
+        }
+    }
+
+    loop {
+        let packet = connection.receive(unsafe { &mut INPUT });
+        let mut response = Response::new(unsafe { &mut OUTPUT });
+
+        match session.dispatch(packet, &mut response) {
+            Action::Reply => connection.send(response.as_bytes()),
+            Action::Resume => return,
+        }
+    }
+}
+
+/// What to do after handling a packet
+enum Action {
+    /// Send the response and wait for the next packet
+    Reply,
+    /// Return from the exception without replying
+    Resume,
+}
+
+/// State of the stub while stopped in a single exception
+struct Session<'a> {
+    /// Registers of the interrupted code
+    context: &'a mut TaskContext,
+    /// Id of the interrupted task, `None` if there is no scheduler yet
+    current_task: Option<u32>,
+    /// Id of the task selected by GDB for register access, `None` for the current task
+    selected_task: Option<u32>,
+    signal: u8,
+}
+
+impl<'a> Session<'a> {
+    fn new(context: &'a mut TaskContext, signal: u8) -> Session<'a> {
+        let current_task = scheduler()
+            .and_then(|s| s.get_active_task())
+            .map(|t| t.id());
+
+        Session {
+            context: context,
+            current_task: current_task,
+            selected_task: None,
+            signal: signal,
+        }
+    }
+
+    fn dispatch(&mut self, packet: &[u8], response: &mut Response) -> Action {
+        if packet.is_empty() {
+            return Action::Reply;
+        }
+
+        let args = &packet[1..];
+
+        match packet[0] {
+            b'?' => self.stop_reply(response),
+            b'g' => self.read_registers(response),
+            b'G' => self.write_registers(args, response),
+            b'p' => self.read_register(args, response),
+            b'P' => self.write_register(args, response),
+            b'm' => read_memory(args, response),
+            b'M' => write_memory(args, response),
+            b'H' => self.set_thread(args, response),
+            b'T' => self.thread_alive(args, response),
+            b'q' => self.query(args, response),
+            b'c' => return self.resume(args, false),
+            b's' => return self.resume(args, true),
+            b'D' => {
+                self.detach();
+                response.push_str("OK");
+            }
+            b'k' => {
+                self.detach();
+                return Action::Resume;
+            }
+            // Unsupported, reply with an empty packet
+            _ => (),
+        }
+
+        Action::Reply
+    }
+
+    fn stop_reply(&self, response: &mut Response) {
+        response.push(b'T');
+        response.push_hex_byte(self.signal);
+
+        if let Some(id) = self.current_task {
+            response.push_str("thread:");
+            response.push_hex_number(thread_id(id));
+            response.push(b';');
+        }
+    }
+
+    fn read_registers(&mut self, response: &mut Response) {
+        let ok = self.with_context(|context| {
+            for n in 0..NUM_REGISTERS {
+                let (value, size) = register(context, n).unwrap();
+                response.push_hex_le(value, size);
+            }
+        });
+
+        if ok.is_none() {
+            response.push_str("E01");
+        }
+    }
+
+    fn write_registers(&mut self, args: &[u8], response: &mut Response) {
+        let ok = self.with_context(|context| {
+            let mut rest = args;
+            for n in 0..NUM_REGISTERS {
+                let size = register(context, n).unwrap().1;
+                if rest.len() < size * 2 {
+                    break;
+                }
+
+                if let (Some(value), Some(reg)) =
+                    (packet::parse_hex_le(&rest[..size * 2]), register_mut(context, n))
+                {
+                    *reg = value;
+                }
+                rest = &rest[size * 2..];
+            }
+        });
+
+        response.push_str(if ok.is_some() { "OK" } else { "E01" });
+    }
+
+    fn read_register(&mut self, args: &[u8], response: &mut Response) {
+        let n = match packet::parse_hex(args) {
+            Some(n) => n as usize,
+            None => return response.push_str("E01"),
+        };
+
+        let value = self.with_context(|context| register(context, n))
+            .and_then(|r| r);
+
+        match value {
+            Some((value, size)) => response.push_hex_le(value, size),
+            None => response.push_str("E01"),
+        }
+    }
+
+    fn write_register(&mut self, args: &[u8], response: &mut Response) {
+        let (n, value) = match packet::split_once(args, b'=') {
+            Some((n, value)) => (packet::parse_hex(n), packet::parse_hex_le(value)),
+            None => return response.push_str("E01"),
+        };
+
+        let ok = match (n, value) {
+            (Some(n), Some(value)) => self.with_context(|context| {
+                match register_mut(context, n as usize) {
+                    Some(reg) => *reg = value,
+                    None => (), // Segment registers are not saved, ignore writes to them
+                }
+            }),
+            _ => None,
+        };
+
+        response.push_str(if ok.is_some() { "OK" } else { "E01" });
+    }
+
+    /// Handle `Hg<id>` and `Hc<id>`, selecting the thread for later operations
+    fn set_thread(&mut self, args: &[u8], response: &mut Response) {
+        if args.is_empty() {
+            return response.push_str("E01");
+        }
+
+        // Only register access ('g') is thread specific, continue and step always resume all tasks
+        if args[0] == b'g' {
+            self.selected_task = match parse_thread_id(&args[1..]) {
+                Ok(id) => id,
+                Err(()) => return response.push_str("E01"),
+            };
+        }
+
+        response.push_str("OK");
+    }
+
+    fn thread_alive(&self, args: &[u8], response: &mut Response) {
+        let alive = match parse_thread_id(args) {
+            Ok(Some(id)) => scheduler().map_or(false, |s| s.tasks().any(|t| t.id() == id)),
+            Ok(None) => true,
+            Err(()) => false,
+        };
+
+        response.push_str(if alive { "OK" } else { "E01" });
+    }
+
+    fn query(&self, args: &[u8], response: &mut Response) {
+        if args.starts_with(b"Supported") {
+            response.push_str("PacketSize=");
+            response.push_hex_number(PACKET_SIZE as u64);
+        } else if args == b"Attached" {
+            // Tell GDB to detach rather than kill when it exits
+            response.push(b'1');
+        } else if args == b"C" {
+            if let Some(id) = self.current_task {
+                response.push_str("QC");
+                response.push_hex_number(thread_id(id));
+            }
+        } else if args == b"fThreadInfo" {
+            response.push(b'm');
+            match scheduler() {
+                Some(s) => for (i, task) in s.tasks().enumerate() {
+                    if i > 0 {
+                        response.push(b',');
+                    }
+                    response.push_hex_number(thread_id(task.id()));
+                },
+                None => response.push(b'1'),
+            }
+        } else if args == b"sThreadInfo" {
+            // Everything was sent in the reply to qfThreadInfo
+            response.push(b'l');
+        } else if args.starts_with(b"ThreadExtraInfo,") {
+            let id = parse_thread_id(&args[16..]).ok().and_then(|id| id);
+            let task = id.and_then(|id| scheduler().and_then(|s| s.tasks().find(|t| t.id() == id)));
+
+            if let Some(task) = task {
+                let _ = write!(HexWriter(response), "{} {:?}", task.name(), task.get_status());
+            }
+        }
+    }
+
+    /// Handle `c[addr]` and `s[addr]`
+    fn resume(&mut self, args: &[u8], step: bool) -> Action {
+        if let Some(addr) = packet::parse_hex(args) {
+            self.context.rip = addr;
+        }
+
+        if step {
+            self.context.rflags |= RFLAGS_TF;
+        } else {
+            self.context.rflags &= !RFLAGS_TF;
+        }
+
+        ATTACHED.store(true, Ordering::SeqCst);
+        Action::Resume
+    }
+
+    fn detach(&mut self) {
+        self.context.rflags &= !RFLAGS_TF;
+        ATTACHED.store(false, Ordering::SeqCst);
+    }
+
+    /// Call `f` with the context of the selected task.
+    ///
+    /// The interrupted task's context is the live exception context, any other task's is the one
+    /// saved by the scheduler. Returns `None` if the selected task no longer exists.
+    fn with_context<F, R>(&mut self, f: F) -> Option<R>
+    where
+        F: FnOnce(&mut TaskContext) -> R,
+    {
+        match self.selected_task {
+            Some(id) if Some(id) != self.current_task => {
+                let task = match scheduler() {
+                    Some(s) => s.get_task_mut(id),
+                    None => None,
+                };
+
+                task.map(|task| {
+                    let mut context = *task.get_context();
+                    let result = f(&mut context);
+                    task.set_context(&context);
+                    result
+                })
+            }
+            _ => Some(f(&mut *self.context)),
+        }
+    }
+}
+
+/// Writes formatted text as hex encoded bytes
+struct HexWriter<'a, 'b: 'a>(&'a mut Response<'b>);
+
+impl<'a, 'b> fmt::Write for HexWriter<'a, 'b> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.0.push_hex_byte(byte);
+        }
+        Ok(())
+    }
+}
+
+fn scheduler() -> Option<&'static mut Scheduler> {
+    kernel::try_kget().map(|k| unsafe { &mut *k.scheduler.get() })
+}
+
+/// GDB thread ids start at 1 as 0 means 'any thread'
+fn thread_id(task_id: u32) -> u64 {
+    task_id as u64 + 1
+}
+
+/// Parse a GDB thread id into a task id.
+///
+/// Returns `Ok(None)` for the special ids 0 (any thread) and -1 (all threads).
+fn parse_thread_id(s: &[u8]) -> Result<Option<u32>, ()> {
+    if s == b"-1" {
+        return Ok(None);
+    }
+
+    match packet::parse_hex(s) {
+        Some(0) => Ok(None),
+        Some(id) => Ok(Some((id - 1) as u32)),
+        None => Err(()),
+    }
+}
+
+/// Returns register `n`, in GDB's amd64 numbering, and its size in bytes
+fn register(context: &TaskContext, n: usize) -> Option<(u64, usize)> {
+    let value = match n {
+        0 => context.rax,
+        1 => context.rbx,
+        2 => context.rcx,
+        3 => context.rdx,
+        4 => context.rsi,
+        5 => context.rdi,
+        6 => context.rbp,
+        7 => context.rsp,
+        8 => context.r8,
+        9 => context.r9,
+        10 => context.r10,
+        11 => context.r11,
+        12 => context.r12,
+        13 => context.r13,
+        14 => context.r14,
+        15 => context.r15,
+        16 => context.rip,
+        17 => context.rflags,
+        18 => context.cs,
+        19 => context.ss,
+        // ds, es, fs and gs are not saved in the context
+        20...23 => 0,
+        _ => return None,
+    };
+
+    Some((value, if n <= 16 { 8 } else { 4 }))
+}
+
+/// Returns a mutable reference to register `n`, `None` if it cannot be written
+fn register_mut(context: &mut TaskContext, n: usize) -> Option<&mut u64> {
+    match n {
+        0 => Some(&mut context.rax),
+        1 => Some(&mut context.rbx),
+        2 => Some(&mut context.rcx),
+        3 => Some(&mut context.rdx),
+        4 => Some(&mut context.rsi),
+        5 => Some(&mut context.rdi),
+        6 => Some(&mut context.rbp),
+        7 => Some(&mut context.rsp),
+        8 => Some(&mut context.r8),
+        9 => Some(&mut context.r9),
+        10 => Some(&mut context.r10),
+        11 => Some(&mut context.r11),
+        12 => Some(&mut context.r12),
+        13 => Some(&mut context.r13),
+        14 => Some(&mut context.r14),
+        15 => Some(&mut context.r15),
+        16 => Some(&mut context.rip),
+        17 => Some(&mut context.rflags),
+        18 => Some(&mut context.cs),
+        19 => Some(&mut context.ss),
+        _ => None,
+    }
+}
+
+/// Parse the `addr,length` arguments of a memory packet
+fn parse_range(args: &[u8]) -> Option<(usize, usize)> {
+    packet::split_once(args, b',').and_then(|(addr, len)| {
+        match (packet::parse_hex(addr), packet::parse_hex(len)) {
+            (Some(addr), Some(len)) => Some((addr as usize, len as usize)),
+            _ => None,
+        }
+    })
+}
+
+/// Returns true if every page in `addr..addr + len` is mapped
+fn is_mapped(addr: usize, len: usize) -> bool {
+    let mut page = addr & !(PAGE_SIZE - 1);
+    while page < addr + len {
+        if memory::translate(page).is_none() {
+            return false;
+        }
+        page += PAGE_SIZE;
+    }
+    true
+}
+
+/// Handle `m<addr>,<length>`
+fn read_memory(args: &[u8], response: &mut Response) {
+    let (addr, len) = match parse_range(args) {
+        Some(range) => range,
+        None => return response.push_str("E01"),
+    };
+
+    // Read up to the first unmapped page
+    for i in 0..len {
+        let byte_addr = addr + i;
+        if (i == 0 || byte_addr % PAGE_SIZE == 0) && !is_mapped(byte_addr, 1) {
+            break;
+        }
+        response.push_hex_byte(unsafe { *(byte_addr as *const u8) });
+    }
+
+    if response.is_empty() && len > 0 {
+        response.push_str("E14");
+    }
+}
+
+/// Handle `M<addr>,<length>:<data>`
+///
+/// Write protection is disabled for the duration of the write so breakpoints can be placed in
+/// read only kernel code.
+fn write_memory(args: &[u8], response: &mut Response) {
+    let (range, data) = match packet::split_once(args, b':') {
+        Some((range, data)) => (parse_range(range), data),
+        None => return response.push_str("E01"),
+    };
+
+    let (addr, len) = match range {
+        Some(range) if data.len() == range.1 * 2 => range,
+        _ => return response.push_str("E01"),
+    };
+
+    if !is_mapped(addr, len) {
+        return response.push_str("E14");
+    }
+
+    unsafe {
+        let saved_cr0 = cr0();
+        cr0_write(saved_cr0 & !CR0_WP);
+
+        for (i, pair) in data.chunks(2).enumerate() {
+            if let Some(byte) = packet::parse_hex(pair) {
+                *((addr + i) as *mut u8) = byte as u8;
+            }
+        }
+
+        cr0_write(saved_cr0);
+    }
+
+    response.push_str("OK");
+}
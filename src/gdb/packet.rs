@@ -0,0 +1,206 @@
+use drivers::serial::SerialPort;
+
+static HEX_DIGITS: [u8; 16] = *b"0123456789abcdef";
+
+/// A GDB Remote Serial Protocol connection over a UART.
+///
+/// The UART is polled, so this can be used with interrupts disabled.
+pub struct Connection {
+    port: SerialPort,
+}
+
+impl Connection {
+    /// Construct a new `Connection` on the UART with registers starting at port `base`.
+    pub const fn new(base: u16) -> Connection {
+        Connection {
+            port: SerialPort::new(base),
+        }
+    }
+
+    /// Configure the UART. Its interrupt is left disabled.
+    pub fn init(&mut self) {
+        self.port.init();
+        self.port.disable_interrupts();
+    }
+
+    fn read(&mut self) -> u8 {
+        loop {
+            if let Some(byte) = self.port.read_byte() {
+                return byte;
+            }
+        }
+    }
+
+    /// Receive the next packet into `buffer`, returning the packet data.
+    ///
+    /// Packets take the form `$data#cs` where `cs` is the modulo 256 sum of `data` as two hex
+    /// digits. Valid packets are acknowledged with `+`, retransmission of corrupt ones is requested
+    /// with `-`. Data that does not fit in `buffer` is dropped.
+    pub fn receive<'a>(&mut self, buffer: &'a mut [u8]) -> &'a [u8] {
+        loop {
+            // Wait for the start of a packet, skipping stray acks and interrupt requests
+            while self.read() != b'$' {}
+
+            let mut len = 0;
+            let mut checksum: u8 = 0;
+
+            loop {
+                let byte = self.read();
+                if byte == b'#' {
+                    break;
+                }
+
+                checksum = checksum.wrapping_add(byte);
+                if len < buffer.len() {
+                    buffer[len] = byte;
+                    len += 1;
+                }
+            }
+
+            let high = hex_value(self.read());
+            let low = hex_value(self.read());
+
+            match (high, low) {
+                (Some(h), Some(l)) if (h << 4 | l) == checksum => {
+                    self.port.write_byte(b'+');
+                    return &buffer[..len];
+                }
+                _ => self.port.write_byte(b'-'),
+            }
+        }
+    }
+
+    /// Send a packet holding `data`, retransmitting until it is acknowledged.
+    pub fn send(&mut self, data: &[u8]) {
+        let checksum = data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+
+        loop {
+            self.port.write_byte(b'$');
+            for &byte in data {
+                self.port.write_byte(byte);
+            }
+            self.port.write_byte(b'#');
+            self.port.write_byte(HEX_DIGITS[(checksum >> 4) as usize]);
+            self.port.write_byte(HEX_DIGITS[(checksum & 0xf) as usize]);
+
+            if self.read() == b'+' {
+                return;
+            }
+        }
+    }
+}
+
+/// A response packet under construction.
+///
+/// Data that does not fit in the underlying buffer is silently dropped.
+pub struct Response<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Response<'a> {
+    pub fn new(buffer: &'a mut [u8]) -> Response<'a> {
+        Response {
+            buffer: buffer,
+            len: 0,
+        }
+    }
+
+    pub fn push(&mut self, byte: u8) {
+        if self.len < self.buffer.len() {
+            self.buffer[self.len] = byte;
+            self.len += 1;
+        }
+    }
+
+    pub fn push_str(&mut self, s: &str) {
+        for byte in s.bytes() {
+            self.push(byte);
+        }
+    }
+
+    /// Push `byte` as two hex digits
+    pub fn push_hex_byte(&mut self, byte: u8) {
+        self.push(HEX_DIGITS[(byte >> 4) as usize]);
+        self.push(HEX_DIGITS[(byte & 0xf) as usize]);
+    }
+
+    /// Push the low `size` bytes of `value` in target (little endian) byte order
+    pub fn push_hex_le(&mut self, value: u64, size: usize) {
+        for i in 0..size {
+            self.push_hex_byte((value >> (i * 8)) as u8);
+        }
+    }
+
+    /// Push `value` as a big endian hex number without leading zeros
+    pub fn push_hex_number(&mut self, value: u64) {
+        let digits = if value == 0 {
+            1
+        } else {
+            16 - (value.leading_zeros() as usize / 4)
+        };
+
+        for i in (0..digits).rev() {
+            self.push(HEX_DIGITS[((value >> (i * 4)) & 0xf) as usize]);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buffer[..self.len]
+    }
+}
+
+/// Returns the value of the hex digit `c`
+pub fn hex_value(c: u8) -> Option<u8> {
+    match c {
+        b'0'...b'9' => Some(c - b'0'),
+        b'a'...b'f' => Some(c - b'a' + 10),
+        b'A'...b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Parse a big endian hex number, as used for addresses and lengths
+pub fn parse_hex(s: &[u8]) -> Option<u64> {
+    if s.is_empty() || s.len() > 16 {
+        return None;
+    }
+
+    let mut value = 0;
+    for &c in s {
+        match hex_value(c) {
+            Some(v) => value = (value << 4) | v as u64,
+            None => return None,
+        }
+    }
+
+    Some(value)
+}
+
+/// Parse a little endian sequence of hex encoded bytes, as used for register values
+pub fn parse_hex_le(s: &[u8]) -> Option<u64> {
+    if s.is_empty() || s.len() % 2 != 0 || s.len() > 16 {
+        return None;
+    }
+
+    let mut value = 0;
+    for (i, pair) in s.chunks(2).enumerate() {
+        match (hex_value(pair[0]), hex_value(pair[1])) {
+            (Some(h), Some(l)) => value |= ((h << 4 | l) as u64) << (i * 8),
+            _ => return None,
+        }
+    }
+
+    Some(value)
+}
+
+/// Split `s` into the parts before and after the first occurrence of `separator`
+pub fn split_once(s: &[u8], separator: u8) -> Option<(&[u8], &[u8])> {
+    s.iter()
+        .position(|&c| c == separator)
+        .map(|i| (&s[..i], &s[i + 1..]))
+}
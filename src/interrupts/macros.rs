@@ -1,48 +1,59 @@
+/// Save the general purpose registers of the interrupted context and call `$handler_impl`.
+///
+/// The registers are pushed in the reverse order of `TaskContext` so that, along with the interrupt
+/// stack frame pushed by the CPU, they form a `TaskContext` on the stack. A pointer to this is passed
+/// as the single argument to `$handler_impl`, any changes made to it are restored on return.
+macro_rules! save_context_and_call {
+    ($handler_impl:ident) => {{
+        unsafe {
+            asm!("push rbp
+                  push r15
+                  push r14
+                  push r13
+                  push r12
+                  push r11
+                  push r10
+                  push r9
+                  push r8
+                  push rsi
+                  push rdi
+                  push rdx
+                  push rcx
+                  push rbx
+                  push rax
+                  mov rdi, rsp
+
+                  call $0
+
+                  pop rax
+                  pop rbx
+                  pop rcx
+                  pop rdx
+                  pop rdi
+                  pop rsi
+                  pop r8
+                  pop r9
+                  pop r10
+                  pop r11
+                  pop r12
+                  pop r13
+                  pop r14
+                  pop r15
+                  pop rbp" :: "s"($handler_impl as unsafe fn(_)) :: "volatile", "intel");
+        }
+    }}
+}
+
 macro_rules! irq_handler {
     ($idt:expr, $irq:expr, $irq_handler:ident) => {{
         extern "x86-interrupt" fn base_handler(_: &mut ExceptionStackFrame) {
             // Base handler. Push and pop context between call to handler implementation.
-            unsafe {
-                asm!("push rbp
-                      push r15
-                      push r14
-                      push r13
-                      push r12
-                      push r11
-                      push r10
-                      push r9
-                      push r8
-                      push rsi
-                      push rdi
-                      push rdx
-                      push rcx
-                      push rbx
-                      push rax
-                      mov rdi, rsp
-
-                      call $0
-
-                      pop rax
-                      pop rbx
-                      pop rcx
-                      pop rdx
-                      pop rdi
-                      pop rsi
-                      pop r8
-                      pop r9
-                      pop r10
-                      pop r11
-                      pop r12
-                      pop r13
-                      pop r14
-                      pop r15
-                      pop rbp" :: "s"(base_handler_impl as unsafe fn(_)) :: "volatile", "intel");
-            }
+            save_context_and_call!(base_handler_impl);
         }
 
         unsafe fn base_handler_impl(context: *mut TaskContext) {
             // Call the interrupt specific handler
-            $irq_handler();
+            $irq_handler(&*context);
 
             PIC.send_end_of_interrupt($irq);
 
@@ -57,3 +68,19 @@ macro_rules! irq_handler {
         $idt.interrupts[$irq].set_handler_fn(base_handler);
     }}
 }
+
+/// Install `$handler` for an exception without an error code, passing it the full `TaskContext` of
+/// the interrupted code.
+macro_rules! context_exception_handler {
+    ($entry:expr, $handler:path) => {{
+        extern "x86-interrupt" fn base_handler(_: &mut ExceptionStackFrame) {
+            save_context_and_call!(base_handler_impl);
+        }
+
+        unsafe fn base_handler_impl(context: *mut TaskContext) {
+            $handler(&mut *context);
+        }
+
+        $entry.set_handler_fn(base_handler);
+    }}
+}
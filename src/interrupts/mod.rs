@@ -5,6 +5,9 @@ mod macros;
 
 use x86;
 use drivers;
+use gdb;
+use ksyms;
+use memory;
 use vga_buffer;
 
 use kernel::kget;
@@ -19,11 +22,17 @@ lazy_static! {
         // Set all the handlers. Set default handler if a specific is not defined
         // to help debugging
         idt.divide_by_zero.set_handler_fn(except_00);
+        idt.device_not_available.set_handler_fn(except_07);
         idt.page_fault.set_handler_fn(except_14);
 
+        // Debugger entry points
+        context_exception_handler!(idt.debug, gdb::handle_debug);
+        context_exception_handler!(idt.breakpoint, gdb::handle_breakpoint);
+
         // Interrupts
         irq_handler!(idt, 0, irq0);
         irq_handler!(idt, 1, irq1);
+        irq_handler!(idt, 4, irq4);
 
         idt
     };
@@ -42,7 +51,7 @@ pub fn init() {
     PIC.clear_mask(1);
     // PIC.clear_mask(2);
     // PIC.clear_mask(3);
-    // PIC.clear_mask(4);
+    PIC.clear_mask(4);
     // PIC.clear_mask(5);
 
     IDT.load();
@@ -58,27 +67,46 @@ pub fn init() {
 /// Divide by zero handler
 ///
 /// Prints out details of the exception then sleeps the CPU forever.
-extern "x86-interrupt" fn except_00(_: &mut ExceptionStackFrame) {
+extern "x86-interrupt" fn except_00(stack_frame: &mut ExceptionStackFrame) {
     unsafe {
-        vga_buffer::print_error(format_args!("EXCEPTION: Divide By Zero\n"));
+        vga_buffer::print_error(format_args!(
+            "EXCEPTION: Divide By Zero at {}\n",
+            ksyms::Location(stack_frame.instruction_pointer.0)
+        ));
     };
 
     hang!();
 }
 
+/// Device not available handler
+///
+/// Raised when a task uses the FPU while CR0.TS is set, the FPU registers are then switched to
+/// the task, see `Scheduler::switch_fpu`.
+extern "x86-interrupt" fn except_07(_stack_frame: &mut ExceptionStackFrame) {
+    let scheduler = unsafe { &mut *kget().scheduler.get() };
+    scheduler.switch_fpu();
+}
+
 /// Page fault handler
 ///
-/// Prints out details of the exception then sleeps the CPU forever.
+/// Faults in reserved memory that is not yet mapped are resolved by the memory manager, see
+/// `memory::handle_page_fault`. Otherwise prints out details of the exception then sleeps the CPU
+/// forever.
 extern "x86-interrupt" fn except_14(
     stack_frame: &mut ExceptionStackFrame,
     error_code: PageFaultErrorCode,
 ) {
+    let address = unsafe { x86::controlregs::cr2() };
+    if memory::handle_page_fault(address, error_code) {
+        return;
+    }
+
     unsafe {
         vga_buffer::print_error(format_args!(
-            "EXCEPTION: Page Fault accessing {:#x} \nerror code: {:?}\n{:#?}",
-            x86::controlregs::cr2(),
+            "EXCEPTION: Page Fault accessing {:#x} \nerror code: {:?}\nat {}",
+            address,
             error_code,
-            stack_frame.instruction_pointer
+            ksyms::Location(stack_frame.instruction_pointer.0)
         ));
     };
 
@@ -89,16 +117,19 @@ extern "x86-interrupt" fn except_14(
 
 /// Handler for IRQ0 - The PIT interrupt
 ///
-/// Ticks the system clock once.
-unsafe fn irq0() {
+/// Ticks the system clock once, and counts the tick against the interrupted task.
+unsafe fn irq0(context: &TaskContext) {
     let clock = &mut *kget().clock.get();
     clock.tick();
+
+    let scheduler = &mut *kget().scheduler.get();
+    scheduler.account_tick(context);
 }
 
 /// Handler for IRQ1 - The keyboard interrupt
 ///
 /// Instantiates and queues up a new keyboard driver bottom half.
-unsafe fn irq1() {
+unsafe fn irq1(_context: &TaskContext) {
     let scheduler = &*kget().scheduler.get();
 
     let bh_manager = scheduler.bh_manager();
@@ -106,3 +137,15 @@ unsafe fn irq1() {
 
     bh_manager.add_bh(bh);
 }
+
+/// Handler for IRQ4 - The COM1 serial interrupt
+///
+/// Queues up a new serial driver bottom half to drain the receive buffer.
+unsafe fn irq4(_context: &TaskContext) {
+    let scheduler = &*kget().scheduler.get();
+
+    let bh_manager = scheduler.bh_manager();
+    let bh = box drivers::SerialBottomHalf::new();
+
+    bh_manager.add_bh(bh);
+}
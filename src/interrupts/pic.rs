@@ -101,11 +101,11 @@ impl Pic {
         if irq < 8 {
             let mask = self.pic1.read() | (1 << irq);
             self.pic1.write(mask);
-            kprintln!("PIC1 Mask: 0x{:x}", mask);
+            kdebug!("PIC1 Mask: 0x{:x}", mask);
         } else {
             let mask = self.pic2.read() | (1 << (irq - 8));
             self.pic2.write(mask);
-            kprintln!("PIC2 Mask: 0x{:x}", mask);
+            kdebug!("PIC2 Mask: 0x{:x}", mask);
         }
     }
 
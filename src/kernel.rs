@@ -21,8 +21,8 @@ pub fn init(memory_manager: MemoryManager) {
     let scheduler = unsafe { &mut *kget().scheduler.get() };
     let mut mm = unsafe { &mut *kget().memory_manager.get() };
 
-    scheduler.new_task(&mut mm, hello);
-    scheduler.new_task(&mut mm, world);
+    scheduler.new_task(&mut mm, "hello", hello);
+    scheduler.new_task(&mut mm, "world", world);
 }
 
 fn hello() {
@@ -56,6 +56,18 @@ pub fn kget() -> &'static Kernel {
     }
 }
 
+/// Returns the global kernel object if it has been initialised.
+///
+/// Unlike `kget()` this is safe to call during early boot, before `init` has run.
+pub fn try_kget() -> Option<&'static Kernel> {
+    unsafe {
+        match PKERNEL {
+            Some(&mut ref p) => Some(p),
+            None => None,
+        }
+    }
+}
+
 pub struct Kernel {
     pub scheduler: UnsafeCell<Scheduler>,
     pub memory_manager: UnsafeCell<MemoryManager>,
@@ -0,0 +1,149 @@
+use core::fmt;
+use core::fmt::Write;
+
+use multiboot2;
+
+use elf::{self, Symbol};
+
+/// The kernel symbol table, empty if the kernel was loaded without symbols
+static mut SYMBOLS: &'static [Symbol] = &[];
+
+/// The string table holding the names of `SYMBOLS`
+static mut STRINGS: &'static [u8] = &[];
+
+/// Locate the kernel symbol and string tables from the multiboot ELF sections tag.
+///
+/// The tables are used in place, they must stay mapped for the lifetime of the kernel (see
+/// `remap_the_kernel`). Does not allocate so is safe to call before the heap is initialized.
+pub fn init(multiboot_info_address: usize) {
+    let boot_info = unsafe { multiboot2::load(multiboot_info_address) };
+
+    match elf::symbol_tables(boot_info) {
+        Some((symbols, strings)) => unsafe {
+            SYMBOLS = symbols;
+            STRINGS = strings;
+        },
+        None => kwarn!("no kernel symbol table, addresses will not be resolved"),
+    }
+}
+
+/// Find the function containing address `addr`.
+///
+/// Returns the (mangled) function name and the offset of `addr` from its start, or `None` if no
+/// function symbol covers `addr`.
+pub fn addr_to_symbol(addr: usize) -> Option<(&'static str, usize)> {
+    let (symbols, strings) = unsafe { (SYMBOLS, STRINGS) };
+
+    symbols
+        .iter()
+        .find(|s| s.is_function() && s.contains(addr))
+        .map(|s| (elf::string_at(strings, s.name), addr - s.value as usize))
+}
+
+/// Formats a code address as `function+0x1a`, falling back to the raw address if it cannot be
+/// resolved.
+pub struct Location(pub usize);
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match addr_to_symbol(self.0) {
+            Some((name, offset)) => write!(f, "{:#018x} {}+{:#x}", self.0, Demangle(name), offset),
+            None => write!(f, "{:#018x} <unknown>", self.0),
+        }
+    }
+}
+
+/// Formats a legacy mangled Rust symbol name as a readable path.
+///
+/// `_ZN4core9panicking5panic17h0123456789abcdefE` is displayed as `core::panicking::panic`. Names
+/// that are not mangled are displayed unchanged.
+pub struct Demangle(pub &'static str);
+
+impl fmt::Display for Demangle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = self.0;
+        if !name.starts_with("_ZN") || !name.ends_with('E') {
+            return f.write_str(name);
+        }
+
+        let mut rest = &name[3..name.len() - 1];
+        let mut first = true;
+
+        while !rest.is_empty() {
+            // Each path element is prefixed with its decimal length
+            let digits = rest.bytes().take_while(|&b| b >= b'0' && b <= b'9').count();
+            let len = match rest[..digits].parse::<usize>() {
+                Ok(len) if digits + len <= rest.len() => len,
+                _ => return f.write_str(name),
+            };
+
+            let element = &rest[digits..digits + len];
+            rest = &rest[digits + len..];
+
+            // The final element is a hash to disambiguate the symbol, skip it
+            if rest.is_empty() && element.len() == 17 && element.starts_with('h') {
+                break;
+            }
+
+            if !first {
+                f.write_str("::")?;
+            }
+            first = false;
+
+            write_element(f, element)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Write a single path element, expanding the `$..$` escapes used in mangled names
+fn write_element(f: &mut fmt::Formatter, element: &str) -> fmt::Result {
+    let mut rest = element;
+
+    // Leading underscores are added to elements that would otherwise start with '$'
+    if rest.starts_with("_$") {
+        rest = &rest[1..];
+    }
+
+    while !rest.is_empty() {
+        if rest.starts_with("..") {
+            f.write_str("::")?;
+            rest = &rest[2..];
+        } else if rest.starts_with('$') {
+            let end = match rest[1..].find('$') {
+                Some(i) => i + 1,
+                None => return f.write_str(rest),
+            };
+
+            match &rest[1..end] {
+                "SP" => f.write_char('@')?,
+                "BP" => f.write_char('*')?,
+                "RF" => f.write_char('&')?,
+                "LT" => f.write_char('<')?,
+                "GT" => f.write_char('>')?,
+                "LP" => f.write_char('(')?,
+                "RP" => f.write_char(')')?,
+                "C" => f.write_char(',')?,
+                "u20" => f.write_char(' ')?,
+                "u27" => f.write_char('\'')?,
+                "u5b" => f.write_char('[')?,
+                "u5d" => f.write_char(']')?,
+                "u7b" => f.write_char('{')?,
+                "u7d" => f.write_char('}')?,
+                "u7e" => f.write_char('~')?,
+                escape => write!(f, "${}$", escape)?,
+            }
+
+            rest = &rest[end + 1..];
+        } else {
+            let end = rest.find(|c: char| c == '$' || c == '.').unwrap_or(rest.len());
+            let end = if end == 0 { 1 } else { end };
+
+            f.write_str(&rest[..end])?;
+            rest = &rest[end..];
+        }
+    }
+
+    Ok(())
+}
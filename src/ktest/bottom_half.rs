@@ -0,0 +1,23 @@
+use core::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+
+use kernel::kget;
+use schedule::bottom_half::BottomHalf;
+
+use super::wait_until;
+
+static EXECUTED: AtomicBool = ATOMIC_BOOL_INIT;
+
+struct TestBottomHalf {}
+
+impl BottomHalf for TestBottomHalf {
+    fn execute(&mut self) {
+        EXECUTED.store(true, Ordering::SeqCst);
+    }
+}
+
+pub fn bottom_half_executes() {
+    let scheduler = unsafe { &*kget().scheduler.get() };
+    scheduler.bh_manager().add_bh(box TestBottomHalf {});
+
+    assert!(wait_until(|| EXECUTED.load(Ordering::SeqCst)));
+}
@@ -0,0 +1,11 @@
+use kernel::kget;
+
+use super::wait_until;
+
+pub fn clock_ticks() {
+    // The clock is driven by IRQ0 so ticking proves interrupts are being delivered
+    let clock = unsafe { &*kget().clock.get() };
+    let start = clock.now();
+
+    assert!(wait_until(|| clock.now() > start));
+}
@@ -0,0 +1,274 @@
+use alloc::Vec;
+use alloc::arc::Arc;
+use alloc::boxed::Box;
+
+use core::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+
+use kernel::kget;
+use memory;
+use memory::PAGE_SIZE;
+use memory::paging::{Level4, Table, KERNEL_P4_START};
+use memory::vma::{VmaError, VmaFlags, VmaSet, GROWS_DOWN, LAZY, READ, WRITE};
+
+use super::wait_until;
+
+pub fn heap_allocation() {
+    let boxed = Box::new(42);
+    assert_eq!(*boxed, 42);
+
+    let mut vec = Vec::new();
+    for i in 0..100 {
+        vec.push(i);
+    }
+    assert_eq!(vec.iter().sum::<usize>(), 4950);
+}
+
+pub fn translate_kernel_mapped() {
+    // The VGA buffer is mapped in the higher half by `remap_the_kernel`
+    assert_eq!(
+        memory::translate(memory::KERNEL_OFFSET + 0xb8000),
+        Some(0xb8000)
+    );
+
+    // The boot identity map has been removed
+    assert_eq!(memory::translate(0xb8000), None);
+}
+
+pub fn stack_allocation() {
+    let mm = unsafe { &mut *kget().memory_manager.get() };
+    let stack = mm.allocate_stack();
+
+    // Every page of the stack must be mapped and writable
+    let mut addr = stack.start_address;
+    while addr < stack.start_address + stack.size {
+        assert!(memory::translate(addr).is_some());
+
+        unsafe {
+            *(addr as *mut u64) = 0xdeadbeef;
+            assert_eq!(*(addr as *const u64), 0xdeadbeef);
+        }
+
+        addr += PAGE_SIZE;
+    }
+
+    // The page below the stack is a reserved guard page
+    let guard = stack.start_address - PAGE_SIZE;
+    assert_eq!(memory::translate(guard), None);
+    assert_eq!(
+        mm.kernel_vmas().find(guard).map(|vma| vma.flags()),
+        Some(VmaFlags::empty())
+    );
+
+    mm.deallocate_stack(&stack);
+}
+
+pub fn stack_canary() {
+    let mm = unsafe { &mut *kget().memory_manager.get() };
+    let stack = mm.allocate_stack();
+
+    assert!(stack.canary_intact());
+    assert_eq!(stack.high_water_mark(), 0);
+
+    unsafe { *((stack.top() - 64) as *mut u64) = 0 };
+    assert_eq!(stack.high_water_mark(), 64);
+
+    unsafe { *(stack.start_address as *mut u64) = 0 };
+    assert!(!stack.canary_intact());
+    assert_eq!(stack.high_water_mark(), stack.size);
+
+    // A reused stack is filled again
+    mm.deallocate_stack(&stack);
+    let stack = mm.allocate_stack();
+    assert!(stack.canary_intact());
+    assert_eq!(stack.high_water_mark(), 0);
+
+    mm.deallocate_stack(&stack);
+}
+
+pub fn direct_map() {
+    let boxed = Box::new(0x1234_5678_u64);
+    let address = &*boxed as *const u64 as usize;
+    let physical = memory::translate(address).unwrap();
+
+    // The same memory is visible through the direct map
+    let direct = memory::phys_to_virt(physical);
+    assert_eq!(unsafe { *(direct as *const u64) }, 0x1234_5678);
+
+    assert_eq!(memory::translate(direct), Some(physical));
+    assert_eq!(memory::virt_to_phys(direct), Some(physical));
+    assert_eq!(memory::virt_to_phys(address), Some(physical));
+}
+
+pub fn map_anonymous() {
+    let mm = unsafe { &mut *kget().memory_manager.get() };
+
+    // Mapped eagerly and zeroed, the size is rounded up to whole pages
+    let start = mm.map_anonymous(2 * PAGE_SIZE + 1, READ | WRITE).unwrap();
+    for i in 0..3 {
+        let address = start + i * PAGE_SIZE;
+        assert!(memory::translate(address).is_some());
+        unsafe {
+            assert_eq!(*(address as *const u64), 0);
+            *(address as *mut u64) = 0xdeadbeef;
+        }
+    }
+
+    mm.protect(start, PAGE_SIZE, READ).unwrap();
+    assert_eq!(mm.kernel_vmas().find(start).unwrap().flags(), READ);
+    assert_eq!(unsafe { *(start as *const u64) }, 0xdeadbeef);
+
+    mm.unmap(start, 3 * PAGE_SIZE).unwrap();
+    assert_eq!(memory::translate(start), None);
+    assert!(mm.kernel_vmas().find(start).is_none());
+
+    // Mapped on first access
+    let lazy = mm.map_anonymous(PAGE_SIZE, READ | WRITE | LAZY).unwrap();
+    assert_eq!(memory::translate(lazy), None);
+    unsafe { *(lazy as *mut u64) = 0xdeadbeef };
+    assert!(memory::translate(lazy).is_some());
+
+    mm.unmap(lazy, PAGE_SIZE).unwrap();
+}
+
+pub fn vma_set() {
+    let mut vmas = VmaSet::new(0x1000, 0x10_0000);
+
+    vmas.reserve(0x4000, 0x4000, READ | WRITE).unwrap();
+    assert_eq!(vmas.reserve(0x6000, 0x1000, READ), Err(VmaError::Overlap));
+    assert_eq!(vmas.reserve(0x6100, 0x1000, READ), Err(VmaError::Unaligned));
+    assert_eq!(vmas.reserve(0x10_0000, 0x1000, READ), Err(VmaError::OutOfBounds));
+
+    // The lowest free range is used
+    assert_eq!(vmas.reserve_any(0x3000, READ), Ok(0x1000));
+    assert_eq!(vmas.reserve_any(0x1000, READ), Ok(0x8000));
+
+    // Protecting part of a `Vma` splits it
+    vmas.protect(0x5000, 0x1000, READ).unwrap();
+    assert_eq!(vmas.find(0x4fff).unwrap().flags(), READ | WRITE);
+    assert_eq!(vmas.find(0x5000).unwrap().flags(), READ);
+    assert_eq!(vmas.find(0x6000).unwrap().start(), 0x6000);
+    assert_eq!(vmas.protect(0x9000, 0x1000, READ), Err(VmaError::NotReserved));
+
+    // Releasing a range shrinks the `Vma`s it overlaps
+    vmas.release(0x2000, 0x3000).unwrap();
+    assert_eq!(vmas.find(0x1000).unwrap().end(), 0x2000);
+    assert!(vmas.find(0x4000).is_none());
+    assert_eq!(vmas.iter().count(), 4);
+}
+
+/// Reserved, but not mapped, in the test address space
+const LAZY_ADDRESS: usize = 0x80_0000;
+
+/// Start of the test stack, which grows down
+const STACK_ADDRESS: usize = 0x100_0000;
+
+static LAZY_RESULT: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Read then write `LAZY_ADDRESS` and below `STACK_ADDRESS`, each access faults
+fn touch_lazy_pages() {
+    let lazy = LAZY_ADDRESS as *mut usize;
+    let stack = (STACK_ADDRESS - 2 * PAGE_SIZE) as *mut usize;
+
+    unsafe {
+        // Newly mapped pages are zeroed
+        let zero = *lazy;
+        *lazy = 21;
+        *stack = 21;
+        LAZY_RESULT.store(zero + *lazy + *stack, Ordering::SeqCst);
+    }
+}
+
+pub fn demand_paging() {
+    let scheduler = unsafe { &mut *kget().scheduler.get() };
+    let mm = unsafe { &mut *kget().memory_manager.get() };
+
+    let address_space = Arc::new(mm.new_address_space());
+    mm.reserve_in(&address_space, LAZY_ADDRESS, PAGE_SIZE, READ | WRITE).unwrap();
+    mm.reserve_in(&address_space, STACK_ADDRESS, PAGE_SIZE, READ | WRITE | GROWS_DOWN).unwrap();
+
+    scheduler.new_task_in(mm, "touch_lazy_pages", touch_lazy_pages, address_space.clone());
+
+    assert!(wait_until(|| LAZY_RESULT.load(Ordering::SeqCst) != 0));
+    assert_eq!(LAZY_RESULT.load(Ordering::SeqCst), 42);
+
+    // The stack grew to cover the fault below it
+    address_space.with(|mapper, vmas| {
+        assert!(mapper.translate(LAZY_ADDRESS).is_some());
+        let stack = vmas.find(STACK_ADDRESS - 2 * PAGE_SIZE).unwrap();
+        assert_eq!(stack.end(), STACK_ADDRESS + PAGE_SIZE);
+    });
+}
+
+/// Shared copy on write by the test address spaces
+const COW_ADDRESS: usize = 0xc0_0000;
+
+static PARENT_VALUE: AtomicUsize = ATOMIC_USIZE_INIT;
+static CHILD_VALUE: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Add `value` to the value at `COW_ADDRESS`, wait for other tasks to run, then publish what is
+/// read back
+fn add_to_shared(value: usize, result: &AtomicUsize) {
+    let shared = COW_ADDRESS as *mut usize;
+    unsafe { *shared += value };
+
+    for _ in 0..5 {
+        halt!();
+    }
+
+    result.store(unsafe { *shared }, Ordering::SeqCst);
+}
+
+fn add_in_parent() {
+    add_to_shared(1, &PARENT_VALUE);
+}
+
+fn add_in_child() {
+    add_to_shared(2, &CHILD_VALUE);
+}
+
+pub fn fork_copy_on_write() {
+    let scheduler = unsafe { &mut *kget().scheduler.get() };
+    let mm = unsafe { &mut *kget().memory_manager.get() };
+
+    let parent = Arc::new(mm.new_address_space());
+    mm.map_in(&parent, COW_ADDRESS, PAGE_SIZE, READ | WRITE).unwrap();
+
+    // The parent is not active, write its initial value through the direct map
+    let physical = parent.with(|mapper, _| mapper.translate(COW_ADDRESS)).unwrap();
+    unsafe { *(memory::phys_to_virt(physical) as *mut usize) = 40 };
+
+    // Both address spaces start out sharing the frame
+    let child = Arc::new(mm.fork_address_space(&parent));
+    assert_eq!(child.with(|mapper, _| mapper.translate(COW_ADDRESS)), Some(physical));
+
+    scheduler.new_task_in(mm, "add_in_parent", add_in_parent, parent.clone());
+    scheduler.new_task_in(mm, "add_in_child", add_in_child, child.clone());
+
+    assert!(wait_until(|| {
+        PARENT_VALUE.load(Ordering::SeqCst) != 0 && CHILD_VALUE.load(Ordering::SeqCst) != 0
+    }));
+    assert_eq!(PARENT_VALUE.load(Ordering::SeqCst), 41);
+    assert_eq!(CHILD_VALUE.load(Ordering::SeqCst), 42);
+
+    // The first write copied the frame, the second reused the original
+    let parent_frame = parent.with(|mapper, _| mapper.translate(COW_ADDRESS));
+    let child_frame = child.with(|mapper, _| mapper.translate(COW_ADDRESS));
+    assert!(parent_frame == Some(physical) || child_frame == Some(physical));
+    assert!(parent_frame != child_frame);
+}
+
+pub fn kernel_half_shared() {
+    let mm = unsafe { &mut *kget().memory_manager.get() };
+    let address_space = mm.new_address_space();
+
+    let active = memory::phys_to_virt(memory::active_p4_address());
+    let active = unsafe { &*(active as *const Table<Level4>) };
+
+    // Every kernel half entry points to a P3 table, so kernel mappings made later are shared
+    address_space.with(|mapper, _| {
+        for i in KERNEL_P4_START..511 {
+            assert!(active[i].pointed_frame().is_some());
+            assert_eq!(mapper.p4()[i].pointed_frame(), active[i].pointed_frame());
+        }
+    });
+}
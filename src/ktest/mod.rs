@@ -0,0 +1,225 @@
+mod memory;
+mod schedule;
+mod bottom_half;
+mod interrupts;
+mod sync;
+
+use core::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+
+use io::Port;
+use kernel::kget;
+
+/// I/O port of QEMU's `isa-debug-exit` device, see the `test` target in the Makefile
+const QEMU_EXIT_PORT: u16 = 0xf4;
+
+/// Number of clock ticks `wait_until` waits before giving up
+const WAIT_TIMEOUT: usize = 100;
+
+/// Exit codes passed to QEMU. QEMU exits with status `(code << 1) | 1`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum QemuExitCode {
+    Success = 0x10,
+    Failed = 0x11,
+}
+
+/// A single kernel test.
+///
+/// Tests fail by panicking, usually through `assert!`.
+pub struct Test {
+    pub name: &'static str,
+    pub run: fn(),
+}
+
+/// A kernel test that passes by panicking in `file`
+pub struct ShouldPanic {
+    pub name: &'static str,
+    pub run: fn(),
+    pub file: &'static str,
+}
+
+/// All kernel tests, run in order
+static TESTS: &'static [Test] = &[
+    Test {
+        name: "memory::heap_allocation",
+        run: memory::heap_allocation,
+    },
+    Test {
+        name: "memory::translate_kernel_mapped",
+        run: memory::translate_kernel_mapped,
+    },
+    Test {
+        name: "memory::stack_allocation",
+        run: memory::stack_allocation,
+    },
+    Test {
+        name: "memory::stack_canary",
+        run: memory::stack_canary,
+    },
+    Test {
+        name: "memory::direct_map",
+        run: memory::direct_map,
+    },
+    Test {
+        name: "memory::vma_set",
+        run: memory::vma_set,
+    },
+    Test {
+        name: "memory::map_anonymous",
+        run: memory::map_anonymous,
+    },
+    Test {
+        name: "interrupts::clock_ticks",
+        run: interrupts::clock_ticks,
+    },
+    Test {
+        name: "schedule::new_task_runs",
+        run: schedule::new_task_runs,
+    },
+    Test {
+        name: "schedule::completed_task_removed",
+        run: schedule::completed_task_removed,
+    },
+    Test {
+        name: "schedule::priority_order",
+        run: schedule::priority_order,
+    },
+    Test {
+        name: "schedule::fair_queue_weights",
+        run: schedule::fair_queue_weights,
+    },
+    Test {
+        name: "schedule::task_stats",
+        run: schedule::task_stats,
+    },
+    Test {
+        name: "schedule::wait_queue_wakes",
+        run: schedule::wait_queue_wakes,
+    },
+    Test {
+        name: "schedule::task_join_and_kill",
+        run: schedule::task_join_and_kill,
+    },
+    Test {
+        name: "schedule::stale_handle_not_killed",
+        run: schedule::stale_handle_not_killed,
+    },
+    Test {
+        name: "schedule::task_names_and_ids",
+        run: schedule::task_names_and_ids,
+    },
+    Test {
+        name: "schedule::fpu_state_switched",
+        run: schedule::fpu_state_switched,
+    },
+    Test {
+        name: "sync::mutex_excludes",
+        run: sync::mutex_excludes,
+    },
+    Test {
+        name: "sync::condvar_notifies",
+        run: sync::condvar_notifies,
+    },
+    Test {
+        name: "sync::rwlock_and_semaphore",
+        run: sync::rwlock_and_semaphore,
+    },
+    Test {
+        name: "schedule::separate_address_spaces",
+        run: schedule::separate_address_spaces,
+    },
+    Test {
+        name: "memory::kernel_half_shared",
+        run: memory::kernel_half_shared,
+    },
+    Test {
+        name: "memory::demand_paging",
+        run: memory::demand_paging,
+    },
+    Test {
+        name: "memory::fork_copy_on_write",
+        run: memory::fork_copy_on_write,
+    },
+    Test {
+        name: "bottom_half::bottom_half_executes",
+        run: bottom_half::bottom_half_executes,
+    },
+];
+
+/// Run after every other test.
+///
+/// A panic can't be recovered from, so this ends the run and the panic handler reports the result
+/// through `panicked`.
+static SHOULD_PANIC: ShouldPanic = ShouldPanic {
+    name: "sync::mutex_self_deadlock",
+    run: sync::mutex_self_deadlock,
+    file: "src/sync/deadlock.rs",
+};
+
+/// Set while `SHOULD_PANIC` runs
+static EXPECT_PANIC: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// Run every kernel test then exit QEMU.
+///
+/// Called from `kernel_main` once the kernel is fully initialized. A failing test panics, the panic
+/// handler then reports the failure and exits QEMU with `QemuExitCode::Failed`.
+pub fn run() -> ! {
+    kprintln!("running {} kernel tests", TESTS.len());
+
+    for test in TESTS {
+        kprint!("test {} ... ", test.name);
+        (test.run)();
+        kprintln!("ok");
+    }
+
+    kprint!("test {} ... ", SHOULD_PANIC.name);
+    EXPECT_PANIC.store(true, Ordering::SeqCst);
+    (SHOULD_PANIC.run)();
+
+    kprintln!("FAILED, did not panic");
+    exit_qemu(QemuExitCode::Failed);
+}
+
+/// Exit QEMU once the kernel has panicked in `file`, called by the panic handler.
+///
+/// The panic is expected if `SHOULD_PANIC` is running and panicked where it should, which ends a
+/// successful run. Any other panic is a test failure. The panic handler has already printed the
+/// panic, the console locks may be held so nothing more is printed here.
+pub fn panicked(file: &str) -> ! {
+    if EXPECT_PANIC.load(Ordering::SeqCst) && file == SHOULD_PANIC.file {
+        exit_qemu(QemuExitCode::Success);
+    }
+
+    exit_qemu(QemuExitCode::Failed);
+}
+
+/// Exit QEMU through the `isa-debug-exit` device.
+///
+/// If the device is not present the machine is hung instead.
+pub fn exit_qemu(code: QemuExitCode) -> ! {
+    unsafe {
+        Port::new(QEMU_EXIT_PORT).write(code as u8);
+    }
+
+    hang!();
+}
+
+/// Sleep until `condition` returns true.
+///
+/// Returns false if `condition` is still false after `WAIT_TIMEOUT` clock ticks.
+pub fn wait_until<F>(condition: F) -> bool
+where
+    F: Fn() -> bool,
+{
+    let clock = unsafe { &*kget().clock.get() };
+    let start = clock.now();
+
+    while !condition() {
+        if clock.now() - start > WAIT_TIMEOUT {
+            return false;
+        }
+
+        halt!();
+    }
+
+    true
+}
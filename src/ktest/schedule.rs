@@ -0,0 +1,353 @@
+use alloc::arc::Arc;
+
+use core::mem;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering, ATOMIC_BOOL_INIT, ATOMIC_USIZE_INIT};
+
+use cpu::fpu::{self, MXCSR_DEFAULT};
+use kernel::kget;
+use memory;
+use memory::PAGE_SIZE;
+use memory::vma::{READ, WRITE};
+use schedule::run_queue::{FairQueue, RunQueue};
+use schedule::{wait_event, WaitQueue};
+use schedule::task::{Task, TaskExit, TaskHandle, TaskPriority, TaskStatus, TidAllocator};
+use schedule::task::EXIT_KILLED;
+use schedule::task::{TID_BOTTOMHALFD, TID_SYSTEMIDLE};
+
+use super::wait_until;
+
+static TASK_RAN: AtomicBool = ATOMIC_BOOL_INIT;
+
+fn set_task_ran() {
+    TASK_RAN.store(true, Ordering::SeqCst);
+}
+
+fn do_nothing() {}
+
+pub fn new_task_runs() {
+    let scheduler = unsafe { &mut *kget().scheduler.get() };
+    let mm = unsafe { &mut *kget().memory_manager.get() };
+
+    scheduler.new_task(mm, "set_task_ran", set_task_ran);
+
+    assert!(wait_until(|| TASK_RAN.load(Ordering::SeqCst)));
+}
+
+pub fn completed_task_removed() {
+    let scheduler = unsafe { &mut *kget().scheduler.get() };
+    let mm = unsafe { &mut *kget().memory_manager.get() };
+
+    let count = scheduler.tasks().count();
+    scheduler.new_task(mm, "do_nothing", do_nothing);
+    assert_eq!(scheduler.tasks().count(), count + 1);
+
+    // COMPLETED tasks are dropped the next time they are switched out
+    assert!(wait_until(|| scheduler.tasks().count() == count));
+}
+
+static RUN_ORDER: AtomicUsize = ATOMIC_USIZE_INIT;
+static HIGH_RAN: AtomicUsize = ATOMIC_USIZE_INIT;
+static LOW_RAN: AtomicUsize = ATOMIC_USIZE_INIT;
+
+fn record_high() {
+    HIGH_RAN.store(RUN_ORDER.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+}
+
+fn record_low() {
+    LOW_RAN.store(RUN_ORDER.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+}
+
+pub fn priority_order() {
+    let scheduler = unsafe { &mut *kget().scheduler.get() };
+    let mm = unsafe { &mut *kget().memory_manager.get() };
+
+    scheduler.new_task_with_priority(mm, "record_low", record_low, TaskPriority::MIN);
+    scheduler.new_task_with_priority(mm, "record_high", record_high, TaskPriority::new(20));
+
+    // The low priority task is queued first but runs last. As this test task is never waiting
+    // it only runs at all due to starvation protection.
+    assert!(wait_until(|| LOW_RAN.load(Ordering::SeqCst) != 0));
+    assert_eq!(HIGH_RAN.load(Ordering::SeqCst), 1);
+    assert_eq!(LOW_RAN.load(Ordering::SeqCst), 2);
+}
+
+pub fn fair_queue_weights() {
+    let mut queue = FairQueue::new();
+
+    let normal = Task::default(1000);
+    let mut nice = Task::default(1001);
+    nice.set_nice(5);
+    queue.push(normal, 0);
+    queue.push(nice, 0);
+
+    // Run whichever task has the least virtual runtime for a tick at a time
+    let mut runs = [0, 0];
+    for _ in 0..400 {
+        let mut task = queue.pop().unwrap();
+        runs[(task.id() - 1000) as usize] += 1;
+        queue.charge(&mut task, 1);
+        queue.push(task, 0);
+    }
+
+    // Nice 5 has a weight of 335 against 1024 for nice 0, so runs roughly a third as often
+    assert!(runs[1] > 0);
+    assert!(runs[0] > 2 * runs[1] && runs[0] < 4 * runs[1]);
+
+    // These tasks own no stack, so must not be dropped
+    while let Some(task) = queue.pop() {
+        mem::forget(task);
+    }
+}
+
+static STOP_SPINNING: AtomicBool = ATOMIC_BOOL_INIT;
+
+fn spin_until_stopped() {
+    while !STOP_SPINNING.load(Ordering::SeqCst) {
+        halt!();
+    }
+}
+
+pub fn task_stats() {
+    let scheduler = unsafe { &mut *kget().scheduler.get() };
+    let mm = unsafe { &mut *kget().memory_manager.get() };
+
+    let id = scheduler.new_task(mm, "spin_until_stopped", spin_until_stopped).id();
+
+    // The task shares the CPU with this one, so is both preempted and kept waiting to run
+    assert!(wait_until(|| {
+        scheduler.stats().tasks.iter().any(|t| {
+            t.id == id && t.stats.kernel_ticks > 0 && t.stats.involuntary_switches > 0
+                && t.stats.ready_ticks > 0
+        })
+    }));
+
+    let stats = scheduler.stats();
+    assert!(stats.context_switches > 0);
+    assert_eq!(stats.tasks.len(), scheduler.tasks().count());
+
+    STOP_SPINNING.store(true, Ordering::SeqCst);
+    assert!(wait_until(|| scheduler.tasks().all(|t| t.id() != id)));
+}
+
+static mut TEST_QUEUE: Option<WaitQueue> = None;
+static WAKE_CONDITION: AtomicBool = ATOMIC_BOOL_INIT;
+static WAITERS_WOKEN: AtomicUsize = ATOMIC_USIZE_INIT;
+
+fn test_queue() -> &'static WaitQueue {
+    unsafe { TEST_QUEUE.as_ref().unwrap() }
+}
+
+fn wait_for_condition() {
+    wait_event(test_queue(), || WAKE_CONDITION.load(Ordering::SeqCst));
+    WAITERS_WOKEN.fetch_add(1, Ordering::SeqCst);
+}
+
+pub fn wait_queue_wakes() {
+    let scheduler = unsafe { &mut *kget().scheduler.get() };
+    let mm = unsafe { &mut *kget().memory_manager.get() };
+
+    unsafe { TEST_QUEUE = Some(WaitQueue::new()) };
+    let queue = test_queue();
+
+    let first = scheduler.new_task(mm, "wait_for_condition", wait_for_condition).id();
+    let second = scheduler.new_task(mm, "wait_for_condition", wait_for_condition).id();
+    let waiting = |id| {
+        scheduler
+            .tasks()
+            .any(|t| t.id() == id && t.get_status() == TaskStatus::WAITING)
+    };
+
+    assert!(wait_until(|| waiting(first) && waiting(second)));
+
+    // Woken without the condition being met, both go back to waiting
+    assert_eq!(queue.wake_all(), 2);
+    assert!(wait_until(|| waiting(first) && waiting(second)));
+    assert_eq!(WAITERS_WOKEN.load(Ordering::SeqCst), 0);
+
+    WAKE_CONDITION.store(true, Ordering::SeqCst);
+    assert!(queue.wake_one());
+    assert!(wait_until(|| WAITERS_WOKEN.load(Ordering::SeqCst) == 1));
+    assert!(waiting(second));
+
+    assert_eq!(queue.wake_all(), 1);
+    assert!(wait_until(|| WAITERS_WOKEN.load(Ordering::SeqCst) == 2));
+    assert!(queue.is_empty());
+}
+
+fn spin_forever() {
+    hang!();
+}
+
+pub fn task_join_and_kill() {
+    let scheduler = unsafe { &mut *kget().scheduler.get() };
+    let mm = unsafe { &mut *kget().memory_manager.get() };
+
+    let value = 40;
+    let adder = scheduler.spawn(mm, "adder", move || value + 2);
+    assert_eq!(adder.join(), 42);
+    assert_eq!(adder.status(), TaskStatus::COMPLETED);
+    assert!(!adder.kill());
+
+    let spinner = scheduler.new_task(mm, "spin_forever", spin_forever);
+    assert_eq!(spinner.status(), TaskStatus::READY);
+    assert_eq!(spinner.exit_code(), None);
+
+    assert!(spinner.kill());
+    assert_eq!(spinner.join(), EXIT_KILLED);
+    assert_eq!(spinner.status(), TaskStatus::COMPLETED);
+    assert!(scheduler.tasks().all(|t| t.id() != spinner.id()));
+}
+
+pub fn stale_handle_not_killed() {
+    let scheduler = unsafe { &mut *kget().scheduler.get() };
+    let mm = unsafe { &mut *kget().memory_manager.get() };
+
+    // Stands in for the handle of an exited task whose id was given to a new task
+    let exited = Arc::new(TaskExit::new());
+    exited.set(0);
+    let stale = |id| TaskHandle::new(id, exited.clone());
+
+    let spinner = scheduler.new_task(mm, "spin_forever", spin_forever);
+    assert!(!stale(spinner.id()).kill());
+    assert_eq!(spinner.exit_code(), None);
+    assert!(scheduler.tasks().any(|t| t.id() == spinner.id()));
+
+    // Killing through a stale handle with the id of the caller must not exit it
+    let active = scheduler.get_active_task().map(|t| t.id()).unwrap();
+    assert!(!stale(active).kill());
+
+    assert!(spinner.kill());
+}
+
+pub fn task_names_and_ids() {
+    let scheduler = unsafe { &mut *kget().scheduler.get() };
+    let mm = unsafe { &mut *kget().memory_manager.get() };
+
+    // Freed ids are only reused once the others have been handed out
+    let mut tids = TidAllocator::new();
+    let first = tids.allocate().unwrap();
+    assert!(first > TID_BOTTOMHALFD);
+    tids.free(first);
+    assert!(!tids.is_used(first));
+    let second = tids.allocate().unwrap();
+    assert_eq!(second, first + 1);
+    assert!(tids.is_used(second));
+
+    let sleeper = scheduler.new_task(mm, "sleeper", spin_forever);
+
+    let named = |id, name| scheduler.tasks().any(|t| t.id() == id && t.name() == name);
+    assert!(named(TID_SYSTEMIDLE, "idle"));
+    assert!(named(TID_BOTTOMHALFD, "bottomhalfd"));
+    assert!(named(sleeper.id(), "sleeper"));
+
+    // The idle task runs on the boot stack, which is not tracked
+    let usage = |id| scheduler.tasks().find(|t| t.id() == id).and_then(|t| t.stack_usage());
+    assert!(wait_until(|| usage(sleeper.id()).map_or(false, |bytes| bytes > 0)));
+    assert_eq!(usage(TID_SYSTEMIDLE), None);
+
+    let peak = |id| {
+        scheduler
+            .tasks()
+            .find(|t| t.id() == id)
+            .and_then(|t| t.stack_high_water_mark())
+    };
+    assert!(peak(sleeper.id()) >= usage(sleeper.id()));
+    assert!(scheduler.tasks().all(|t| t.stack_canary_intact()));
+
+    assert!(sleeper.kill());
+
+    let stats = scheduler.stats();
+    assert!(stats.tasks.iter().any(|t| t.name == "bottomhalfd"));
+    assert!(stats.tasks.iter().all(|t| t.id != sleeper.id()));
+}
+
+static FPU_TASKS_SET: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Set the SSE rounding mode to `mode`, then check it is kept while the other task sets its own.
+///
+/// Returns 0 on success, or the step that failed.
+fn keep_rounding_mode(mode: u32) -> i32 {
+    // Every task starts with the initial FPU state
+    if fpu::read_mxcsr() != MXCSR_DEFAULT {
+        return 1;
+    }
+
+    let mxcsr = MXCSR_DEFAULT | mode << 13;
+    unsafe { fpu::write_mxcsr(mxcsr) };
+
+    FPU_TASKS_SET.fetch_add(1, Ordering::SeqCst);
+    while FPU_TASKS_SET.load(Ordering::SeqCst) < 2 {
+        halt!();
+    }
+
+    if fpu::read_mxcsr() != mxcsr {
+        return 2;
+    }
+    0
+}
+
+pub fn fpu_state_switched() {
+    let scheduler = unsafe { &mut *kget().scheduler.get() };
+    let mm = unsafe { &mut *kget().memory_manager.get() };
+
+    let first = scheduler.spawn(mm, "round_down", || keep_rounding_mode(1));
+    let second = scheduler.spawn(mm, "round_up", || keep_rounding_mode(2));
+
+    assert_eq!(first.join(), 0);
+    assert_eq!(second.join(), 0);
+}
+
+/// Mapped separately in each test address space
+const PRIVATE_ADDRESS: usize = 0x40_0000;
+
+static FIRST_VALUE: AtomicUsize = ATOMIC_USIZE_INIT;
+static SECOND_VALUE: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Write `value` to `PRIVATE_ADDRESS`, wait for other tasks to run, then publish what is read back
+fn write_private(value: usize, result: &AtomicUsize) {
+    let private = PRIVATE_ADDRESS as *mut usize;
+    unsafe { *private = value };
+
+    for _ in 0..5 {
+        halt!();
+    }
+
+    result.store(unsafe { *private }, Ordering::SeqCst);
+}
+
+fn write_first() {
+    write_private(1, &FIRST_VALUE);
+}
+
+fn write_second() {
+    write_private(2, &SECOND_VALUE);
+}
+
+pub fn separate_address_spaces() {
+    let scheduler = unsafe { &mut *kget().scheduler.get() };
+    let mm = unsafe { &mut *kget().memory_manager.get() };
+
+    let first = Arc::new(mm.new_address_space());
+    let second = Arc::new(mm.new_address_space());
+    mm.map_in(&first, PRIVATE_ADDRESS, PAGE_SIZE, READ | WRITE).unwrap();
+    mm.map_in(&second, PRIVATE_ADDRESS, PAGE_SIZE, READ | WRITE).unwrap();
+
+    scheduler.new_task_in(mm, "write_first", write_first, first.clone());
+    scheduler.new_task_in(mm, "write_second", write_second, second.clone());
+
+    // Each task sees only its own write, the kernel address space has neither page
+    assert!(wait_until(|| {
+        FIRST_VALUE.load(Ordering::SeqCst) != 0 && SECOND_VALUE.load(Ordering::SeqCst) != 0
+    }));
+    assert_eq!(FIRST_VALUE.load(Ordering::SeqCst), 1);
+    assert_eq!(SECOND_VALUE.load(Ordering::SeqCst), 2);
+    assert_eq!(memory::translate(PRIVATE_ADDRESS), None);
+
+    // Wait for the tasks to be dropped, leaving the test with the last references
+    assert!(wait_until(|| {
+        Arc::strong_count(&first) == 1 && Arc::strong_count(&second) == 1
+    }));
+
+    mm.release_in(&first, PRIVATE_ADDRESS, PAGE_SIZE).unwrap();
+    mm.release_in(&second, PRIVATE_ADDRESS, PAGE_SIZE).unwrap();
+}
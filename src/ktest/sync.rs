@@ -0,0 +1,117 @@
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering, ATOMIC_BOOL_INIT, ATOMIC_USIZE_INIT};
+
+use kernel::kget;
+use schedule::task::TID_SYSTEMIDLE;
+use sync::{CondVar, Mutex, RwLock, Semaphore};
+
+use super::wait_until;
+
+static mut COUNTER: Option<Mutex<usize>> = None;
+static INCREMENTS_DONE: AtomicUsize = ATOMIC_USIZE_INIT;
+
+fn counter() -> &'static Mutex<usize> {
+    unsafe { COUNTER.as_ref().unwrap() }
+}
+
+/// Increment the counter a few times, halting while the lock is held so the other task contends
+fn increment_slowly() {
+    for _ in 0..3 {
+        let mut count = counter().lock();
+        let value = *count;
+        for _ in 0..3 {
+            halt!();
+        }
+        *count = value + 1;
+    }
+
+    INCREMENTS_DONE.fetch_add(1, Ordering::SeqCst);
+}
+
+pub fn mutex_excludes() {
+    let scheduler = unsafe { &mut *kget().scheduler.get() };
+    let mm = unsafe { &mut *kget().memory_manager.get() };
+
+    unsafe { COUNTER = Some(Mutex::new(0)) };
+
+    {
+        let count = counter().lock();
+        assert_eq!(counter().owner(), Some(TID_SYSTEMIDLE));
+        assert!(counter().try_lock().is_none());
+        assert_eq!(*count, 0);
+    }
+    assert_eq!(counter().owner(), None);
+
+    scheduler.new_task(mm, "increment_slowly", increment_slowly);
+    scheduler.new_task(mm, "increment_slowly", increment_slowly);
+
+    // Without mutual exclusion the read, halt, write increments would be lost
+    assert!(wait_until(|| INCREMENTS_DONE.load(Ordering::SeqCst) == 2));
+    assert_eq!(*counter().lock(), 6);
+}
+
+static mut FLAG: Option<(Mutex<bool>, CondVar)> = None;
+static WAITER_DONE: AtomicBool = ATOMIC_BOOL_INIT;
+
+fn flag() -> &'static (Mutex<bool>, CondVar) {
+    unsafe { FLAG.as_ref().unwrap() }
+}
+
+fn wait_for_flag() {
+    let &(ref mutex, ref condvar) = flag();
+
+    let set = condvar.wait_while(mutex.lock(), |set| !*set);
+    assert!(*set);
+
+    WAITER_DONE.store(true, Ordering::SeqCst);
+}
+
+pub fn condvar_notifies() {
+    let scheduler = unsafe { &mut *kget().scheduler.get() };
+    let mm = unsafe { &mut *kget().memory_manager.get() };
+
+    unsafe { FLAG = Some((Mutex::new(false), CondVar::new())) };
+    let &(ref mutex, ref condvar) = flag();
+
+    scheduler.new_task(mm, "wait_for_flag", wait_for_flag);
+
+    // A notification without the flag set leaves the task waiting
+    assert!(wait_until(|| condvar.notify_one()));
+    assert!(!wait_until(|| WAITER_DONE.load(Ordering::SeqCst)));
+
+    *mutex.lock() = true;
+    condvar.notify_all();
+    assert!(wait_until(|| WAITER_DONE.load(Ordering::SeqCst)));
+}
+
+pub fn rwlock_and_semaphore() {
+    let lock = RwLock::new(1);
+    {
+        let first = lock.read();
+        let second = lock.try_read().unwrap();
+        assert_eq!(*first + *second, 2);
+        assert_eq!(lock.readers(), 2);
+        assert!(lock.try_write().is_none());
+    }
+
+    {
+        let mut writer = lock.write();
+        *writer = 2;
+        assert_eq!(lock.writer(), Some(TID_SYSTEMIDLE));
+        assert!(lock.try_read().is_none());
+    }
+    assert_eq!(*lock.read(), 2);
+
+    let semaphore = Semaphore::new(2);
+    semaphore.acquire();
+    assert!(semaphore.try_acquire());
+    assert!(!semaphore.try_acquire());
+    semaphore.release();
+    assert_eq!(semaphore.count(), 1);
+}
+
+/// Take a lock the task already holds, the deadlock detector panics rather than let it wait
+pub fn mutex_self_deadlock() {
+    let mutex = Mutex::new(0);
+    let _guard = mutex.lock();
+    let _ = mutex.lock();
+}
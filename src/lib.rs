@@ -5,17 +5,21 @@
 #![feature(asm)]
 #![feature(abi_x86_interrupt)]
 #![feature(box_syntax)]
+#![feature(fnbox)]
 #![feature(global_allocator)]
 #![feature(const_unsafe_cell_new)]
 #![feature(const_unique_new)]
 #![feature(ptr_internals)]
+#![feature(conservative_impl_trait)]
 #![no_std]
 
 extern crate alloc;
 extern crate alloc_opsys;
 extern crate multiboot2;
+extern crate paging_opsys;
 extern crate rlibc;
 extern crate spin;
+extern crate sync_opsys;
 extern crate x86;
 extern crate x86_64;
 
@@ -31,6 +35,11 @@ use alloc_opsys::LockedAllocator;
 static ALLOCATOR: LockedAllocator = LockedAllocator::empty();
 
 #[macro_use]
+mod console;
+
+#[macro_use]
+mod log;
+
 mod vga_buffer;
 
 #[macro_use]
@@ -41,7 +50,16 @@ mod interrupts;
 mod drivers;
 mod io;
 mod schedule;
+mod sync;
 mod kernel;
+mod cmdline;
+mod elf;
+mod ksyms;
+mod panic;
+mod gdb;
+
+#[cfg(feature = "ktest")]
+mod ktest;
 
 // Main entry point, need no_mangle so we can call from assembly
 // Extern to abide with C calling convention
@@ -50,13 +68,20 @@ pub extern "C" fn kernel_main(multiboot_info_address: usize) {
     // Initialise the hardware
     init_cpu();
 
-    // Initialise the memory paging and instantiate a new memory manager
+    // Bring up the serial console first so all boot output can be captured
+    drivers::serial::COM1.lock().init();
+    gdb::init();
+    ksyms::init(multiboot_info_address);
+
+    // Initialise the memory paging and heap, and instantiate a new memory manager
     let memory_manager = memory::init(multiboot_info_address);
 
-    // Setup the heap allocator
-    unsafe {
-        ALLOCATOR.init(memory::KERN_HEAP_START, memory::KERN_HEAP_SIZE);
-    }
+    // Now the heap is available read the command line options
+    cmdline::init(multiboot_info_address);
+    log::init();
+
+    // Saving the FPU registers of tasks needs the heap
+    cpu::fpu::init();
 
     vga_buffer::clear_screen();
 
@@ -68,6 +93,22 @@ pub extern "C" fn kernel_main(multiboot_info_address: usize) {
 
     kprintln!("opsys v{}", "0.0.1");
 
+    // In a test build run the kernel tests, this never returns
+    #[cfg(feature = "ktest")]
+    ktest::run();
+
+    #[cfg(not(feature = "ktest"))]
+    idle();
+}
+
+/// Continue as the system idle task
+#[cfg(not(feature = "ktest"))]
+fn idle() -> ! {
+    // Stop in the debugger straight away if requested
+    if cmdline::get("gdb").is_some() {
+        gdb::breakpoint();
+    }
+
     // This thread now becomess the System 'Idle' thread.
     hang!();
 }
@@ -103,11 +144,7 @@ extern "C" fn eh_personality() {}
 #[no_mangle]
 #[lang = "panic_fmt"]
 pub extern "C" fn panic_fmt(fmt: core::fmt::Arguments, file: &str, line: u32) -> ! {
-    kprintln!("\n\nPANIC in {} at line {}:", file, line);
-    kprintln!("    {}", fmt);
-
-    // Hang here.
-    loop {}
+    panic::panic(fmt, file, line)
 }
 
 #[allow(non_snake_case)]
@@ -0,0 +1,302 @@
+use core::fmt;
+use core::fmt::Write;
+use core::str;
+
+use alloc::{String, Vec};
+
+use sync_opsys::IrqSpinLock;
+
+use cmdline;
+use kernel;
+
+/// Size of the in-memory kernel log in bytes
+const LOG_BUFFER_SIZE: usize = 16 * 1024;
+
+/// Level used for modules with no matching filter directive
+const DEFAULT_LEVEL: LogLevel = LogLevel::INFO;
+
+/// Severity of a kernel log message
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    /// Unrecoverable or unexpected failures
+    ERROR,
+    /// Recoverable problems
+    WARN,
+    /// Normal, notable events
+    INFO,
+    /// Information useful when debugging a subsystem
+    DEBUG,
+    /// Very noisy, low level information
+    TRACE,
+}
+
+impl LogLevel {
+    /// Parse a level from its lower case name
+    fn from_name(name: &str) -> Option<LogLevel> {
+        match name {
+            "error" => Some(LogLevel::ERROR),
+            "warn" => Some(LogLevel::WARN),
+            "info" => Some(LogLevel::INFO),
+            "debug" => Some(LogLevel::DEBUG),
+            "trace" => Some(LogLevel::TRACE),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match *self {
+            LogLevel::ERROR => "ERROR",
+            LogLevel::WARN => "WARN",
+            LogLevel::INFO => "INFO",
+            LogLevel::DEBUG => "DEBUG",
+            LogLevel::TRACE => "TRACE",
+        }
+    }
+}
+
+macro_rules! klog {
+    ($level:expr, $($arg:tt)*) => ({
+            $crate::log::log($level, module_path!(), format_args!($($arg)*));
+    });
+}
+
+macro_rules! kerror {
+    ($($arg:tt)*) => (klog!($crate::log::LogLevel::ERROR, $($arg)*));
+}
+
+macro_rules! kwarn {
+    ($($arg:tt)*) => (klog!($crate::log::LogLevel::WARN, $($arg)*));
+}
+
+macro_rules! kinfo {
+    ($($arg:tt)*) => (klog!($crate::log::LogLevel::INFO, $($arg)*));
+}
+
+macro_rules! kdebug {
+    ($($arg:tt)*) => (klog!($crate::log::LogLevel::DEBUG, $($arg)*));
+}
+
+macro_rules! ktrace {
+    ($($arg:tt)*) => (klog!($crate::log::LogLevel::TRACE, $($arg)*));
+}
+
+/// A per-module log level override
+struct Directive {
+    module: String,
+    level: LogLevel,
+}
+
+/// Decides which log messages are emitted
+struct Filter {
+    level: LogLevel,
+    directives: Option<Vec<Directive>>,
+}
+
+impl Filter {
+    const fn new() -> Filter {
+        Filter {
+            level: DEFAULT_LEVEL,
+            directives: None,
+        }
+    }
+
+    /// Parse a filter specification of the form `level,module=level,...`.
+    ///
+    /// A bare level sets the default for all modules. Module paths are relative to the crate root,
+    /// e.g. `memory::stack_allocator=trace`, and apply to all submodules. Unknown levels are ignored.
+    fn parse(&mut self, spec: &str) {
+        let mut directives = Vec::new();
+
+        for part in spec.split(',') {
+            let mut kv = part.splitn(2, '=');
+            let first = kv.next().unwrap_or("");
+
+            match kv.next() {
+                Some(level) => match LogLevel::from_name(level) {
+                    Some(level) => directives.push(Directive {
+                        module: String::from(first),
+                        level: level,
+                    }),
+                    None => kwarn!("ignoring unknown log level '{}'", level),
+                },
+                None => match LogLevel::from_name(first) {
+                    Some(level) => self.level = level,
+                    None => kwarn!("ignoring unknown log level '{}'", first),
+                },
+            }
+        }
+
+        self.directives = Some(directives);
+    }
+
+    /// Returns true if a message at `level` from `module` should be emitted.
+    ///
+    /// The directive with the longest module prefix matching `module` is used, falling back to the
+    /// default level.
+    fn enabled(&self, level: LogLevel, module: &str) -> bool {
+        let mut max_level = self.level;
+        let mut best_len = 0;
+
+        if let Some(ref directives) = self.directives {
+            for d in directives {
+                if d.module.len() >= best_len && module_matches(&d.module, module) {
+                    max_level = d.level;
+                    best_len = d.module.len();
+                }
+            }
+        }
+
+        level <= max_level
+    }
+}
+
+/// Returns true if `module` is `prefix` or one of its submodules
+fn module_matches(prefix: &str, module: &str) -> bool {
+    module.starts_with(prefix)
+        && (module.len() == prefix.len() || module[prefix.len()..].starts_with("::"))
+}
+
+/// Fixed size byte buffer that overwrites its oldest contents when full
+struct RingBuffer {
+    buffer: [u8; LOG_BUFFER_SIZE],
+    head: usize,
+    len: usize,
+}
+
+impl RingBuffer {
+    const fn new() -> RingBuffer {
+        RingBuffer {
+            buffer: [0; LOG_BUFFER_SIZE],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, byte: u8) {
+        let tail = (self.head + self.len) % LOG_BUFFER_SIZE;
+        self.buffer[tail] = byte;
+
+        if self.len == LOG_BUFFER_SIZE {
+            // Full, drop the oldest byte
+            self.head = (self.head + 1) % LOG_BUFFER_SIZE;
+        } else {
+            self.len += 1;
+        }
+    }
+
+    /// Returns the contents as two slices, oldest first
+    fn as_slices(&self) -> (&[u8], &[u8]) {
+        if self.head + self.len <= LOG_BUFFER_SIZE {
+            (&self.buffer[self.head..self.head + self.len], &[])
+        } else {
+            let wrapped = self.head + self.len - LOG_BUFFER_SIZE;
+            (&self.buffer[self.head..], &self.buffer[..wrapped])
+        }
+    }
+}
+
+impl fmt::Write for RingBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.push(byte);
+        }
+        Ok(())
+    }
+}
+
+// Messages are logged from interrupt handlers, for example when a task is dropped during a
+// reschedule, so both locks disable interrupts while held
+static FILTER: IrqSpinLock<Filter> = IrqSpinLock::new(Filter::new());
+static DMESG: IrqSpinLock<RingBuffer> = IrqSpinLock::new(RingBuffer::new());
+
+/// Configure log filtering from the `log=` kernel command line option.
+///
+/// Must be called after `cmdline::init`. Until then all modules log at the default level.
+pub fn init() {
+    if let Some(spec) = cmdline::get("log") {
+        // Parse into a new filter so the lock is not held while logging any parse warnings
+        let mut filter = Filter::new();
+        filter.parse(spec);
+        *FILTER.lock() = filter;
+    }
+}
+
+/// Log a message at `level` from module `module_path`.
+///
+/// Messages passing the filter are timestamped, stored in the kernel log and printed to the
+/// console. This is normally called through the `kerror!`..`ktrace!` macros.
+pub fn log(level: LogLevel, module_path: &str, args: fmt::Arguments) {
+    // Filters are relative to the crate root so strip off the crate name
+    let module = match module_path.find("::") {
+        Some(i) => &module_path[i + 2..],
+        None => module_path,
+    };
+
+    if !FILTER.lock().enabled(level, module) {
+        return;
+    }
+
+    // The clock is not available until the kernel is initialized
+    let now = match kernel::try_kget() {
+        Some(k) => unsafe { (*k.clock.get()).now() },
+        None => 0,
+    };
+
+    let (secs, millis) = (now / 1000, now % 1000);
+
+    let _ = write!(
+        DMESG.lock(),
+        "[{:>5}.{:03}] {:<5} {}: {}\n",
+        secs,
+        millis,
+        level.name(),
+        module,
+        args
+    );
+
+    kprintln!(
+        "[{:>5}.{:03}] {:<5} {}: {}",
+        secs,
+        millis,
+        level.name(),
+        module,
+        args
+    );
+}
+
+/// Print the contents of the kernel log to the console.
+///
+/// The log is copied out first, so interrupts are not held off while it is printed.
+pub fn dmesg() {
+    let mut log = Vec::new();
+    let wrapped = {
+        let buffer = DMESG.lock();
+        let (first, second) = buffer.as_slices();
+        log.extend_from_slice(first);
+        log.extend_from_slice(second);
+        buffer.len == LOG_BUFFER_SIZE
+    };
+
+    // If the log has wrapped the oldest line is most likely truncated, skip it
+    let start = if wrapped {
+        log.iter().position(|&b| b == b'\n').map_or(0, |i| i + 1)
+    } else {
+        0
+    };
+
+    // Characters may have been split across the wrap point, print what is valid
+    let mut rest = &log[start..];
+    while !rest.is_empty() {
+        match str::from_utf8(rest) {
+            Ok(s) => {
+                kprint!("{}", s);
+                break;
+            }
+            Err(e) => {
+                let valid = unsafe { str::from_utf8_unchecked(&rest[..e.valid_up_to()]) };
+                kprint!("{}", valid);
+                rest = &rest[e.valid_up_to() + 1..];
+            }
+        }
+    }
+}
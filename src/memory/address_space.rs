@@ -0,0 +1,121 @@
+use alloc::arc::Arc;
+
+use core::fmt;
+
+use spin::Mutex;
+use x86::controlregs;
+
+use super::paging::{DirectMapMemory, InactivePageTable, Mapper, PhysicalAddress};
+use super::vma::VmaSet;
+use super::{USER_SPACE_END, USER_SPACE_START};
+
+use kernel::kget;
+use schedule::bottom_half::BottomHalf;
+
+/// A virtual address space for one or more tasks.
+///
+/// The kernel half is shared with every other address space, the lower half is private. The
+/// ranges in use in the lower half are recorded as `Vma`s.
+pub struct AddressSpace {
+    inner: Mutex<Inner>,
+    p4_address: PhysicalAddress,
+}
+
+struct Inner {
+    table: InactivePageTable,
+    vmas: VmaSet,
+}
+
+impl AddressSpace {
+    /// Construct a new `AddressSpace` using page table `table`.
+    ///
+    /// `table` must share the kernel half, see `InactivePageTable::new_sharing_kernel`.
+    pub fn new(table: InactivePageTable) -> AddressSpace {
+        let p4_address = table.p4_address();
+
+        AddressSpace {
+            inner: Mutex::new(Inner {
+                table: table,
+                vmas: VmaSet::new(USER_SPACE_START, USER_SPACE_END),
+            }),
+            p4_address: p4_address,
+        }
+    }
+
+    /// Returns the physical address of the P4 table, as loaded into CR3
+    pub fn p4_address(&self) -> PhysicalAddress {
+        self.p4_address
+    }
+
+    /// Call `f` with a `Mapper` and the `Vma`s of this address space, which need not be active
+    pub fn with<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut Mapper<DirectMapMemory>, &mut VmaSet) -> R,
+    {
+        let mut inner = self.inner.lock();
+        let inner = &mut *inner;
+        f(&mut inner.table.mapper(), &mut inner.vmas)
+    }
+}
+
+impl fmt::Debug for AddressSpace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "AddressSpace {{ p4_address: {:#x} }}", self.p4_address)
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        ktrace!("AddressSpace::Drop {:#x}", self.p4_address);
+
+        assert!(
+            active_p4_address() != self.p4_address,
+            "dropping the active address space"
+        );
+
+        let mm = unsafe { &mut *kget().memory_manager.get() };
+        let mut inner = self.inner.lock();
+        let inner = &mut *inner;
+        mm.destroy_address_space(&mut inner.table, &mut inner.vmas);
+    }
+}
+
+/// Drops a reference to an address space from the bottomhalfd task.
+///
+/// Dropping the last reference frees the page tables through the `MemoryManager`, which a task
+/// interrupted by the scheduler may be in the middle of using. The scheduler leaves the address
+/// spaces of COMPLETED tasks here rather than dropping them itself.
+pub struct AddressSpaceRelease {
+    address_space: Option<Arc<AddressSpace>>,
+}
+
+impl AddressSpaceRelease {
+    pub fn new(address_space: Arc<AddressSpace>) -> AddressSpaceRelease {
+        AddressSpaceRelease {
+            address_space: Some(address_space),
+        }
+    }
+}
+
+impl BottomHalf for AddressSpaceRelease {
+    fn execute(&mut self) {
+        self.address_space.take();
+    }
+}
+
+/// Returns the physical address of the active P4 table
+pub fn active_p4_address() -> PhysicalAddress {
+    unsafe { controlregs::cr3() as usize }
+}
+
+/// Make the P4 table at `p4_address` active.
+///
+/// Does nothing if it is already active, avoiding an unnecessary TLB flush.
+///
+/// # Safety
+/// The table must map the kernel half, including the current stack.
+pub unsafe fn switch_to(p4_address: PhysicalAddress) {
+    if active_p4_address() != p4_address {
+        controlregs::cr3_write(p4_address as u64);
+    }
+}
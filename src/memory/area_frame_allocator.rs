@@ -1,6 +1,15 @@
-use memory::{Frame, FrameAllocator};
+use memory::{self, Frame, FrameAllocator, SharedFrameAllocator};
 use multiboot2::{MemoryArea, MemoryAreaIter};
 
+use alloc::btree_map::BTreeMap;
+
+use core::ptr;
+
+/// Stored at the start of each frame on the free list
+struct FreeFrame {
+    next: Option<Frame>,
+}
+
 pub struct AreaFrameAllocator {
     next_free_frame: Frame,
     current_area: Option<&'static MemoryArea>,
@@ -9,10 +18,21 @@ pub struct AreaFrameAllocator {
     kernel_end: Frame,
     multiboot_start: Frame,
     multiboot_end: Frame,
+    free_frames: Option<Frame>,
+    /// Reference counts of frames with more than one reference, keyed by frame number. Created
+    /// when first needed as the allocator is constructed before the heap is available.
+    shared_frames: Option<BTreeMap<usize, usize>>,
 }
 
 impl FrameAllocator for AreaFrameAllocator {
     fn allocate_frame(&mut self) -> Option<Frame> {
+        // Reuse deallocated frames first
+        if let Some(frame) = self.free_frames.take() {
+            let free_frame = memory::phys_to_virt(frame.start_address()) as *const FreeFrame;
+            self.free_frames = unsafe { ptr::read(free_frame) }.next;
+            return Some(frame);
+        }
+
         if let Some(area) = self.current_area {
             // "Clone" the frame to return it if it's free. Frame doesn't
             // implement Clone, but we can construct an identical frame.
@@ -51,8 +71,49 @@ impl FrameAllocator for AreaFrameAllocator {
         }
     }
 
-    fn deallocate_frame(&mut self, _: Frame) {
-        unimplemented!()
+    /// Push `frame` onto the free list, or drop one reference if it is shared.
+    ///
+    /// The list is linked through the freed frames themselves, accessed through the direct map, so
+    /// frames must not be deallocated until it has been set up by `remap_the_kernel`.
+    fn deallocate_frame(&mut self, frame: Frame) {
+        let refs = self.frame_refs(&frame);
+        if refs > 1 {
+            let shared_frames = self.shared_frames.as_mut().unwrap();
+            if refs == 2 {
+                shared_frames.remove(&frame.number);
+            } else {
+                shared_frames.insert(frame.number, refs - 1);
+            }
+            return;
+        }
+
+        let free_frame = memory::phys_to_virt(frame.start_address()) as *mut FreeFrame;
+        unsafe {
+            ptr::write(
+                free_frame,
+                FreeFrame {
+                    next: self.free_frames.take(),
+                },
+            );
+        }
+        self.free_frames = Some(frame);
+    }
+}
+
+impl SharedFrameAllocator for AreaFrameAllocator {
+    fn share_frame(&mut self, frame: &Frame) {
+        let refs = self.frame_refs(frame);
+        self.shared_frames
+            .get_or_insert_with(BTreeMap::new)
+            .insert(frame.number, refs + 1);
+    }
+
+    fn frame_refs(&self, frame: &Frame) -> usize {
+        self.shared_frames
+            .as_ref()
+            .and_then(|shared_frames| shared_frames.get(&frame.number))
+            .cloned()
+            .unwrap_or(1)
     }
 }
 
@@ -72,6 +133,8 @@ impl AreaFrameAllocator {
             kernel_end: Frame::containing_address(kernel_end),
             multiboot_start: Frame::containing_address(multiboot_start),
             multiboot_end: Frame::containing_address(multiboot_end),
+            free_frames: None,
+            shared_frames: None,
         };
 
         allocator.choose_next_area();
@@ -1,4 +1,10 @@
-use super::paging::ActivePageTable;
+use alloc::Vec;
+
+use x86_64::structures::idt::PageFaultErrorCode;
+
+use super::paging::{ActivePageTable, InactivePageTable, VirtualAddress};
+use super::vma::{self, VmaError, VmaFlags, VmaSet};
+use super::{AddressSpace, FrameAllocator, PAGE_SIZE};
 
 use super::area_frame_allocator::AreaFrameAllocator;
 
@@ -10,19 +16,24 @@ pub struct MemoryManager {
     frame_allocator: AreaFrameAllocator,
     active_table: ActivePageTable,
     stack_allocator: StackAllocator,
+    kernel_vmas: VmaSet,
 }
 
 impl MemoryManager {
     /// Constructs a new `MemoryManager`
+    ///
+    /// `kernel_vmas` records the dynamic kernel mappings, see `KERNEL_SPACE_START`.
     pub fn new(
         frame_allocator: AreaFrameAllocator,
         active_table: ActivePageTable,
         stack_allocator: StackAllocator,
+        kernel_vmas: VmaSet,
     ) -> MemoryManager {
         MemoryManager {
             frame_allocator: frame_allocator,
             active_table: active_table,
             stack_allocator: stack_allocator,
+            kernel_vmas: kernel_vmas,
         }
     }
 
@@ -34,8 +45,11 @@ impl MemoryManager {
     /// TODO This is super un-rusty, we should use RAII or something to ensure stacks deallocate
     /// themselves.
     pub fn allocate_stack(&mut self) -> Stack {
-        self.stack_allocator
-            .allocate(&mut self.active_table, &mut self.frame_allocator)
+        self.stack_allocator.allocate(
+            &mut self.active_table,
+            &mut self.kernel_vmas,
+            &mut self.frame_allocator,
+        )
     }
 
     /// Deallocate a kernel stack.
@@ -44,4 +58,199 @@ impl MemoryManager {
     pub fn deallocate_stack(&mut self, stack: &Stack) {
         self.stack_allocator.deallocate(stack);
     }
+
+    /// Map `size` bytes of anonymous memory at free addresses in the kernel region, returning the
+    /// start address.
+    ///
+    /// `size` is rounded up to whole pages, which are zeroed. With `LAZY` pages are mapped when
+    /// first accessed, such memory must not be accessed while the `MemoryManager` is in use.
+    pub fn map_anonymous(
+        &mut self,
+        size: usize,
+        flags: VmaFlags,
+    ) -> Result<VirtualAddress, VmaError> {
+        let size = round_up_to_page(size);
+        let start = self.kernel_vmas.reserve_any(size, flags)?;
+
+        if !flags.contains(vma::LAZY) {
+            vma::populate_range(
+                &self.kernel_vmas,
+                &mut self.active_table,
+                &mut self.frame_allocator,
+                start,
+                size,
+            )?;
+        }
+
+        Ok(start)
+    }
+
+    /// Unmap the `size` bytes at `start` in the kernel region, returning their frames to the
+    /// frame allocator.
+    ///
+    /// `size` is rounded up to whole pages.
+    pub fn unmap(&mut self, start: VirtualAddress, size: usize) -> Result<(), VmaError> {
+        vma::release_range(
+            &mut self.kernel_vmas,
+            &mut self.active_table,
+            &mut self.frame_allocator,
+            start,
+            round_up_to_page(size),
+        )
+    }
+
+    /// Change the flags of the `size` bytes at `start` in the kernel region, which must be
+    /// mapped.
+    ///
+    /// `size` is rounded up to whole pages.
+    pub fn protect(
+        &mut self,
+        start: VirtualAddress,
+        size: usize,
+        flags: VmaFlags,
+    ) -> Result<(), VmaError> {
+        vma::protect_range(
+            &mut self.kernel_vmas,
+            &mut self.active_table,
+            &mut self.frame_allocator,
+            start,
+            round_up_to_page(size),
+            flags,
+        )
+    }
+
+    /// Create a new address space sharing the kernel half of the active one.
+    pub fn new_address_space(&mut self) -> AddressSpace {
+        let frame = self.frame_allocator
+            .allocate_frame()
+            .expect("no frames available");
+        let table = InactivePageTable::new_sharing_kernel(frame, &self.active_table);
+
+        AddressSpace::new(table)
+    }
+
+    /// Create a new address space with a copy of the lower half of `parent`.
+    ///
+    /// The frames mapped by `parent` are shared copy on write, so neither address space sees the
+    /// other's later writes. See `vma::fork`.
+    pub fn fork_address_space(&mut self, parent: &AddressSpace) -> AddressSpace {
+        let child = self.new_address_space();
+
+        {
+            let allocator = &mut self.frame_allocator;
+            parent.with(|parent_mapper, parent_vmas| {
+                child.with(|child_mapper, child_vmas| {
+                    vma::fork(parent_vmas, parent_mapper, child_vmas, child_mapper, allocator)
+                })
+            });
+        }
+
+        child
+    }
+
+    /// Reserve the `size` bytes at `start` in `address_space`.
+    ///
+    /// Nothing is mapped, pages are mapped to zeroed frames when first accessed.
+    pub fn reserve_in(
+        &mut self,
+        address_space: &AddressSpace,
+        start: VirtualAddress,
+        size: usize,
+        flags: VmaFlags,
+    ) -> Result<(), VmaError> {
+        address_space.with(|_, vmas| vmas.reserve(start, size, flags))
+    }
+
+    /// Reserve the `size` bytes at `start` in `address_space` and map them to zeroed frames.
+    pub fn map_in(
+        &mut self,
+        address_space: &AddressSpace,
+        start: VirtualAddress,
+        size: usize,
+        flags: VmaFlags,
+    ) -> Result<(), VmaError> {
+        let allocator = &mut self.frame_allocator;
+        address_space.with(|mapper, vmas| {
+            vma::map_range(vmas, mapper, allocator, start, size, flags)
+        })
+    }
+
+    /// Change the flags of the `size` bytes at `start` in `address_space`, which must be reserved.
+    pub fn protect_in(
+        &mut self,
+        address_space: &AddressSpace,
+        start: VirtualAddress,
+        size: usize,
+        flags: VmaFlags,
+    ) -> Result<(), VmaError> {
+        let allocator = &mut self.frame_allocator;
+        address_space.with(|mapper, vmas| {
+            vma::protect_range(vmas, mapper, allocator, start, size, flags)
+        })
+    }
+
+    /// Release the `size` bytes at `start` in `address_space`, freeing the frames mapped there.
+    pub fn release_in(
+        &mut self,
+        address_space: &AddressSpace,
+        start: VirtualAddress,
+        size: usize,
+    ) -> Result<(), VmaError> {
+        let allocator = &mut self.frame_allocator;
+        address_space.with(|mapper, vmas| {
+            vma::release_range(vmas, mapper, allocator, start, size)
+        })
+    }
+
+    /// Release every `Vma` in `vmas` then free the frames used by `table`, see
+    /// `InactivePageTable::deallocate`.
+    pub fn destroy_address_space(&mut self, table: &mut InactivePageTable, vmas: &mut VmaSet) {
+        let ranges: Vec<(VirtualAddress, usize)> =
+            vmas.iter().map(|vma| (vma.start(), vma.size())).collect();
+
+        for (start, size) in ranges {
+            vma::release_range(vmas, &mut table.mapper(), &mut self.frame_allocator, start, size)
+                .unwrap();
+        }
+
+        table.deallocate(&mut self.frame_allocator);
+    }
+
+    /// Try to resolve a page fault at `address` in the kernel region, see `vma::handle_fault`.
+    pub fn handle_kernel_fault(
+        &mut self,
+        address: VirtualAddress,
+        error_code: PageFaultErrorCode,
+    ) -> bool {
+        vma::handle_fault(
+            &mut self.kernel_vmas,
+            &mut self.active_table,
+            &mut self.frame_allocator,
+            address,
+            error_code,
+        )
+    }
+
+    /// Try to resolve a page fault at `address` in the lower half of `address_space`, which must
+    /// be active.
+    pub fn handle_user_fault(
+        &mut self,
+        address_space: &AddressSpace,
+        address: VirtualAddress,
+        error_code: PageFaultErrorCode,
+    ) -> bool {
+        let allocator = &mut self.frame_allocator;
+        address_space.with(|mapper, vmas| {
+            vma::handle_fault(vmas, mapper, allocator, address, error_code)
+        })
+    }
+
+    /// Returns the `Vma`s of the kernel region
+    pub fn kernel_vmas(&self) -> &VmaSet {
+        &self.kernel_vmas
+    }
+}
+
+fn round_up_to_page(size: usize) -> usize {
+    (size + PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE
 }
@@ -1,22 +1,75 @@
-mod paging;
+pub mod paging;
+pub mod vma;
 mod memory_manager;
 mod stack_allocator;
 mod area_frame_allocator;
+mod address_space;
 
 pub use self::memory_manager::MemoryManager;
 pub use self::stack_allocator::Stack;
+pub use self::address_space::{active_p4_address, switch_to, AddressSpace, AddressSpaceRelease};
+
+pub use self::paging::{Frame, FrameAllocator, PAGE_SIZE};
 
 use self::paging::Page;
-use self::paging::PhysicalAddress;
+use self::paging::{PhysicalAddress, VirtualAddress};
 use self::area_frame_allocator::AreaFrameAllocator;
 use self::stack_allocator::StackAllocator;
+use self::vma::{VmaFlags, VmaSet};
 
 use multiboot2;
+use x86_64::structures::idt::PageFaultErrorCode;
+use elf;
+use kernel;
+
+/// A `FrameAllocator` counting the references to frames shared between address spaces.
+///
+/// Deallocating a shared frame drops one reference, it is only freed once no references remain.
+pub trait SharedFrameAllocator: FrameAllocator {
+    /// Add a reference to the allocated `frame`
+    fn share_frame(&mut self, frame: &Frame);
+
+    /// Returns the number of references to the allocated `frame`
+    fn frame_refs(&self, frame: &Frame) -> usize;
+}
 
-pub const KERN_HEAP_START: usize = 0o_000_001_000_000_0000;
+/// Offset of the kernel's virtual addresses from its physical load address, see `linker.ld`.
+///
+/// The VGA buffer, multiboot information and symbol tables are also mapped at this offset from
+/// their physical addresses.
+pub const KERNEL_OFFSET: usize = 0xffff_ff00_0000_0000;
+
+/// Start of the lower half of the address space, private to each `AddressSpace`. The first page
+/// is never mapped so null pointer dereferences fault.
+pub const USER_SPACE_START: usize = PAGE_SIZE;
+
+/// End of the lower half of the address space
+pub const USER_SPACE_END: usize = 0x0000_8000_0000_0000;
+
+/// Start of the region holding the kernel heap, stacks and other dynamic kernel mappings.
+///
+/// The region is a single P4 entry, the ranges in use are recorded by the kernel `VmaSet`.
+pub const KERNEL_SPACE_START: usize = 0xffff_fe80_0000_0000;
+
+/// End of the dynamic kernel region, the kernel itself is mapped above
+pub const KERNEL_SPACE_END: usize = KERNEL_OFFSET;
+
+pub const KERN_HEAP_START: usize = KERNEL_SPACE_START;
 pub const KERN_HEAP_SIZE: usize = 100 * 1024; // 100 Kb
 
-/// Initialises kernel memory using the multiboot header at `multiboot_info_address`
+/// Page used by `remap_the_kernel` to edit page tables before the direct map exists
+pub const TEMPORARY_PAGE_ADDRESS: usize = KERNEL_SPACE_END - PAGE_SIZE;
+
+/// Start of the direct map, all usable physical memory is mapped linearly from here
+pub const PHYSICAL_MEMORY_OFFSET: usize = 0xffff_8000_0000_0000;
+
+/// Maximum size of the direct map, a single P4 entry
+pub const PHYSICAL_MEMORY_MAX_SIZE: usize = 512 * 1024 * 1024 * 1024; // 512 Gb
+
+/// Initialises kernel memory using the multiboot header at `multiboot_info_address`, the
+/// higher half address of the multiboot information passed by `long_mode.asm`
+///
+/// The heap allocator is initialised, so the heap may be used once this returns.
 ///
 /// # Safety
 /// Only ONE `MemoryManager` object should ever be instantiated for the lifetime of the kernel.
@@ -30,29 +83,45 @@ pub fn init(multiboot_info_address: usize) -> MemoryManager {
         .elf_sections_tag()
         .expect("Elf sections tag required");
 
+    // The symbol and string tables are not allocated sections but are still loaded by the
+    // bootloader. Include them in the kernel range so their frames are never handed out.
+    let section_headers = elf::section_headers(boot_info);
+
     let kernel_start = elf_sections_tag
         .sections()
         .filter(|s| s.is_allocated())
-        .map(|s| s.addr)
+        .map(|s| kernel_to_phys(s.addr as usize) as u64)
+        .chain(
+            section_headers
+                .iter()
+                .filter(|s| s.is_symbol_data())
+                .map(|s| s.addr),
+        )
         .min()
         .unwrap();
 
     let kernel_end = elf_sections_tag
         .sections()
         .filter(|s| s.is_allocated())
-        .map(|s| s.addr + s.size)
+        .map(|s| kernel_to_phys(s.addr as usize) as u64 + s.size)
+        .chain(
+            section_headers
+                .iter()
+                .filter(|s| s.is_symbol_data())
+                .map(|s| s.addr + s.size),
+        )
         .max()
         .unwrap();
 
-    let multiboot_start = multiboot_info_address;
+    let multiboot_start = kernel_to_phys(multiboot_info_address);
     let multiboot_end = multiboot_start + (boot_info.total_size as usize);
 
-    kprintln!(
+    kinfo!(
         "kernel start: 0x{:x}, kernel end: 0x{:x}",
         kernel_start,
         kernel_end
     );
-    kprintln!(
+    kinfo!(
         "multiboot start: 0x{:x}, multiboot end: 0x{:x}",
         multiboot_start,
         multiboot_end
@@ -75,68 +144,98 @@ pub fn init(multiboot_info_address: usize) -> MemoryManager {
         active_table.map(page, paging::WRITABLE, &mut frame_allocator);
     }
 
-    let next_page = heap_end_page.next_page();
+    unsafe {
+        ::ALLOCATOR.init(KERN_HEAP_START, KERN_HEAP_SIZE);
+    }
+
+    // Now the heap is available record the ranges of the kernel region already in use
+    let mut kernel_vmas = VmaSet::new(KERNEL_SPACE_START, KERNEL_SPACE_END);
+    kernel_vmas
+        .reserve(KERN_HEAP_START, KERN_HEAP_SIZE, vma::READ | vma::WRITE)
+        .unwrap();
+    kernel_vmas
+        .reserve(TEMPORARY_PAGE_ADDRESS, PAGE_SIZE, VmaFlags::empty())
+        .unwrap();
 
     MemoryManager::new(
         frame_allocator,
         active_table,
-        StackAllocator::new(next_page),
+        StackAllocator::new(),
+        kernel_vmas,
     )
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
-pub struct Frame {
-    number: usize,
-}
-
-pub const PAGE_SIZE: usize = 4096;
+/// Try to resolve a page fault at `address`, returning `false` if the fault is an error.
+///
+/// Faults in the dynamic kernel region are resolved using the kernel `Vma`s, faults in the lower
+/// half using the `Vma`s of the active task's address space.
+pub fn handle_page_fault(address: VirtualAddress, error_code: PageFaultErrorCode) -> bool {
+    let kernel = match kernel::try_kget() {
+        Some(k) => k,
+        None => return false,
+    };
+    let mm = unsafe { &mut *kernel.memory_manager.get() };
+
+    if address >= KERNEL_SPACE_START && address < KERNEL_SPACE_END {
+        return mm.handle_kernel_fault(address, error_code);
+    }
 
-impl Frame {
-    // TODO change to from_address?
-    fn containing_address(address: usize) -> Frame {
-        Frame {
-            number: address / PAGE_SIZE,
+    if address < USER_SPACE_END {
+        let scheduler = unsafe { &*kernel.scheduler.get() };
+        let task = scheduler.get_active_task();
+        if let Some(address_space) = task.and_then(|t| t.address_space()) {
+            return mm.handle_user_fault(address_space, address, error_code);
         }
     }
 
-    fn start_address(&self) -> PhysicalAddress {
-        self.number * PAGE_SIZE
-    }
+    false
+}
 
-    fn clone(&self) -> Frame {
-        Frame {
-            number: self.number,
-        }
+/// Translate virtual address `address` to a physical address using the active page table.
+///
+/// Returns `None` if `address` is not canonical or is not mapped.
+pub fn translate(address: usize) -> Option<PhysicalAddress> {
+    if address >= 0x0000_8000_0000_0000 && address < 0xffff_8000_0000_0000 {
+        return None;
     }
 
-    fn range_inclusive(start: Frame, end: Frame) -> FrameIter {
-        FrameIter {
-            start: start,
-            end: end,
-        }
-    }
+    unsafe { paging::Mapper::new(paging::RecursiveMemory) }.translate(address)
 }
 
-pub trait FrameAllocator {
-    fn allocate_frame(&mut self) -> Option<Frame>;
-    fn deallocate_frame(&mut self, frame: Frame);
+/// Returns the physical address of `address` in the kernel mapping (see `KERNEL_OFFSET`).
+///
+/// The boot code is linked at its physical address so addresses below `KERNEL_OFFSET` are
+/// returned unchanged.
+pub fn kernel_to_phys(address: VirtualAddress) -> PhysicalAddress {
+    if address >= KERNEL_OFFSET {
+        address - KERNEL_OFFSET
+    } else {
+        address
+    }
 }
 
-struct FrameIter {
-    start: Frame,
-    end: Frame,
+/// Returns the address of physical address `address` in the direct map.
+///
+/// Only usable RAM is mapped, `address` must not refer to a memory hole or device memory.
+pub fn phys_to_virt(address: PhysicalAddress) -> VirtualAddress {
+    assert!(
+        address < PHYSICAL_MEMORY_MAX_SIZE,
+        "physical address {:#x} outside the direct map",
+        address
+    );
+    PHYSICAL_MEMORY_OFFSET + address
 }
 
-impl Iterator for FrameIter {
-    type Item = Frame;
-
-    fn next(&mut self) -> Option<Frame> {
-        if self.start <= self.end {
-            let frame = self.start.clone();
-            self.start.number += 1;
-            Some(frame)
-        } else {
-            None
-        }
+/// Translate virtual address `address` to a physical address.
+///
+/// Addresses in the direct map are converted without walking the page tables, so are not checked
+/// to be mapped. Other addresses are translated as in `translate`.
+pub fn virt_to_phys(address: VirtualAddress) -> Option<PhysicalAddress> {
+    if address >= PHYSICAL_MEMORY_OFFSET
+        && address < PHYSICAL_MEMORY_OFFSET + PHYSICAL_MEMORY_MAX_SIZE
+    {
+        Some(address - PHYSICAL_MEMORY_OFFSET)
+    } else {
+        translate(address)
     }
 }
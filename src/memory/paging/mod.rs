@@ -1,89 +1,72 @@
-mod entry;
-mod table;
-mod mapper;
 mod temporary_page;
 
-pub use self::entry::*;
-pub use self::mapper::Mapper;
+pub use paging_opsys::*;
 use self::temporary_page::TemporaryPage;
-use multiboot2::BootInformation;
-use memory::{Frame, FrameAllocator, PAGE_SIZE};
+use multiboot2::{BootInformation, ElfSection};
+use memory;
+use elf;
+use cpu;
+use x86;
 
 use core::ops::{Deref, DerefMut};
 
-pub type PhysicalAddress = usize;
-pub type VirtualAddress = usize;
+/// Page table access through the recursive mapping in the last entry of the active P4 table
+pub struct RecursiveMemory;
 
-const ENTRY_COUNT: usize = 512;
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-pub struct Page {
-    number: usize,
-}
-
-impl Page {
-    pub fn containing_address(address: VirtualAddress) -> Page {
-        assert!(
-            address < 0x0000_8000_0000_0000 || address >= 0xffff_8000_0000_0000,
-            "invalid address: 0x{:x}",
-            address
-        );
-        Page {
-            number: address / PAGE_SIZE,
-        }
+impl PageTableMemory for RecursiveMemory {
+    fn p4(&self) -> *mut Table<Level4> {
+        0xffffffff_fffff000 as *mut _
     }
 
-    pub fn range_inclusive(start: Page, end: Page) -> PageIter {
-        PageIter {
-            start: start,
-            end: end,
-        }
+    fn next_table_address(&self, table_address: usize, index: usize, _: &Frame) -> usize {
+        (table_address << 9) | (index << 12)
     }
 
-    pub fn next_page(&self) -> Page {
-        Page {
-            number: self.number + 1,
-        }
-    }
-
-    pub fn start_address(&self) -> usize {
-        self.number * PAGE_SIZE
+    fn flush(&self, address: VirtualAddress) {
+        unsafe { x86::tlb::flush(address) };
     }
+}
 
-    fn p4_index(&self) -> usize {
-        (self.number >> 27) & 0o777
-    }
+/// Page table access through the direct map, allowing tables that are not active to be modified
+pub struct DirectMapMemory {
+    p4_address: PhysicalAddress,
+}
 
-    fn p3_index(&self) -> usize {
-        (self.number >> 18) & 0o777
+impl PageTableMemory for DirectMapMemory {
+    fn p4(&self) -> *mut Table<Level4> {
+        memory::phys_to_virt(self.p4_address) as *mut _
     }
 
-    fn p2_index(&self) -> usize {
-        (self.number >> 9) & 0o777
+    fn next_table_address(&self, _: usize, _: usize, frame: &Frame) -> usize {
+        memory::phys_to_virt(frame.start_address())
     }
 
-    fn p1_index(&self) -> usize {
-        (self.number >> 0) & 0o777
+    fn flush(&self, address: VirtualAddress) {
+        // Only the active table's translations can be cached
+        if unsafe { x86::controlregs::cr3() } as usize == self.p4_address {
+            unsafe { x86::tlb::flush(address) };
+        }
     }
 }
 
-pub struct PageIter {
-    start: Page,
-    end: Page,
-}
+/// Returns the page table flags required to map the ELF `section`
+pub fn flags_from_elf_section(section: &ElfSection) -> EntryFlags {
+    use multiboot2::{ELF_SECTION_ALLOCATED, ELF_SECTION_EXECUTABLE, ELF_SECTION_WRITABLE};
 
-impl Iterator for PageIter {
-    type Item = Page;
+    let mut flags = EntryFlags::empty();
 
-    fn next(&mut self) -> Option<Page> {
-        if self.start <= self.end {
-            let page = self.start;
-            self.start.number += 1;
-            Some(page)
-        } else {
-            None
-        }
+    if section.flags().contains(ELF_SECTION_ALLOCATED) {
+        // section is loaded to memory
+        flags = flags | PRESENT;
     }
+    if section.flags().contains(ELF_SECTION_WRITABLE) {
+        flags = flags | WRITABLE;
+    }
+    if !section.flags().contains(ELF_SECTION_EXECUTABLE) {
+        flags = flags | NO_EXECUTE;
+    }
+
+    flags
 }
 
 pub struct InactivePageTable {
@@ -108,22 +91,100 @@ impl InactivePageTable {
         temporary_page.unmap(active_table);
         InactivePageTable { p4_frame: frame }
     }
+
+    /// Create a new table in `frame` with an empty lower half, sharing the kernel half of
+    /// `active_table`.
+    ///
+    /// The kernel half P4 entries are copied. Each points to a P3 table created by
+    /// `remap_the_kernel` and never freed, so kernel mappings made later are shared too. The table
+    /// is accessed through the direct map.
+    pub fn new_sharing_kernel(frame: Frame, active_table: &ActivePageTable) -> InactivePageTable {
+        {
+            let address = memory::phys_to_virt(frame.start_address());
+            let table = unsafe { &mut *(address as *mut Table<Level4>) };
+            table.zero();
+
+            let kernel_table = active_table.p4();
+            for i in KERNEL_P4_START..511 {
+                if let Some(p3_frame) = kernel_table[i].pointed_frame() {
+                    table[i].set(p3_frame, kernel_table[i].flags());
+                    table.increment_entry_count();
+                }
+            }
+
+            // set up recursive mapping for the table
+            table[511].set(frame.clone(), PRESENT | WRITABLE);
+        }
+
+        InactivePageTable { p4_frame: frame }
+    }
+
+    /// Returns the physical address of the P4 table, as loaded into CR3
+    pub fn p4_address(&self) -> PhysicalAddress {
+        self.p4_frame.start_address()
+    }
+
+    /// Returns a `Mapper` modifying this table through the direct map.
+    ///
+    /// The table must have been created with `new_sharing_kernel`, or otherwise be reachable
+    /// through the direct map.
+    pub fn mapper(&mut self) -> Mapper<DirectMapMemory> {
+        unsafe {
+            Mapper::new(DirectMapMemory {
+                p4_address: self.p4_address(),
+            })
+        }
+    }
+
+    /// Return the lower half page tables and the P4 table to `allocator`.
+    ///
+    /// The frames mapped by the lower half are not freed, they belong to whoever mapped them. The
+    /// table must not be active, and must not be used again afterwards.
+    pub fn deallocate<A>(&mut self, allocator: &mut A)
+    where
+        A: FrameAllocator,
+    {
+        let memory = DirectMapMemory {
+            p4_address: self.p4_address(),
+        };
+        let p4 = unsafe { &mut *memory.p4() };
+
+        for i in 0..KERNEL_P4_START {
+            let p3_frame = p4[i].pointed_frame();
+            if let Some(p3) = p4.next_table_mut(i, &memory) {
+                for j in 0..ENTRY_COUNT {
+                    let p2_frame = p3[j].pointed_frame();
+                    if let Some(p2) = p3.next_table_mut(j, &memory) {
+                        for k in 0..ENTRY_COUNT {
+                            if p2.next_table(k, &memory).is_some() {
+                                allocator.deallocate_frame(p2[k].pointed_frame().unwrap());
+                            }
+                        }
+                        allocator.deallocate_frame(p2_frame.unwrap());
+                    }
+                }
+                allocator.deallocate_frame(p3_frame.unwrap());
+            }
+        }
+
+        allocator.deallocate_frame(self.p4_frame.clone());
+    }
 }
 
 pub struct ActivePageTable {
-    mapper: Mapper,
+    mapper: Mapper<RecursiveMemory>,
 }
 
 impl Deref for ActivePageTable {
-    type Target = Mapper;
+    type Target = Mapper<RecursiveMemory>;
 
-    fn deref(&self) -> &Mapper {
+    fn deref(&self) -> &Mapper<RecursiveMemory> {
         &self.mapper
     }
 }
 
 impl DerefMut for ActivePageTable {
-    fn deref_mut(&mut self) -> &mut Mapper {
+    fn deref_mut(&mut self) -> &mut Mapper<RecursiveMemory> {
         &mut self.mapper
     }
 }
@@ -131,7 +192,7 @@ impl DerefMut for ActivePageTable {
 impl ActivePageTable {
     unsafe fn new() -> ActivePageTable {
         ActivePageTable {
-            mapper: Mapper::new(),
+            mapper: Mapper::new(RecursiveMemory),
         }
     }
 
@@ -141,7 +202,7 @@ impl ActivePageTable {
         temporary_page: &mut temporary_page::TemporaryPage,
         f: F,
     ) where
-        F: FnOnce(&mut Mapper),
+        F: FnOnce(&mut Mapper<RecursiveMemory>),
     {
         use x86::{controlregs, tlb};
         let flush_tlb = || unsafe { tlb::flush_all() };
@@ -186,7 +247,8 @@ pub fn remap_the_kernel<A>(allocator: &mut A, boot_info: &BootInformation) -> Ac
 where
     A: FrameAllocator,
 {
-    let mut temporary_page = TemporaryPage::new(Page { number: 0xcafebabe }, allocator);
+    let temporary_page = Page::containing_address(memory::TEMPORARY_PAGE_ADDRESS);
+    let mut temporary_page = TemporaryPage::new(temporary_page, allocator);
 
     let mut active_table = unsafe { ActivePageTable::new() };
     let mut new_table = {
@@ -195,7 +257,7 @@ where
     };
 
     active_table.with(&mut new_table, &mut temporary_page, |mapper| {
-        // Identity map the kernel sections
+        // Map the kernel sections at their higher half addresses
         let elf_sections_tag = boot_info
             .elf_sections_tag()
             .expect("Memory map tag required");
@@ -206,7 +268,12 @@ where
                 continue;
             }
 
-            kprintln!(
+            if (section.addr as usize) < memory::KERNEL_OFFSET {
+                // the boot code is only used until the jump to the higher half
+                continue;
+            }
+
+            kdebug!(
                 "mapping section at addr: {:#x}, size: {}",
                 section.addr,
                 section.size
@@ -217,34 +284,143 @@ where
                 "sections need to be page aligned"
             );
 
-            let flags = EntryFlags::from_elf_section_flags(section);
+            let flags = flags_from_elf_section(section);
 
-            let start_frame = Frame::containing_address(section.start_address());
-            let end_frame = Frame::containing_address(section.end_address() - 1);
+            let start_address = memory::kernel_to_phys(section.start_address());
+            let end_address = memory::kernel_to_phys(section.end_address() - 1);
+            let start_frame = Frame::containing_address(start_address);
+            let end_frame = Frame::containing_address(end_address);
             for frame in Frame::range_inclusive(start_frame, end_frame) {
-                mapper.identity_map(frame, flags, allocator);
+                kernel_map(mapper, frame, flags, allocator);
             }
         }
 
-        // Identity map the VGA text buffer
+        // Map the VGA text buffer
         let vga_buffer_frame = Frame::containing_address(0xb8000);
-        mapper.identity_map(vga_buffer_frame, WRITABLE, allocator);
+        kernel_map(mapper, vga_buffer_frame, WRITABLE, allocator);
 
-        // Identity map the multiboot info structure
-        let multiboot_start = Frame::containing_address(boot_info.start_address());
-        let multiboot_end = Frame::containing_address(boot_info.end_address() - 1);
+        // Map the multiboot info structure
+        let multiboot_start =
+            Frame::containing_address(memory::kernel_to_phys(boot_info.start_address()));
+        let multiboot_end =
+            Frame::containing_address(memory::kernel_to_phys(boot_info.end_address() - 1));
         for frame in Frame::range_inclusive(multiboot_start, multiboot_end) {
-            mapper.identity_map(frame, PRESENT, allocator);
+            kernel_map(mapper, frame, PRESENT, allocator);
+        }
+
+        // Map the symbol and string tables so addresses can be resolved to symbol names. These
+        // are not page aligned so may share frames with each other or the multiboot info.
+        for section in elf::section_headers(boot_info) {
+            if !section.is_symbol_data() {
+                continue;
+            }
+
+            let start_frame = Frame::containing_address(section.start_address());
+            let end_frame = Frame::containing_address(section.end_address() - 1);
+            for frame in Frame::range_inclusive(start_frame, end_frame) {
+                let page = Page::containing_address(memory::KERNEL_OFFSET + frame.start_address());
+                if mapper.translate_page(page).is_none() {
+                    kernel_map(mapper, frame, PRESENT | NO_EXECUTE, allocator);
+                }
+            }
+        }
+
+        map_physical_memory(mapper, boot_info, allocator);
+
+        // Give every kernel half P4 entry a P3 table, so the entries copied into new address
+        // spaces never change
+        let p4 = mapper.p4_mut();
+        for i in KERNEL_P4_START..511 {
+            p4.next_table_create(i, allocator, &RecursiveMemory);
         }
     });
 
     let old_table = active_table.switch(new_table);
-    kprintln!("Success, we have switched to the new table :)");
+    kinfo!("Success, we have switched to the new table :)");
 
     // Add a guard page (the old p4 page)
-    let old_p4_page = Page::containing_address(old_table.p4_frame.start_address());
+    let old_p4_page =
+        Page::containing_address(memory::KERNEL_OFFSET + old_table.p4_frame.start_address());
     active_table.unmap(old_p4_page, allocator);
-    kprintln!("guard page at {:#x}", old_p4_page.start_address());
+    kdebug!("guard page at {:#x}", old_p4_page.start_address());
 
     active_table
 }
+
+/// Map `frame` at `KERNEL_OFFSET` from its physical address
+fn kernel_map<A>(
+    mapper: &mut Mapper<RecursiveMemory>,
+    frame: Frame,
+    flags: EntryFlags,
+    allocator: &mut A,
+) where
+    A: FrameAllocator,
+{
+    let page = Page::containing_address(memory::KERNEL_OFFSET + frame.start_address());
+    mapper.map_to(page, frame, flags, allocator)
+}
+
+/// Map all usable physical memory at `PHYSICAL_MEMORY_OFFSET` using the largest pages possible
+fn map_physical_memory<A>(
+    mapper: &mut Mapper<RecursiveMemory>,
+    boot_info: &BootInformation,
+    allocator: &mut A,
+) where
+    A: FrameAllocator,
+{
+    let memory_map_tag = boot_info.memory_map_tag().expect("Memory map tag required");
+    let huge_1g = cpu::has_1g_pages();
+
+    // The memory areas are not sorted and may share frames at their edges. Map them in address
+    // order, starting each from the first frame not already mapped.
+    let mut next_frame = Frame::containing_address(0);
+    let mut mapped = 0;
+
+    loop {
+        let area = memory_map_tag
+            .memory_areas()
+            .filter(|area| {
+                let address = area.base_addr + area.length - 1;
+                Frame::containing_address(address as usize) >= next_frame
+            })
+            .min_by_key(|area| area.base_addr);
+
+        let area = match area {
+            Some(area) => area,
+            None => break,
+        };
+
+        let mut start_frame = Frame::containing_address(area.base_addr as usize);
+        if start_frame < next_frame {
+            start_frame = next_frame.clone();
+        }
+        let end_frame = Frame::containing_address((area.base_addr + area.length - 1) as usize);
+
+        assert!(
+            end_frame.start_address() < memory::PHYSICAL_MEMORY_MAX_SIZE,
+            "physical memory too large for the direct map"
+        );
+
+        let count = end_frame.number - start_frame.number + 1;
+        let page = Page::containing_address(memory::phys_to_virt(start_frame.start_address()));
+
+        kdebug!(
+            "direct mapping frames {:#x} to {:#x} at {:#x}",
+            start_frame.start_address(),
+            end_frame.start_address() + PAGE_SIZE - 1,
+            page.start_address()
+        );
+
+        mapper.map_linear(page, start_frame, count, WRITABLE | NO_EXECUTE, huge_1g, allocator);
+
+        next_frame = end_frame;
+        next_frame.number += 1;
+        mapped += count;
+    }
+
+    kinfo!(
+        "direct mapped {} MiB of physical memory, 1GiB pages {}",
+        mapped * PAGE_SIZE / (1024 * 1024),
+        if huge_1g { "enabled" } else { "unavailable" }
+    );
+}
@@ -1,7 +1,7 @@
 use super::Page;
 use super::{ActivePageTable, VirtualAddress};
 
-use super::table::{Level1, Table};
+use super::{Level1, Table};
 
 use memory::Frame;
 use memory::FrameAllocator;
@@ -35,7 +35,7 @@ impl TemporaryPage {
     /// Maps the temporary page to the given frame in the active table.
     /// Returns the start address of the temporary page.
     pub fn map(&mut self, frame: Frame, active_table: &mut ActivePageTable) -> VirtualAddress {
-        use super::entry::WRITABLE;
+        use super::WRITABLE;
 
         assert!(
             active_table.translate_page(self.page).is_none(),
@@ -48,7 +48,7 @@ impl TemporaryPage {
 
     /// Unmaps the temporary page in the active table.
     pub fn unmap(&mut self, active_table: &mut ActivePageTable) {
-        active_table.unmap(self.page, &mut self.allocator)
+        active_table.unmap(self.page, &mut self.allocator);
     }
 }
 
@@ -1,13 +1,20 @@
 use alloc::Vec;
 
+use core::slice;
+
 use super::PAGE_SIZE;
-use super::paging;
-use super::paging::Page;
 use super::paging::ActivePageTable;
 use super::area_frame_allocator::AreaFrameAllocator;
+use super::vma::{self, VmaFlags, VmaSet, READ, WRITE};
 
 const DEFAULT_STACK_SIZE_PAGES: u8 = 2;
 
+/// Value new stacks are filled with, words still holding it have never been used
+const STACK_SENTINEL: u64 = 0x5a5a_5a5a_5a5a_5a5a;
+
+/// Value of the lowest word of a stack, a task that overwrites it has overflowed its stack
+const STACK_CANARY: u64 = 0xdead_beef_cafe_f00d;
+
 /// A structre defining memory allocated for use as a kernel stack
 #[derive(Clone, Copy, Debug)]
 pub struct Stack {
@@ -20,69 +27,121 @@ pub struct Stack {
 impl Stack {
     /// Return the address at the top of the `Stack`
     pub fn top(&self) -> usize {
-        self.start_address - self.size
+        self.start_address + self.size
+    }
+
+    /// Returns false if the canary at the bottom of the `Stack` has been overwritten.
+    ///
+    /// An empty `Stack`, such as the boot stack of the idle task, has no canary.
+    pub fn canary_intact(&self) -> bool {
+        if self.size == 0 {
+            return true;
+        }
+
+        unsafe { self.words() }[0] == STACK_CANARY
+    }
+
+    /// Returns the most bytes of the `Stack` that have been used since it was allocated.
+    ///
+    /// Found by searching up from the bottom for the first word that no longer holds the sentinel
+    /// value, so is the whole size if the canary has been overwritten.
+    pub fn high_water_mark(&self) -> usize {
+        if self.size == 0 {
+            return 0;
+        }
+        if !self.canary_intact() {
+            return self.size;
+        }
+
+        let words = unsafe { self.words() };
+        match words.iter().skip(1).position(|&word| word != STACK_SENTINEL) {
+            Some(unused) => self.size - (unused + 1) * 8,
+            None => 0,
+        }
+    }
+
+    /// Fill the `Stack` with the sentinel value, placing the canary in the lowest word
+    fn fill(&self) {
+        let words = unsafe { self.words() };
+        for word in words.iter_mut() {
+            *word = STACK_SENTINEL;
+        }
+
+        if let Some(bottom) = words.first_mut() {
+            *bottom = STACK_CANARY;
+        }
+    }
+
+    /// The memory of the `Stack` as words, the `Stack` must be mapped and not empty
+    unsafe fn words(&self) -> &mut [u64] {
+        slice::from_raw_parts_mut(self.start_address as *mut u64, self.size / 8)
     }
 }
 
 /// Allocator of `Stack` objects.
 ///
 /// The `StackAllocator` manages allocated stacks, maintaining an internal list of both free and
-/// allocated stacks. When allocating a `Stack`, if no free stacks are available a new page will be
-/// mapped.
+/// allocated stacks. When allocating a `Stack`, if no free stacks are available new pages will be
+/// reserved in the kernel `VmaSet` and mapped.
 pub struct StackAllocator {
     /// List of allocated `Stack` objects that have not yet been free'ed
     allocated: Vec<Stack>,
     /// List of free `Stack` objects
     free: Vec<Stack>,
-    /// The next page to allocate
-    next_page: Page,
 }
 
 impl StackAllocator {
     /// Constructs a `StackAllocator` with empty allocated and free lists.
-    pub fn new(starting_page: Page) -> StackAllocator {
+    pub fn new() -> StackAllocator {
         StackAllocator {
             allocated: Vec::new(),
             free: Vec::new(),
-            next_page: starting_page,
         }
     }
 
     /// Allocates a new `Stack`.
     ///
-    /// If no `Stack` is available on the free list DEFAULT_STACK_SIZE_PAGES will be mapped along
-    /// with a further guard page.
+    /// If no `Stack` is available on the free list DEFAULT_STACK_SIZE_PAGES will be mapped above
+    /// a further guard page, which is reserved in `vmas` so nothing else is placed there.
+    ///
+    /// The `Stack` is filled with a sentinel value with a canary at the bottom, see
+    /// `Stack::high_water_mark` and `Stack::canary_intact`.
     pub fn allocate(
         &mut self,
         table: &mut ActivePageTable,
+        vmas: &mut VmaSet,
         allocator: &mut AreaFrameAllocator,
     ) -> Stack {
         // If we have a free stack just return that.
         if !self.free.is_empty() {
             let stack = self.free.pop().unwrap();
+            stack.fill();
             self.allocated.push(stack);
             return stack;
         }
 
-        // Allocate a guard page by skipping to the next page
-        let next_page = self.next_page.next_page();
-        self.next_page = next_page;
-
-        // Allocate the stack pages
-        let start = self.allocate_pages(DEFAULT_STACK_SIZE_PAGES, table, allocator);
         let size = (DEFAULT_STACK_SIZE_PAGES as usize) * PAGE_SIZE;
 
+        // Reserve the guard page and stack together, then make the stack pages accessible
+        let guard = vmas.reserve_any(PAGE_SIZE + size, VmaFlags::empty())
+            .expect("no virtual memory for a kernel stack");
+        let start = guard + PAGE_SIZE;
+
+        vmas.protect(start, size, READ | WRITE).unwrap();
+        vma::populate_range(vmas, table, allocator, start, size).unwrap();
+
         // Store the stack on the allocated list and return a copy
         let stack = Stack {
             start_address: start,
             size: size,
         };
+        stack.fill();
 
         self.allocated.push(stack);
 
-        kprintln!("Count: {} Allocate: {:?}", self.allocated.len(), stack);
+        kdebug!("Count: {} Allocate: {:?}", self.allocated.len(), stack);
         for s in &self.allocated {
-            kprintln!("A: {:?}", s);
+            ktrace!("A: {:?}", s);
         }
 
         stack
@@ -93,7 +152,7 @@ impl StackAllocator {
     /// The `Stack` is found on the allocated list and moved onto the free list.
     pub fn deallocate(&mut self, stack: &Stack) {
         for s in &self.free {
-            kprintln!("Deallocate: {:?}", s);
+            ktrace!("Deallocate: {:?}", s);
         }
 
         let index = self.allocated
@@ -112,36 +171,4 @@ impl StackAllocator {
             ),
         }
     }
-
-    fn allocate_pages(
-        &mut self,
-        num: u8,
-        table: &mut ActivePageTable,
-        allocator: &mut AreaFrameAllocator,
-    ) -> usize {
-        // Allocating zero pages is silly
-        assert!(num != 0);
-
-        let start = self.allocate_page(table, allocator);
-        for _ in 1..num {
-            self.allocate_page(table, allocator);
-        }
-
-        // Return the start address of the page we just mapped
-        start
-    }
-
-    fn allocate_page(
-        &mut self,
-        table: &mut ActivePageTable,
-        allocator: &mut AreaFrameAllocator,
-    ) -> usize {
-        let next_page = self.next_page.next_page();
-        let page = self.next_page;
-        self.next_page = next_page;
-
-        // Map the new page and return the start address
-        table.map(page, paging::WRITABLE, allocator);
-        page.start_address()
-    }
 }
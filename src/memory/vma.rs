@@ -0,0 +1,608 @@
+use alloc::Vec;
+use alloc::btree_map::{BTreeMap, Values};
+
+use core::ptr;
+
+use x86_64::structures::idt::PageFaultErrorCode;
+
+use super::paging::{self, EntryFlags, Mapper, Page, PageTableMemory, VirtualAddress};
+use super::{FrameAllocator, SharedFrameAllocator, PAGE_SIZE};
+
+use memory;
+
+/// Maximum distance below a `GROWS_DOWN` `Vma` at which a fault extends it
+const MAX_GROWTH: usize = 16 * PAGE_SIZE;
+
+bitflags! {
+    /// Access rights and properties of a `Vma`.
+    ///
+    /// A `Vma` without `READ` is only a reservation, every access to it faults. Guard pages are
+    /// reserved this way so nothing else is placed there.
+    pub flags VmaFlags: u32 {
+        const READ =        1 << 0,
+        const WRITE =       1 << 1,
+        const EXEC =        1 << 2,
+        const USER =        1 << 3,
+        /// Backed by a file rather than anonymous zeroed memory
+        const FILE_BACKED = 1 << 4,
+        /// Extended downwards by faults just below its start, as used for stacks
+        const GROWS_DOWN =  1 << 5,
+        /// Mapped on first access rather than when created, see `MemoryManager::map_anonymous`
+        const LAZY =        1 << 6,
+    }
+}
+
+impl VmaFlags {
+    /// Returns the page table flags for a page of a `Vma` with these flags
+    pub fn entry_flags(&self) -> EntryFlags {
+        let mut flags = paging::PRESENT;
+
+        if self.contains(WRITE) {
+            flags = flags | paging::WRITABLE;
+        }
+        if !self.contains(EXEC) {
+            flags = flags | paging::NO_EXECUTE;
+        }
+        if self.contains(USER) {
+            flags = flags | paging::USER_ACCESSIBLE;
+        }
+
+        flags
+    }
+}
+
+/// Errors from operations on a `VmaSet`
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum VmaError {
+    /// The range is empty or not page aligned
+    Unaligned,
+    /// The range is outside the addresses managed by the set
+    OutOfBounds,
+    /// The range overlaps an existing `Vma`
+    Overlap,
+    /// Part of the range is not covered by a `Vma`
+    NotReserved,
+    /// There is no free range large enough
+    NoSpace,
+}
+
+/// A virtual memory area, a page aligned range of addresses with the same flags
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Vma {
+    start: VirtualAddress,
+    end: VirtualAddress,
+    flags: VmaFlags,
+}
+
+impl Vma {
+    /// Returns the first address of the area
+    pub fn start(&self) -> VirtualAddress {
+        self.start
+    }
+
+    /// Returns the address just past the end of the area
+    pub fn end(&self) -> VirtualAddress {
+        self.end
+    }
+
+    pub fn size(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn flags(&self) -> VmaFlags {
+        self.flags
+    }
+
+    pub fn contains(&self, address: VirtualAddress) -> bool {
+        address >= self.start && address < self.end
+    }
+
+    /// Returns true if the access described by the page fault `error_code` is allowed
+    pub fn allows(&self, error_code: PageFaultErrorCode) -> bool {
+        if !self.flags.contains(READ) {
+            return false;
+        }
+        if error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE) && !self.flags.contains(WRITE)
+        {
+            return false;
+        }
+        if error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH)
+            && !self.flags.contains(EXEC)
+        {
+            return false;
+        }
+        if error_code.contains(PageFaultErrorCode::USER_MODE) && !self.flags.contains(USER) {
+            return false;
+        }
+
+        true
+    }
+}
+
+/// The `Vma`s of an address space, sorted by address and never overlapping.
+///
+/// The set only records which ranges are in use, see `map_range`, `protect_range` and
+/// `release_range` to also update the page tables.
+pub struct VmaSet {
+    start: VirtualAddress,
+    end: VirtualAddress,
+    vmas: BTreeMap<VirtualAddress, Vma>,
+}
+
+impl VmaSet {
+    /// Construct an empty `VmaSet` managing the addresses from `start` up to `end`
+    pub fn new(start: VirtualAddress, end: VirtualAddress) -> VmaSet {
+        assert!(start % PAGE_SIZE == 0 && end % PAGE_SIZE == 0 && start < end);
+
+        VmaSet {
+            start: start,
+            end: end,
+            vmas: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the `Vma` containing `address`
+    pub fn find(&self, address: VirtualAddress) -> Option<&Vma> {
+        if let Some(vma) = self.vmas.get(&address) {
+            return Some(vma);
+        }
+
+        match self.vmas.range(..address).next_back() {
+            Some((_, vma)) if vma.contains(address) => Some(vma),
+            _ => None,
+        }
+    }
+
+    /// Returns an iterator over the `Vma`s in address order
+    pub fn iter(&self) -> Values<VirtualAddress, Vma> {
+        self.vmas.values()
+    }
+
+    /// Reserve the `size` bytes at `start` as a new `Vma` with `flags`
+    pub fn reserve(
+        &mut self,
+        start: VirtualAddress,
+        size: usize,
+        flags: VmaFlags,
+    ) -> Result<(), VmaError> {
+        let end = self.check_range(start, size)?;
+
+        if !self.is_free(start, end) {
+            return Err(VmaError::Overlap);
+        }
+
+        self.vmas.insert(
+            start,
+            Vma {
+                start: start,
+                end: end,
+                flags: flags,
+            },
+        );
+        Ok(())
+    }
+
+    /// Reserve `size` bytes at the lowest free address as a new `Vma` with `flags`, returning its
+    /// start address
+    pub fn reserve_any(
+        &mut self,
+        size: usize,
+        flags: VmaFlags,
+    ) -> Result<VirtualAddress, VmaError> {
+        if size == 0 || size % PAGE_SIZE != 0 {
+            return Err(VmaError::Unaligned);
+        }
+
+        let start = self.find_free(size).ok_or(VmaError::NoSpace)?;
+        self.reserve(start, size, flags)?;
+        Ok(start)
+    }
+
+    /// Change the flags of the `size` bytes at `start` to `flags`.
+    ///
+    /// The whole range must be reserved. `Vma`s are split where the range starts or ends part
+    /// way through them.
+    pub fn protect(
+        &mut self,
+        start: VirtualAddress,
+        size: usize,
+        flags: VmaFlags,
+    ) -> Result<(), VmaError> {
+        let end = self.check_range(start, size)?;
+
+        if !self.is_reserved(start, end) {
+            return Err(VmaError::NotReserved);
+        }
+
+        self.split_at(start);
+        self.split_at(end);
+
+        for (_, vma) in self.vmas.range_mut(start..end) {
+            vma.flags = flags;
+        }
+
+        Ok(())
+    }
+
+    /// Release the `size` bytes at `start`, which need not all be reserved.
+    ///
+    /// `Vma`s partly inside the range are shrunk or split.
+    pub fn release(&mut self, start: VirtualAddress, size: usize) -> Result<(), VmaError> {
+        let end = self.check_range(start, size)?;
+
+        self.split_at(start);
+        self.split_at(end);
+
+        let released: Vec<VirtualAddress> = self.vmas.range(start..end).map(|(&s, _)| s).collect();
+        for vma_start in released {
+            self.vmas.remove(&vma_start);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the `Vma` containing `address`, first extending a `GROWS_DOWN` `Vma` just above
+    /// `address` to cover it if needed.
+    ///
+    /// A `Vma` grows at most `MAX_GROWTH` bytes at a time, and never to within a page of the
+    /// `Vma` below it.
+    pub fn find_or_grow(&mut self, address: VirtualAddress) -> Option<Vma> {
+        if let Some(vma) = self.find(address) {
+            return Some(*vma);
+        }
+
+        let vma = match self.vmas.range(address..).next() {
+            Some((_, vma)) if vma.flags.contains(GROWS_DOWN) => *vma,
+            _ => return None,
+        };
+
+        let start = Page::containing_address(address).start_address();
+        if vma.start - start > MAX_GROWTH || start < self.start + PAGE_SIZE
+            || !self.is_free(start - PAGE_SIZE, start)
+        {
+            return None;
+        }
+
+        let grown = Vma {
+            start: start,
+            end: vma.end,
+            flags: vma.flags,
+        };
+
+        self.vmas.remove(&vma.start);
+        self.vmas.insert(start, grown);
+        Some(grown)
+    }
+
+    /// Check the `size` bytes at `start` are a valid range, returning its end
+    fn check_range(&self, start: VirtualAddress, size: usize) -> Result<VirtualAddress, VmaError> {
+        if size == 0 || start % PAGE_SIZE != 0 || size % PAGE_SIZE != 0 {
+            return Err(VmaError::Unaligned);
+        }
+
+        if start < self.start || size > self.end - start {
+            return Err(VmaError::OutOfBounds);
+        }
+
+        Ok(start + size)
+    }
+
+    /// Returns true if no `Vma` overlaps the addresses from `start` up to `end`
+    fn is_free(&self, start: VirtualAddress, end: VirtualAddress) -> bool {
+        match self.vmas.range(..end).next_back() {
+            Some((_, vma)) => vma.end <= start,
+            None => true,
+        }
+    }
+
+    /// Returns true if every address from `start` up to `end` is in a `Vma`
+    pub fn is_reserved(&self, start: VirtualAddress, end: VirtualAddress) -> bool {
+        let mut address = start;
+
+        while address < end {
+            match self.find(address) {
+                Some(vma) => address = vma.end,
+                None => return false,
+            }
+        }
+
+        true
+    }
+
+    /// Returns the lowest address with `size` free bytes above it
+    fn find_free(&self, size: usize) -> Option<VirtualAddress> {
+        let mut gap_start = self.start;
+
+        for vma in self.vmas.values() {
+            if vma.start - gap_start >= size {
+                return Some(gap_start);
+            }
+            gap_start = vma.end;
+        }
+
+        if self.end - gap_start >= size {
+            Some(gap_start)
+        } else {
+            None
+        }
+    }
+
+    /// Split the `Vma` containing `address` in two, unless it starts at `address`
+    fn split_at(&mut self, address: VirtualAddress) {
+        let (lower_start, upper) = match self.find(address) {
+            Some(vma) if vma.start != address => (
+                vma.start,
+                Vma {
+                    start: address,
+                    end: vma.end,
+                    flags: vma.flags,
+                },
+            ),
+            _ => return,
+        };
+
+        self.vmas.get_mut(&lower_start).unwrap().end = address;
+        self.vmas.insert(address, upper);
+    }
+}
+
+/// Reserve the `size` bytes at `start` in `vmas` and map them to zeroed frames.
+///
+/// Nothing is mapped if `flags` does not include `READ`.
+pub fn map_range<M, A>(
+    vmas: &mut VmaSet,
+    mapper: &mut Mapper<M>,
+    allocator: &mut A,
+    start: VirtualAddress,
+    size: usize,
+    flags: VmaFlags,
+) -> Result<(), VmaError>
+where
+    M: PageTableMemory,
+    A: FrameAllocator,
+{
+    vmas.reserve(start, size, flags)?;
+    populate_range(vmas, mapper, allocator, start, size)
+}
+
+/// Map every unmapped page of the `size` bytes at `start` in `vmas` to a zeroed frame, rather
+/// than waiting for the first access to fault.
+///
+/// The whole range must be reserved, pages of `Vma`s without `READ` are left unmapped.
+pub fn populate_range<M, A>(
+    vmas: &VmaSet,
+    mapper: &mut Mapper<M>,
+    allocator: &mut A,
+    start: VirtualAddress,
+    size: usize,
+) -> Result<(), VmaError>
+where
+    M: PageTableMemory,
+    A: FrameAllocator,
+{
+    if size == 0 || start % PAGE_SIZE != 0 || size % PAGE_SIZE != 0 {
+        return Err(VmaError::Unaligned);
+    }
+
+    if !vmas.is_reserved(start, start + size) {
+        return Err(VmaError::NotReserved);
+    }
+
+    for page in pages(start, size) {
+        let flags = vmas.find(page.start_address()).unwrap().flags;
+        if flags.contains(READ) && mapper.translate_page(page).is_none() {
+            map_zeroed(mapper, page, flags.entry_flags(), allocator);
+        }
+    }
+
+    Ok(())
+}
+
+/// Change the flags of the `size` bytes at `start` in `vmas`, updating any mapped pages.
+///
+/// Pages left without `READ` are unmapped and their contents discarded.
+pub fn protect_range<M, A>(
+    vmas: &mut VmaSet,
+    mapper: &mut Mapper<M>,
+    allocator: &mut A,
+    start: VirtualAddress,
+    size: usize,
+    flags: VmaFlags,
+) -> Result<(), VmaError>
+where
+    M: PageTableMemory,
+    A: FrameAllocator,
+{
+    vmas.protect(start, size, flags)?;
+
+    for page in pages(start, size) {
+        if mapper.translate_page(page).is_none() {
+            continue;
+        }
+
+        if flags.contains(READ) {
+            let mut entry_flags = flags.entry_flags();
+
+            // Shared pages stay read only until written, see `handle_fault`
+            if mapper.page_flags(page).unwrap().contains(paging::COPY_ON_WRITE) {
+                entry_flags.remove(paging::WRITABLE);
+                entry_flags.insert(paging::COPY_ON_WRITE);
+            }
+
+            mapper.update_flags(page, entry_flags, allocator);
+        } else {
+            let frame = mapper.unmap(page, allocator);
+            allocator.deallocate_frame(frame);
+        }
+    }
+
+    Ok(())
+}
+
+/// Release the `size` bytes at `start` in `vmas`, unmapping any mapped pages and freeing their
+/// frames
+pub fn release_range<M, A>(
+    vmas: &mut VmaSet,
+    mapper: &mut Mapper<M>,
+    allocator: &mut A,
+    start: VirtualAddress,
+    size: usize,
+) -> Result<(), VmaError>
+where
+    M: PageTableMemory,
+    A: FrameAllocator,
+{
+    vmas.release(start, size)?;
+
+    for page in pages(start, size) {
+        if mapper.translate_page(page).is_some() {
+            let frame = mapper.unmap(page, allocator);
+            allocator.deallocate_frame(frame);
+        }
+    }
+
+    Ok(())
+}
+
+/// Share the pages mapped in `parent` with `child`, copying the `Vma`s of `parent_vmas` to the
+/// empty `child_vmas`.
+///
+/// Writable pages are made read only and marked `COPY_ON_WRITE` in both, the first write from
+/// either side then copies the frame, see `handle_fault`.
+pub fn fork<M, N, A>(
+    parent_vmas: &VmaSet,
+    parent: &mut Mapper<M>,
+    child_vmas: &mut VmaSet,
+    child: &mut Mapper<N>,
+    allocator: &mut A,
+) where
+    M: PageTableMemory,
+    N: PageTableMemory,
+    A: SharedFrameAllocator,
+{
+    for vma in parent_vmas.iter() {
+        child_vmas
+            .reserve(vma.start, vma.size(), vma.flags)
+            .expect("forking into a used address space");
+
+        for page in pages(vma.start, vma.size()) {
+            let frame = match parent.translate_page(page) {
+                Some(frame) => frame,
+                None => continue,
+            };
+
+            let mut flags = parent.page_flags(page).unwrap();
+            if flags.contains(paging::WRITABLE) {
+                flags.remove(paging::WRITABLE);
+                flags.insert(paging::COPY_ON_WRITE);
+                parent.update_flags(page, flags, allocator);
+            }
+
+            allocator.share_frame(&frame);
+            child.map_to(page, frame, flags, allocator);
+        }
+    }
+}
+
+/// Resolve a page fault at `address` using the `Vma`s in `vmas`.
+///
+/// Pages of anonymous `Vma`s are mapped to a zeroed frame on first access, and writes to
+/// `COPY_ON_WRITE` pages are given their own copy of the frame. Returns `false` if the fault can
+/// not be resolved, either as `address` is not in a `Vma` or the `Vma` does not allow the access.
+pub fn handle_fault<M, A>(
+    vmas: &mut VmaSet,
+    mapper: &mut Mapper<M>,
+    allocator: &mut A,
+    address: VirtualAddress,
+    error_code: PageFaultErrorCode,
+) -> bool
+where
+    M: PageTableMemory,
+    A: SharedFrameAllocator,
+{
+    let vma = match vmas.find_or_grow(address) {
+        Some(vma) => vma,
+        None => return false,
+    };
+
+    if !vma.allows(error_code) {
+        return false;
+    }
+
+    let page = Page::containing_address(address);
+
+    // The page is present but its flags do not allow the access, only copy on write pages are
+    // expected to fault this way
+    if error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+        let copy_on_write = mapper
+            .page_flags(page)
+            .map_or(false, |flags| flags.contains(paging::COPY_ON_WRITE));
+
+        if copy_on_write && error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE) {
+            copy_frame(mapper, page, vma.flags.entry_flags(), allocator);
+            return true;
+        }
+
+        return false;
+    }
+
+    // TODO There are no files yet to load file backed pages from
+    if vma.flags.contains(FILE_BACKED) {
+        return false;
+    }
+
+    map_zeroed(mapper, page, vma.flags.entry_flags(), allocator);
+    true
+}
+
+/// Returns the pages of the `size` bytes at `start`
+fn pages(start: VirtualAddress, size: usize) -> paging::PageIter {
+    Page::range_inclusive(
+        Page::containing_address(start),
+        Page::containing_address(start + size - 1),
+    )
+}
+
+/// Give the copy on write `page` its own copy of the frame it maps, and map it with `flags`.
+///
+/// If no other address space still shares the frame it is reused without copying.
+fn copy_frame<M, A>(mapper: &mut Mapper<M>, page: Page, flags: EntryFlags, allocator: &mut A)
+where
+    M: PageTableMemory,
+    A: SharedFrameAllocator,
+{
+    let frame = mapper.translate_page(page).unwrap();
+
+    if allocator.frame_refs(&frame) == 1 {
+        mapper.update_flags(page, flags, allocator);
+        return;
+    }
+
+    let copy = allocator.allocate_frame().expect("out of memory");
+    unsafe {
+        let source = memory::phys_to_virt(frame.start_address());
+        let destination = memory::phys_to_virt(copy.start_address());
+        ptr::copy_nonoverlapping(source as *const u8, destination as *mut u8, PAGE_SIZE);
+    }
+
+    mapper.unmap(page, allocator);
+    mapper.map_to(page, copy, flags, allocator);
+
+    // Drop this address space's reference to the shared frame
+    allocator.deallocate_frame(frame);
+}
+
+/// Map `page` to a newly allocated frame, zeroed through the direct map
+fn map_zeroed<M, A>(mapper: &mut Mapper<M>, page: Page, flags: EntryFlags, allocator: &mut A)
+where
+    M: PageTableMemory,
+    A: FrameAllocator,
+{
+    let frame = allocator.allocate_frame().expect("out of memory");
+    unsafe {
+        let address = memory::phys_to_virt(frame.start_address());
+        ptr::write_bytes(address as *mut u8, 0, PAGE_SIZE);
+    }
+
+    mapper.map_to(page, frame, flags, allocator);
+}
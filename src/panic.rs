@@ -0,0 +1,190 @@
+use core::fmt;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+
+use x86;
+
+#[cfg(not(feature = "ktest"))]
+use cmdline;
+#[cfg(not(feature = "ktest"))]
+use cpu;
+use kernel;
+use ksyms;
+use memory;
+use vga_buffer;
+use drivers::serial::{self, SerialPort};
+
+/// Maximum number of frames printed in a backtrace
+const MAX_BACKTRACE_DEPTH: usize = 32;
+
+/// Set once a panic has started, to catch panics from within the panic handler
+static PANICKING: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// Report a kernel panic and stop the machine.
+///
+/// Interrupts are disabled, then the message, current task, stack and control registers and a
+/// backtrace are printed to both the VGA buffer and serial port. Afterwards the machine halts, or
+/// reboots if booted with `panic=reboot`.
+pub fn panic(args: fmt::Arguments, file: &str, line: u32) -> ! {
+    unsafe {
+        x86::irq::disable();
+    }
+
+    // A panic while panicking, don't risk making things worse.
+    if PANICKING.swap(true, Ordering::SeqCst) {
+        hang!();
+    }
+
+    let registers = Registers::capture();
+    let mut console = PanicConsole::new();
+
+    let _ = writeln!(console, "\n\nPANIC in {} at line {}:", file, line);
+    let _ = writeln!(console, "    {}", args);
+
+    print_task(&mut console);
+    let _ = writeln!(console, "{:#?}", registers);
+    print_backtrace(&mut console, registers.rbp as usize);
+
+    stop(file)
+}
+
+/// Halt the machine, or reboot it if booted with `panic=reboot`
+#[cfg(not(feature = "ktest"))]
+fn stop(_file: &str) -> ! {
+    match cmdline::get("panic") {
+        Some("reboot") => cpu::reboot(),
+        _ => hang!(),
+    }
+}
+
+/// A panic in a test build is a test failure, unless the test expected it
+#[cfg(feature = "ktest")]
+fn stop(file: &str) -> ! {
+    ::ktest::panicked(file);
+}
+
+/// Console used while panicking.
+///
+/// Writes through new VGA and serial writers rather than the shared ones as their locks may be held
+/// by the code that panicked.
+struct PanicConsole {
+    vga: vga_buffer::Writer,
+    serial: SerialPort,
+}
+
+impl PanicConsole {
+    fn new() -> PanicConsole {
+        PanicConsole {
+            vga: unsafe { vga_buffer::error_writer() },
+            serial: SerialPort::new(serial::COM1_PORT),
+        }
+    }
+}
+
+impl fmt::Write for PanicConsole {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let _ = self.vga.write_str(s);
+        self.serial.write_str(s)
+    }
+}
+
+/// Registers captured at the point of the panic.
+///
+/// The general purpose registers are not captured, by then they only hold values of the panic
+/// handler itself.
+struct Registers {
+    rsp: u64,
+    rbp: u64,
+    rflags: u64,
+    cr0: u64,
+    cr2: u64,
+    cr3: u64,
+    cr4: u64,
+}
+
+impl Registers {
+    #[inline(always)]
+    fn capture() -> Registers {
+        let rsp: u64;
+        let rbp: u64;
+        let rflags: u64;
+
+        unsafe {
+            asm!("mov $0, rsp" : "=r"(rsp) ::: "intel");
+            asm!("mov $0, rbp" : "=r"(rbp) ::: "intel");
+            asm!("pushfq
+                  pop $0" : "=r"(rflags) ::: "intel", "volatile");
+
+            use x86::controlregs;
+
+            Registers {
+                rsp: rsp,
+                rbp: rbp,
+                rflags: rflags,
+                cr0: controlregs::cr0() as u64,
+                cr2: controlregs::cr2() as u64,
+                cr3: controlregs::cr3() as u64,
+                cr4: controlregs::cr4() as u64,
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "rsp: {:#018x} rbp: {:#018x} rflags: {:#x}\n\
+             cr0: {:#x} cr2: {:#x} cr3: {:#x} cr4: {:#x}",
+            self.rsp,
+            self.rbp,
+            self.rflags,
+            self.cr0,
+            self.cr2,
+            self.cr3,
+            self.cr4
+        )
+    }
+}
+
+/// Print the id and name of the task that panicked
+fn print_task(console: &mut PanicConsole) {
+    let kernel = match kernel::try_kget() {
+        Some(k) => k,
+        None => {
+            let _ = writeln!(console, "Task: none, kernel not initialised");
+            return;
+        }
+    };
+
+    let scheduler = unsafe { &*kernel.scheduler.get() };
+    let _ = match scheduler.get_active_task() {
+        Some(task) => writeln!(console, "Task: {} ({})", task.id(), task.name()),
+        None => writeln!(console, "Task: none, context switch in progress"),
+    };
+}
+
+/// Walk the frame pointer chain starting at `rbp`, printing each return address.
+///
+/// Each frame holds the caller's `rbp` followed by the return address. The walk stops at a null
+/// frame pointer (see `long_mode.asm` and `Task::new`) or if a frame does not look valid.
+fn print_backtrace(console: &mut PanicConsole, mut rbp: usize) {
+    let _ = writeln!(console, "Backtrace:");
+
+    for _ in 0..MAX_BACKTRACE_DEPTH {
+        if rbp == 0 || rbp % 8 != 0 || memory::translate(rbp).is_none()
+            || memory::translate(rbp + 8).is_none()
+        {
+            break;
+        }
+
+        let (next_rbp, rip) = unsafe { (*(rbp as *const usize), *((rbp + 8) as *const usize)) };
+        if rip == 0 {
+            break;
+        }
+
+        let _ = writeln!(console, "    {}", ksyms::Location(rip));
+
+        rbp = next_rbp;
+    }
+}
@@ -2,29 +2,23 @@ use alloc::linked_list::LinkedList;
 use alloc::boxed::Box;
 use kernel::kget;
 
-use schedule::task::TID_BOTTOMHALFD;
-use schedule::task::TaskStatus;
+use schedule::wait_queue::{self, WaitQueue};
 
-use spin::Mutex;
+use sync_opsys::IrqSpinLock;
 
 /// Main bottomhalfd task
 ///
-/// Loops forever, iterating over all queued `BottomHalf` tasks and executing them in series.
+/// Loops forever, waiting for `BottomHalf` tasks to be queued and executing them in series.
 pub fn execute() {
+    let scheduler = unsafe { &*kget().scheduler.get() };
+    let bh_manager = scheduler.bh_manager();
+
     loop {
-        let scheduler = unsafe { &mut *kget().scheduler.get() };
-        let bh_manager = scheduler.bh_manager();
+        // Sleep until there is work to do
+        wait_queue::wait_event(&bh_manager.wait_queue, || !bh_manager.is_empty());
 
         // Execute all waiting bottom halves
         bh_manager.execute_all();
-
-        // Set the current task status to WAITING and set the scheduler to reschedule
-        // at its next opportunity.
-        scheduler.set_task_status(TID_BOTTOMHALFD, TaskStatus::WAITING);
-        scheduler.set_need_resched();
-
-        // Sleep until the next interrupt
-        halt!();
     }
 }
 
@@ -69,32 +63,32 @@ impl BottomHalfQueue {
 ///
 /// Provides thread safety to the `BottomHalf` processing.
 pub struct BottomHalfManager {
-    queue: Mutex<BottomHalfQueue>,
+    queue: IrqSpinLock<BottomHalfQueue>,
+    /// The bottomhalfd task waits here while there are no queued `BottomHalf` tasks
+    wait_queue: WaitQueue,
 }
 
 impl BottomHalfManager {
     /// Construct a new `BottomHalfManager`
     pub fn new() -> BottomHalfManager {
         BottomHalfManager {
-            queue: Mutex::new(BottomHalfQueue::new()),
+            queue: IrqSpinLock::new(BottomHalfQueue::new()),
+            wait_queue: WaitQueue::new(),
         }
     }
 
+    /// Returns true if no `BottomHalf` tasks are queued
+    pub fn is_empty(&self) -> bool {
+        self.queue.lock().len() == 0
+    }
+
     /// Push a `BottomHalf` onto the back of the queue
     pub fn add_bh(&self, bh: Box<BottomHalf>) {
-        let len = {
-            let mut q = self.queue.lock();
-            q.push(bh);
-            q.len()
-        };
-
-        // If this is the first task added then tell the scheduler to schedule the bottom half
-        // thread next time it runs
-        if len == 1 {
-            let scheduler = unsafe { &mut *kget().scheduler.get() };
-            scheduler.set_task_status(TID_BOTTOMHALFD, TaskStatus::READY);
-            scheduler.set_need_resched();
-        }
+        self.queue.lock().push(bh);
+
+        // Wake the bottomhalfd task if it is waiting, the scheduler runs it next as it has
+        // priority IRQ
+        self.wait_queue.wake_one();
     }
 
     /// Execute all currently queued `BottomHalf` tasks
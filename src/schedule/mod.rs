@@ -1,7 +1,12 @@
+pub mod run_queue;
 mod scheduler;
+mod stats;
+pub mod wait_queue;
 // mod timer;
 
 pub mod task;
 pub mod bottom_half;
 
 pub use self::scheduler::Scheduler;
+pub use self::stats::{SchedulerStats, TaskSnapshot};
+pub use self::wait_queue::{wait_event, wait_on, wait_on_releasing, WaitQueue};
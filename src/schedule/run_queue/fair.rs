@@ -0,0 +1,201 @@
+use alloc::boxed::Box;
+use alloc::btree_map::BTreeMap;
+use alloc::vec_deque::VecDeque;
+
+use core::cmp;
+
+use super::RunQueue;
+use super::super::task::{Task, TaskPriority, MIN_NICE};
+
+/// Weight of a task with nice value 0
+const NICE_0_WEIGHT: u64 = 1024;
+
+/// Weight of each nice value from `MIN_NICE` to `MAX_NICE`, each step is roughly 10% of the CPU
+const NICE_WEIGHTS: [u64; 40] = [
+    /* -20 */ 88761, 71755, 56483, 46273, 36291,
+    /* -15 */ 29154, 23254, 18705, 14949, 11916,
+    /* -10 */ 9548, 7620, 6100, 4904, 3906,
+    /*  -5 */ 3121, 2501, 1991, 1586, 1277,
+    /*   0 */ 1024, 820, 655, 526, 423,
+    /*   5 */ 335, 272, 215, 172, 137,
+    /*  10 */ 110, 87, 70, 56, 45,
+    /*  15 */ 36, 29, 23, 18, 15,
+];
+
+/// Virtual runtime accumulated by a nice 0 task in one clock tick
+const VRUNTIME_PER_TICK: u64 = 1 << 10;
+
+/// Number of clock ticks in which every READY task should run once
+const SCHED_PERIOD: usize = 20;
+
+/// Minimum number of clock ticks a task runs before it is preempted
+const MIN_GRANULARITY: usize = 2;
+
+/// Virtual runtime a waking task must be behind the active task by to preempt it
+const WAKEUP_GRANULARITY: u64 = 2 * VRUNTIME_PER_TICK;
+
+/// Virtual runtime a waking task may be placed behind the rest, favouring tasks that wait
+const SLEEPER_CREDIT: u64 = (SCHED_PERIOD as u64 / 2) * VRUNTIME_PER_TICK;
+
+/// Returns the weight of `task` from its nice value
+fn weight(task: &Task) -> u64 {
+    NICE_WEIGHTS[(task.nice() - MIN_NICE) as usize]
+}
+
+/// Fair share scheduling of tasks by virtual runtime.
+///
+/// Each task accumulates virtual runtime as it runs, more slowly the higher its weight, and the
+/// task with the least virtual runtime runs next. Over time each task receives CPU time in
+/// proportion to its weight.
+///
+/// Tasks of priority `IRQ` are still run first, in the order they are queued.
+pub struct FairQueue {
+    /// Tasks ordered by virtual runtime, then by id to keep keys unique
+    tasks: BTreeMap<(u64, u32), Task>,
+    irq_tasks: VecDeque<Task>,
+    /// Lower bound of the virtual runtime of every task, never decreases
+    min_vruntime: u64,
+    /// Sum of the weights of `tasks`
+    total_weight: u64,
+}
+
+impl FairQueue {
+    /// Construct an empty `FairQueue`
+    pub fn new() -> FairQueue {
+        FairQueue {
+            tasks: BTreeMap::new(),
+            irq_tasks: VecDeque::new(),
+            min_vruntime: 0,
+            total_weight: 0,
+        }
+    }
+
+    /// Returns the task with the least virtual runtime
+    fn leftmost(&self) -> Option<&Task> {
+        self.tasks.values().next()
+    }
+
+    fn take(&mut self, key: (u64, u32)) -> Option<Task> {
+        let task = self.tasks.remove(&key);
+        if let Some(ref t) = task {
+            self.total_weight -= weight(t);
+        }
+        task
+    }
+
+    fn update_min_vruntime(&mut self, vruntime: u64) {
+        let vruntime = match self.leftmost() {
+            Some(t) => cmp::min(vruntime, t.vruntime()),
+            None => vruntime,
+        };
+        self.min_vruntime = cmp::max(self.min_vruntime, vruntime);
+    }
+}
+
+impl RunQueue for FairQueue {
+    fn name(&self) -> &'static str {
+        "fair"
+    }
+
+    /// Tasks that have been waiting, including new tasks, are placed no further behind the
+    /// others than `SLEEPER_CREDIT`, so they can't monopolise the CPU to catch up.
+    fn push(&mut self, mut task: Task, now: usize) {
+        task.set_ready_since(now);
+
+        if task.get_priority() == TaskPriority::IRQ {
+            self.irq_tasks.push_back(task);
+            return;
+        }
+
+        let vruntime = cmp::max(
+            task.vruntime(),
+            self.min_vruntime.saturating_sub(SLEEPER_CREDIT),
+        );
+        task.set_vruntime(vruntime);
+
+        self.total_weight += weight(&task);
+        self.tasks.insert((vruntime, task.id()), task);
+    }
+
+    fn pop(&mut self) -> Option<Task> {
+        if let Some(task) = self.irq_tasks.pop_front() {
+            return Some(task);
+        }
+
+        let key = match self.tasks.keys().next() {
+            Some(key) => *key,
+            None => return None,
+        };
+
+        let task = self.take(key);
+        if let Some(ref t) = task {
+            self.min_vruntime = cmp::max(self.min_vruntime, t.vruntime());
+        }
+        task
+    }
+
+    fn remove(&mut self, id: u32) -> Option<Task> {
+        let position = self.irq_tasks.iter().position(|t| t.id() == id);
+        if let Some(i) = position {
+            return self.irq_tasks.remove(i);
+        }
+
+        let key = match self.tasks.iter().find(|&(_, t)| t.id() == id) {
+            Some((key, _)) => *key,
+            None => return None,
+        };
+        self.take(key)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.irq_tasks.is_empty() && self.tasks.is_empty()
+    }
+
+    fn charge(&mut self, task: &mut Task, ticks: usize) {
+        if task.get_priority() == TaskPriority::IRQ {
+            return;
+        }
+
+        let vruntime = task.vruntime() + ticks as u64 * VRUNTIME_PER_TICK * NICE_0_WEIGHT
+            / weight(task);
+        task.set_vruntime(vruntime);
+        self.update_min_vruntime(vruntime);
+    }
+
+    /// Each task runs for its weighted share of `SCHED_PERIOD`, but at least `MIN_GRANULARITY`
+    fn timeslice(&self, task: &Task) -> usize {
+        let task_weight = weight(task);
+        let slice = SCHED_PERIOD as u64 * task_weight / (self.total_weight + task_weight);
+        cmp::max(slice as usize, MIN_GRANULARITY)
+    }
+
+    /// `IRQ` tasks replace any other task, or another `IRQ` task once its timeslice expires.
+    /// Otherwise the task with the least virtual runtime replaces the active task once its
+    /// timeslice expires, or straight away if it is more than `WAKEUP_GRANULARITY` behind.
+    fn preempts(&self, task: &Task, expired: bool) -> bool {
+        let irq = task.get_priority() == TaskPriority::IRQ;
+        if !self.irq_tasks.is_empty() {
+            return !irq || expired;
+        }
+        if irq {
+            return false;
+        }
+
+        match self.leftmost() {
+            Some(t) => {
+                (expired && t.vruntime() < task.vruntime())
+                    || t.vruntime() + WAKEUP_GRANULARITY < task.vruntime()
+            }
+            None => false,
+        }
+    }
+
+    /// Iterates `IRQ` tasks first, then in order of virtual runtime
+    fn iter<'a>(&'a self) -> Box<Iterator<Item = &'a Task> + 'a> {
+        box self.irq_tasks.iter().chain(self.tasks.values())
+    }
+
+    fn iter_mut<'a>(&'a mut self) -> Box<Iterator<Item = &'a mut Task> + 'a> {
+        box self.irq_tasks.iter_mut().chain(self.tasks.values_mut())
+    }
+}
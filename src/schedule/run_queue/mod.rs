@@ -0,0 +1,52 @@
+use alloc::boxed::Box;
+
+use super::task::Task;
+
+mod fair;
+mod priority;
+
+pub use self::fair::FairQueue;
+pub use self::priority::PriorityQueue;
+
+/// The tasks that are READY to run, ordered by a scheduling policy.
+///
+/// The `Scheduler` owns the active task and switches to whichever task the run queue returns
+/// from `pop`. Tasks that are WAITING are never queued.
+pub trait RunQueue {
+    /// Returns the name of the scheduling policy
+    fn name(&self) -> &'static str;
+
+    /// Queue `task` to run, `now` is the current clock tick
+    fn push(&mut self, task: Task, now: usize);
+
+    /// Remove and return the task that should run next
+    fn pop(&mut self) -> Option<Task>;
+
+    /// Remove and return the task with `id`
+    fn remove(&mut self, id: u32) -> Option<Task>;
+
+    /// Returns true if no tasks are queued
+    fn is_empty(&self) -> bool;
+
+    /// Called at the start of every reschedule, at clock tick `now`
+    fn tick(&mut self, _now: usize) {}
+
+    /// Account for `ticks` of CPU time used by the active `task`
+    fn charge(&mut self, _task: &mut Task, _ticks: usize) {}
+
+    /// Returns the number of ticks the active `task` may run before it is preempted
+    fn timeslice(&self, task: &Task) -> usize;
+
+    /// Returns true if a queued task should replace the READY active `task`.
+    ///
+    /// `expired` is true if `task` has used its whole timeslice.
+    fn preempts(&self, task: &Task, expired: bool) -> bool;
+
+    /// Returns an iterator over the queued tasks
+    fn iter<'a>(&'a self) -> Box<Iterator<Item = &'a Task> + 'a>;
+
+    /// Returns a mutable iterator over the queued tasks.
+    ///
+    /// Anything the queue is ordered by must not be changed, see `remove` and `push`.
+    fn iter_mut<'a>(&'a mut self) -> Box<Iterator<Item = &'a mut Task> + 'a>;
+}
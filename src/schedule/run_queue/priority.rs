@@ -0,0 +1,145 @@
+use alloc::boxed::Box;
+use alloc::vec_deque::VecDeque;
+
+use super::RunQueue;
+use super::super::task::{Task, TaskPriority, PRIORITY_LEVELS};
+
+/// Number of clock ticks a task runs before tasks of the same priority get a turn
+const THREAD_QUANTUM: usize = 10;
+
+/// Number of clock ticks a READY task may wait before it is treated as starving
+const STARVATION_TICKS: usize = 30;
+
+/// Round robin scheduling of tasks by priority.
+///
+/// Each priority has its own first in first out queue, and a bitmap records which queues are non
+/// empty so the highest priority task is found in constant time.
+pub struct PriorityQueue {
+    queues: [VecDeque<Task>; PRIORITY_LEVELS],
+    /// Bit `n` is set if `queues[n]` is not empty
+    non_empty: u32,
+}
+
+impl PriorityQueue {
+    /// Construct an empty `PriorityQueue`
+    pub fn new() -> PriorityQueue {
+        PriorityQueue {
+            queues: Default::default(),
+            non_empty: 0,
+        }
+    }
+
+    /// Returns the priority of the highest priority queued task
+    fn highest_priority(&self) -> Option<TaskPriority> {
+        self.highest_level().map(TaskPriority::new)
+    }
+
+    fn highest_level(&self) -> Option<usize> {
+        if self.non_empty == 0 {
+            None
+        } else {
+            Some(31 - self.non_empty.leading_zeros() as usize)
+        }
+    }
+
+    fn pop_level(&mut self, level: usize) -> Option<Task> {
+        let task = self.queues[level].pop_front();
+        self.update_non_empty(level);
+        task
+    }
+
+    fn update_non_empty(&mut self, level: usize) {
+        if self.queues[level].is_empty() {
+            self.non_empty &= !(1 << level);
+        }
+    }
+}
+
+impl RunQueue for PriorityQueue {
+    fn name(&self) -> &'static str {
+        "priority"
+    }
+
+    /// Queue `task` behind any others of the same priority, `now` is the current clock tick
+    fn push(&mut self, mut task: Task, now: usize) {
+        let level = task.get_priority().level();
+        task.set_ready_since(now);
+
+        self.queues[level].push_back(task);
+        self.non_empty |= 1 << level;
+    }
+
+    /// Remove and return the first task of the highest priority
+    fn pop(&mut self) -> Option<Task> {
+        match self.highest_level() {
+            Some(level) => self.pop_level(level),
+            None => None,
+        }
+    }
+
+    /// Remove and return the task with `id`
+    fn remove(&mut self, id: u32) -> Option<Task> {
+        for level in 0..PRIORITY_LEVELS {
+            let position = self.queues[level].iter().position(|t| t.id() == id);
+
+            if let Some(i) = position {
+                let task = self.queues[level].remove(i);
+                self.update_non_empty(level);
+                return task;
+            }
+        }
+
+        None
+    }
+
+    /// Run tasks starved by higher priority ones at `TaskPriority::MAX` for one quantum.
+    ///
+    /// Only the task at the front of each queue can have waited longest, so this checks one task
+    /// per priority.
+    fn tick(&mut self, now: usize) {
+        let highest = match self.highest_level() {
+            Some(level) => level,
+            None => return,
+        };
+
+        for level in 0..highest {
+            let starving = match self.queues[level].front() {
+                Some(task) => now - task.ready_since() > STARVATION_TICKS,
+                None => false,
+            };
+
+            if starving {
+                let mut task = self.pop_level(level).unwrap();
+                task.set_starving(true);
+                self.push(task, now);
+            }
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.non_empty == 0
+    }
+
+    fn timeslice(&self, _task: &Task) -> usize {
+        THREAD_QUANTUM
+    }
+
+    /// Tasks are replaced by a higher priority task, or by one of the same priority once their
+    /// quantum expires.
+    fn preempts(&self, task: &Task, expired: bool) -> bool {
+        let priority = task.get_priority();
+        match self.highest_priority() {
+            Some(highest) => highest > priority || (highest == priority && expired),
+            None => false,
+        }
+    }
+
+    /// Iterates highest priority first
+    fn iter<'a>(&'a self) -> Box<Iterator<Item = &'a Task> + 'a> {
+        box self.queues.iter().rev().flat_map(|queue| queue.iter())
+    }
+
+    fn iter_mut<'a>(&'a mut self) -> Box<Iterator<Item = &'a mut Task> + 'a> {
+        box self.queues.iter_mut().rev().flat_map(|queue| queue.iter_mut())
+    }
+}
@@ -1,127 +1,306 @@
-use alloc::linked_list::LinkedList;
 use alloc::arc::Arc;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
 
 use super::bottom_half;
 use super::bottom_half::BottomHalfManager;
+use super::run_queue::{FairQueue, PriorityQueue, RunQueue};
+use super::stats::{SchedulerStats, TaskSnapshot};
 
 use super::task::{TID_BOTTOMHALFD, TID_SYSTEMIDLE};
-use super::task::{Task, TaskContext, TaskPriority, TaskStatus};
+use super::task::{Task, TaskContext, TaskEntry, TaskHandle, TaskPriority, TaskStatus};
+use super::task::TidAllocator;
+use super::task::EXIT_KILLED;
 
+use cmdline;
+use cpu::fpu;
 use kernel::kget;
-use memory::MemoryManager;
-
-const THREAD_QUANTUM: usize = 10;
+use memory::{self, AddressSpace, AddressSpaceRelease, MemoryManager};
+use memory::paging::PhysicalAddress;
 
 /// Scheduler for the kernel. Manages scheduling of tasks and timers
+///
+/// The order READY tasks run in is decided by the scheduling policy of the run queue, chosen at
+/// boot with the `sched` command line option. `sched=fair` selects `FairQueue`, otherwise
+/// `PriorityQueue` is used.
 pub struct Scheduler {
-    inactive_tasks: LinkedList<Task>,
+    run_queue: Box<RunQueue>,
+    waiting_tasks: Vec<Task>,
     active_task: Option<Task>,
-    task_count: u32,
+    tids: TidAllocator,
+    /// Clock tick at which the active task was switched to, or its timeslice last restarted
     last_resched: usize,
+    /// Clock tick up to which the active task has been charged for its CPU time
+    last_charged: usize,
+    /// Number of context switches since boot
+    context_switches: usize,
+    /// Id of the task whose state the FPU registers hold
+    fpu_owner: Option<u32>,
     need_resched: bool,
     bh_manager: Arc<BottomHalfManager>,
+    kernel_p4_address: PhysicalAddress,
 }
 
 impl Scheduler {
     /// Creates a new scheduler
     ///
-    /// The currently active task is created along with a single, currently `WAITING`, task of
-    /// priority `IRQ`.
+    /// The currently active task is created along with a single task of priority `IRQ`, which
+    /// waits for bottom halves to process once it first runs.
     pub fn new(memory_manager: &mut MemoryManager) -> Scheduler {
-        let mut inactive_tasks = LinkedList::new();
+        let mut run_queue: Box<RunQueue> = match cmdline::get("sched") {
+            Some("fair") => box FairQueue::new(),
+            _ => box PriorityQueue::new(),
+        };
+        kinfo!("Scheduling policy: {}", run_queue.name());
 
         // Create the kernel bottom_half IRQ processing thread
         let stack = memory_manager.allocate_stack();
-        inactive_tasks.push_front(Task::new(
-            TID_BOTTOMHALFD,
-            stack,
-            bottom_half::execute,
-            TaskPriority::IRQ,
-            TaskStatus::WAITING,
-        ));
+        run_queue.push(
+            Task::new(
+                TID_BOTTOMHALFD,
+                "bottomhalfd",
+                stack,
+                entry_from_fn(bottom_half::execute),
+                TaskPriority::IRQ,
+                TaskStatus::READY,
+            ),
+            0,
+        );
+
+        let mut idle_task = Task::default(TID_SYSTEMIDLE);
+        idle_task.set_name("idle");
 
         Scheduler {
-            inactive_tasks: inactive_tasks,
-            active_task: Some(Task::default(TID_SYSTEMIDLE)),
-            task_count: 2,
+            run_queue: run_queue,
+            waiting_tasks: Vec::new(),
+            active_task: Some(idle_task),
+            tids: TidAllocator::new(),
             last_resched: 0,
+            last_charged: 0,
+            context_switches: 0,
+            fpu_owner: None,
             need_resched: false,
             bh_manager: Arc::new(BottomHalfManager::new()),
+            kernel_p4_address: memory::active_p4_address(),
         }
     }
 
-    /// Create a new task to be scheduled.
-    pub fn new_task(&mut self, memory_manager: &mut MemoryManager, func: fn()) {
-        let stack = memory_manager.allocate_stack();
+    /// Create a new task to be scheduled, returning a handle to it.
+    ///
+    /// The task exits with code 0 once `func` returns.
+    pub fn new_task(
+        &mut self,
+        memory_manager: &mut MemoryManager,
+        name: &str,
+        func: fn(),
+    ) -> TaskHandle {
+        self.new_task_with_priority(memory_manager, name, func, TaskPriority::NORMAL)
+    }
+
+    /// Create a new task to be scheduled at `priority`, returning a handle to it.
+    pub fn new_task_with_priority(
+        &mut self,
+        memory_manager: &mut MemoryManager,
+        name: &str,
+        func: fn(),
+        priority: TaskPriority,
+    ) -> TaskHandle {
+        let task = self.create_task(memory_manager, name, entry_from_fn(func), priority);
+        let handle = task.handle();
+        self.enqueue(task);
+        handle
+    }
+
+    /// Create a new task to be scheduled, running in `address_space`, returning a handle to it.
+    ///
+    /// The address space may be shared by several tasks.
+    pub fn new_task_in(
+        &mut self,
+        memory_manager: &mut MemoryManager,
+        name: &str,
+        func: fn(),
+        address_space: Arc<AddressSpace>,
+    ) -> TaskHandle {
+        let entry = entry_from_fn(func);
+        let mut task = self.create_task(memory_manager, name, entry, TaskPriority::NORMAL);
+        task.set_address_space(address_space);
+        let handle = task.handle();
+        self.enqueue(task);
+        handle
+    }
 
-        self.inactive_tasks.push_front(Task::new(
-            self.task_count,
-            stack,
-            func,
-            TaskPriority::NORMAL,
-            TaskStatus::READY,
-        ));
+    /// Create a new task to be scheduled running the closure `entry`, returning a handle to it.
+    ///
+    /// The task exits with the code returned by `entry`, which may capture the task's arguments.
+    pub fn spawn<F>(
+        &mut self,
+        memory_manager: &mut MemoryManager,
+        name: &str,
+        entry: F,
+    ) -> TaskHandle
+    where
+        F: FnOnce() -> i32 + Send + 'static,
+    {
+        let task = self.create_task(memory_manager, name, box entry, TaskPriority::NORMAL);
+        let handle = task.handle();
+        self.enqueue(task);
+        handle
+    }
 
-        self.task_count += 1;
+    fn create_task(
+        &mut self,
+        memory_manager: &mut MemoryManager,
+        name: &str,
+        entry: TaskEntry,
+        priority: TaskPriority,
+    ) -> Task {
+        let id = self.tids.allocate().expect("Out of task ids");
+        let stack = memory_manager.allocate_stack();
+
+        Task::new(id, name, stack, entry, priority, TaskStatus::READY)
     }
 
     /// Schedule the next task.
     ///
-    /// Choses the next task with status != `TaskStatus::COMPLETED` and switches its context with
-    /// that of the currently active task.
+    /// The active task is first charged for the CPU time it has used. It is then switched for the
+    /// next task from the run queue if it is no longer READY, or if the run queue's policy decides
+    /// a queued task should preempt it.
+    ///
+    /// The stack canary of a task is checked whenever it is switched out, panicking if it has been
+    /// overwritten.
+    ///
+    /// Tasks that use their whole timeslice lose any priority boost, while tasks that wait before
+    /// it expires are boosted.
     pub fn schedule(&mut self, active_ctx: &mut TaskContext) {
+        let now = unsafe { &*kget().clock.get() }.now();
+
+        if let Some(ref mut t) = self.active_task {
+            self.run_queue.charge(t, now - self.last_charged);
+        }
+        self.last_charged = now;
+
+        self.run_queue.tick(now);
+
         // Optimization - return early if nothing to do
-        if self.inactive_tasks.len() == 0 {
+        if self.run_queue.is_empty() {
+            self.need_resched = false;
             return;
         }
 
-        // First look for active high priority tasks first, if none of these exist then look for
-        // normal priority tasks. There is guaranteed to always be at least one NORMAL priority
-        // task so it is safe to call unwrap.
-        let new_task = match self.next_task(TaskPriority::IRQ) {
-            Some(t) => t,
-            None => {
-                if let Some(ref t) = self.active_task {
-                    // The active task is an interrupt handler that hasn't yet completed, let it
-                    // run to completion.
-                    if t.get_priority() == TaskPriority::IRQ && t.get_status() == TaskStatus::READY
-                    {
-                        return;
-                    }
+        let expired = match self.active_task {
+            Some(ref t) => (now - self.last_resched) > self.run_queue.timeslice(t),
+            None => false,
+        };
+
+        if let Some(ref mut t) = self.active_task {
+            if t.get_status() == TaskStatus::READY {
+                if expired {
+                    t.lower_boost();
                 }
 
-                // Theres definitely nothing higer priority!
-                self.next_task(TaskPriority::NORMAL).unwrap()
+                // Let the active task carry on if nothing more important is waiting, starting a
+                // new timeslice if the last one expired
+                if !self.run_queue.preempts(t, expired) {
+                    if expired {
+                        self.last_resched = now;
+                    }
+                    self.need_resched = false;
+                    return;
+                }
             }
-        };
+        }
+
+        let mut new_task = self.run_queue.pop().unwrap();
+        new_task.set_starving(false);
+        new_task.account_dequeued(now);
 
         let mut old_task = self.active_task.take().unwrap();
+        old_task.account_switch();
+        self.context_switches += 1;
 
         // Swap the contexts
         // Copy the active context to save it
         old_task.set_context(active_ctx);
+
+        // Catch stack overflows before they corrupt anything more than the stack
+        if !old_task.stack_canary_intact() {
+            panic!("Task {} ({}) overflowed its stack", old_task.id(), old_task.name());
+        }
         *active_ctx = *new_task.get_context();
 
+        // Switch to the new task's address space. This must happen before `old_task` may be
+        // dropped below, as dropping it could free the active address space.
+        let p4_address = match new_task.address_space() {
+            Some(address_space) => address_space.p4_address(),
+            None => self.kernel_p4_address,
+        };
+        unsafe { memory::switch_to(p4_address) };
+
+        // Only the task whose state the FPU holds may use it straight away, any other traps on
+        // first use so the state can be switched then, see `switch_fpu`
+        fpu::set_task_switched(self.fpu_owner != Some(new_task.id()));
+
         // Update the schedulers internal references and store the initial task back into the
-        // inactive_tasks list if it is not yet finished. By not restoring COMPLETED tasks here
-        // we force cleanup of COMPLETED tasks.
+        // run queue or waiting tasks if it is not yet finished. By not restoring COMPLETED tasks
+        // here we force cleanup of COMPLETED tasks.
         self.active_task = Some(new_task);
-        if old_task.get_status() != TaskStatus::COMPLETED {
-            self.inactive_tasks.push_back(old_task);
+        match old_task.get_status() {
+            TaskStatus::READY => {
+                old_task.account_queued(now);
+                self.run_queue.push(old_task, now);
+            }
+            TaskStatus::WAITING => {
+                // Favour tasks that wait before using their timeslice, such as those doing I/O
+                if !expired {
+                    old_task.raise_boost();
+                }
+                self.wait(old_task, now);
+            }
+            TaskStatus::COMPLETED => self.destroy(old_task),
         }
 
         // Update the last_resched time
         self.update_last_resched();
     }
 
+    /// Get a reference to the current active task.
+    ///
+    /// Returns `None` while a context switch is in progress.
+    pub fn get_active_task(&self) -> Option<&Task> {
+        self.active_task.as_ref()
+    }
+
     /// Get a mutable reference to the current active task.
     pub fn get_active_task_mut(&mut self) -> Option<&mut Task> {
         self.active_task.as_mut()
     }
 
+    /// Returns an iterator over all tasks, starting with the active task.
+    ///
+    /// Each task's id, name, status, priority and stack usage are available through its accessors.
+    pub fn tasks<'a>(&'a self) -> impl Iterator<Item = &'a Task> + 'a {
+        self.active_task
+            .iter()
+            .chain(self.run_queue.iter())
+            .chain(self.waiting_tasks.iter())
+    }
+
+    /// Get a mutable reference to the task with `id`
+    ///
+    /// The status, priority and nice value of the task must be changed with `set_task_status`,
+    /// `set_task_priority` and `set_task_nice`, and its virtual runtime not at all, so it is kept
+    /// in the right place in the run queue.
+    pub fn get_task_mut(&mut self, id: u32) -> Option<&mut Task> {
+        self.active_task
+            .iter_mut()
+            .chain(self.run_queue.iter_mut())
+            .chain(self.waiting_tasks.iter_mut())
+            .find(|t| t.id() == id)
+    }
+
     /// Returns true if a reschedule is needed
     ///
-    /// Returns true if the last reschedule was over `THREAD_QUANTUM` cpu ticks ago.
+    /// Returns true if the active task has used its timeslice since the last reschedule.
     pub fn need_resched(&self) -> bool {
         if self.need_resched {
             return true;
@@ -129,7 +308,37 @@ impl Scheduler {
 
         let clock = unsafe { &mut *kget().clock.get() };
         let now = clock.now();
-        (now - self.last_resched) > THREAD_QUANTUM
+        match self.active_task {
+            Some(ref t) => (now - self.last_resched) > self.run_queue.timeslice(t),
+            None => false,
+        }
+    }
+
+    /// Returns the name of the scheduling policy
+    pub fn policy(&self) -> &'static str {
+        self.run_queue.name()
+    }
+
+    /// Count a clock tick against the active task.
+    ///
+    /// Called on every clock interrupt with the `context` it interrupted, which tells whether the
+    /// task was running in user or kernel mode.
+    pub fn account_tick(&mut self, context: &TaskContext) {
+        if let Some(ref mut t) = self.active_task {
+            t.account_tick(context.cs & 3 == 3);
+        }
+    }
+
+    /// Returns a snapshot of the scheduler and the statistics of every task
+    pub fn stats(&self) -> SchedulerStats {
+        let clock = unsafe { &*kget().clock.get() };
+
+        SchedulerStats {
+            policy: self.policy(),
+            now: clock.now(),
+            context_switches: self.context_switches,
+            tasks: self.tasks().map(TaskSnapshot::new).collect(),
+        }
     }
 
     /// Returns an Arc pointer to the bh_fifo
@@ -138,6 +347,9 @@ impl Scheduler {
     }
 
     /// Set the status of task with `id`
+    ///
+    /// A task made READY is queued to run, a task already queued keeps its place, and a task made
+    /// WAITING is removed from the run queue.
     pub fn set_task_status(&mut self, id: u32, status: TaskStatus) {
         if let Some(ref mut t) = self.active_task {
             if t.id() == id {
@@ -146,12 +358,128 @@ impl Scheduler {
             }
         }
 
-        for t in self.inactive_tasks.iter_mut() {
+        let position = self.waiting_tasks.iter().position(|t| t.id() == id);
+        let task = match position {
+            Some(_) if status == TaskStatus::WAITING => return,
+            Some(i) => Some(self.waiting_tasks.swap_remove(i)),
+            // Tasks in the run queue are already READY, leave them in their place
+            None if status == TaskStatus::READY => return,
+            None => self.run_queue.remove(id),
+        };
+
+        if let Some(mut task) = task {
+            let now = unsafe { &*kget().clock.get() }.now();
+            task.account_dequeued(now);
+            task.set_status(status);
+            match status {
+                TaskStatus::READY => self.enqueue(task),
+                TaskStatus::WAITING => self.wait(task, now),
+                TaskStatus::COMPLETED => self.destroy(task),
+            }
+        }
+    }
+
+    /// Make the active task WAITING and request a reschedule, returning a handle to the task.
+    ///
+    /// The task keeps running until the next reschedule switches it out, and runs again once it is
+    /// made READY, see `wake_task`.
+    pub fn wait_active_task(&mut self) -> Option<TaskHandle> {
+        let handle = match self.active_task {
+            Some(ref mut t) => {
+                t.set_status(TaskStatus::WAITING);
+                t.handle()
+            }
+            None => return None,
+        };
+
+        self.need_resched = true;
+        Some(handle)
+    }
+
+    /// Make the task with `id` READY if it is WAITING, returning true if it was.
+    pub fn wake_task(&mut self, id: u32) -> bool {
+        if let Some(ref mut t) = self.active_task {
             if t.id() == id {
-                t.set_status(status);
-                return;
+                let waiting = t.get_status() == TaskStatus::WAITING;
+                if waiting {
+                    t.set_status(TaskStatus::READY);
+                }
+                return waiting;
             }
         }
+
+        // Tasks in the run queue are already READY
+        let waiting = self.waiting_tasks.iter().any(|t| t.id() == id);
+        if waiting {
+            self.set_task_status(id, TaskStatus::READY);
+        }
+        waiting
+    }
+
+    /// Give the FPU to the active task.
+    ///
+    /// Called when the active task traps using the FPU after being switched to. The registers are
+    /// saved for the task that last used them, then loaded with the active task's state.
+    pub fn switch_fpu(&mut self) {
+        fpu::set_task_switched(false);
+
+        let active = match self.active_task {
+            Some(ref t) => t.id(),
+            None => return,
+        };
+        if self.fpu_owner == Some(active) {
+            return;
+        }
+
+        if let Some(owner) = self.fpu_owner {
+            if let Some(task) = self.get_task_mut(owner) {
+                unsafe { task.fpu_state_mut().save() };
+            }
+        }
+
+        if let Some(ref mut t) = self.active_task {
+            unsafe { t.fpu_state_mut().restore() };
+        }
+        self.fpu_owner = Some(active);
+    }
+
+    /// Kill the task with `id`, removing it from the scheduler and dropping it.
+    ///
+    /// Returns false if there is no such task, or it is the active task or a system task. The
+    /// active task should `exit` instead. Interrupts must be disabled.
+    pub fn kill_task(&mut self, id: u32) -> bool {
+        if id == TID_SYSTEMIDLE || id == TID_BOTTOMHALFD {
+            return false;
+        }
+
+        if self.active_task.as_ref().map(|t| t.id()) == Some(id) {
+            return false;
+        }
+
+        let position = self.waiting_tasks.iter().position(|t| t.id() == id);
+        let task = match position {
+            Some(i) => Some(self.waiting_tasks.swap_remove(i)),
+            None => self.run_queue.remove(id),
+        };
+
+        match task {
+            Some(mut task) => {
+                task.complete(EXIT_KILLED);
+                self.destroy(task);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Set the base priority of task with `id`
+    pub fn set_task_priority(&mut self, id: u32, priority: TaskPriority) {
+        self.update_task(id, |t| t.set_priority(priority));
+    }
+
+    /// Set the nice value of task with `id`, used by the fair scheduling policy
+    pub fn set_task_nice(&mut self, id: u32, nice: i8) {
+        self.update_task(id, |t| t.set_nice(nice));
     }
 
     /// Set the internal 'need_resched' flag to true
@@ -159,44 +487,76 @@ impl Scheduler {
         self.need_resched = true;
     }
 
-    /// Update `last_resched` to now and reset the `need_resched` flag
-    fn update_last_resched(&mut self) {
-        let clock = unsafe { &mut *kget().clock.get() };
-        self.last_resched = clock.now();
-        self.need_resched = false;
+    /// Apply `update` to the task with `id`.
+    ///
+    /// A queued task is queued again afterwards, as `update` may change its place in the queue.
+    fn update_task<F: FnOnce(&mut Task)>(&mut self, id: u32, update: F) {
+        if let Some(ref mut t) = self.active_task {
+            if t.id() == id {
+                update(t);
+                return;
+            }
+        }
+
+        if let Some(t) = self.waiting_tasks.iter_mut().find(|t| t.id() == id) {
+            update(t);
+            return;
+        }
+
+        // Queue the task again as `update` may change its place, keeping when it became READY
+        if let Some(mut task) = self.run_queue.remove(id) {
+            let ready_since = task.ready_since();
+            update(&mut task);
+            self.run_queue.push(task, ready_since);
+        }
     }
 
-    /// Find the next task with priority matching `priority`
-    fn next_task(&mut self, priority: TaskPriority) -> Option<Task> {
-        let mut i = 0;
-        let mut found = false;
+    /// Queue a READY task to run, preempting the active task if the run queue's policy decides
+    fn enqueue(&mut self, mut task: Task) {
+        let now = unsafe { &*kget().clock.get() }.now();
+        task.account_queued(now);
+        self.run_queue.push(task, now);
 
-        for ref t in self.inactive_tasks.iter() {
-            if t.get_priority() != priority || t.get_status() != TaskStatus::READY {
-                // On to the next, this is not suitable
-                i += 1;
-            } else {
-                found = true;
-                break;
+        if let Some(ref t) = self.active_task {
+            if self.run_queue.preempts(t, false) {
+                self.need_resched = true;
             }
         }
+    }
 
-        if found {
-            // Split inactive_tasks, remove the task we found, then re-merge the two lists
-            let mut remainder = self.inactive_tasks.split_off(i);
-            let next_task = remainder.pop_front();
+    /// Add a task to the WAITING tasks, `now` is the current clock tick
+    fn wait(&mut self, mut task: Task, now: usize) {
+        task.account_queued(now);
+        self.waiting_tasks.push(task);
+    }
 
-            // Merge the lists
-            loop {
-                match remainder.pop_front() {
-                    Some(t) => self.inactive_tasks.push_back(t),
-                    None => break,
-                }
-            }
+    /// Drop a COMPLETED task, freeing its id to be reused
+    fn destroy(&mut self, mut task: Task) {
+        // The registers are no longer needed, nor is there anywhere to save them
+        if self.fpu_owner == Some(task.id()) {
+            self.fpu_owner = None;
+        }
 
-            next_task
-        } else {
-            None
+        // This may run in the timer interrupt, so leave freeing the address space to bottomhalfd
+        if let Some(address_space) = task.take_address_space() {
+            self.bh_manager.add_bh(box AddressSpaceRelease::new(address_space));
         }
+
+        self.tids.free(task.id());
+    }
+
+    /// Update `last_resched` to now and reset the `need_resched` flag
+    fn update_last_resched(&mut self) {
+        let clock = unsafe { &mut *kget().clock.get() };
+        self.last_resched = clock.now();
+        self.need_resched = false;
+    }
+}
+
+/// Wrap `func` as a `TaskEntry` exiting with code 0
+fn entry_from_fn(func: fn()) -> TaskEntry {
+    box move || {
+        func();
+        0
     }
 }
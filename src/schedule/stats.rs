@@ -0,0 +1,135 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use core::fmt;
+
+use super::task::{Task, TaskPriority, TaskStats, TaskStatus};
+
+/// The state and statistics of a task when `Scheduler::stats` was called
+#[derive(Debug, Clone)]
+pub struct TaskSnapshot {
+    pub id: u32,
+    pub name: String,
+    pub status: TaskStatus,
+    pub priority: TaskPriority,
+    pub nice: i8,
+    pub stats: TaskStats,
+    /// Bytes of stack in use when the task was last switched out, see `Task::stack_usage`
+    pub stack_usage: Option<usize>,
+    /// Most bytes of stack used by the task, see `Task::stack_high_water_mark`
+    pub stack_high_water_mark: Option<usize>,
+}
+
+impl TaskSnapshot {
+    pub fn new(task: &Task) -> TaskSnapshot {
+        TaskSnapshot {
+            id: task.id(),
+            name: String::from(task.name()),
+            status: task.get_status(),
+            priority: task.get_priority(),
+            nice: task.nice(),
+            stats: *task.stats(),
+            stack_usage: task.stack_usage(),
+            stack_high_water_mark: task.stack_high_water_mark(),
+        }
+    }
+
+    /// Returns the clock ticks the task has been running for
+    pub fn cpu_ticks(&self) -> usize {
+        self.stats.user_ticks + self.stats.kernel_ticks
+    }
+}
+
+fn status_name(status: TaskStatus) -> &'static str {
+    match status {
+        TaskStatus::READY => "READY",
+        TaskStatus::WAITING => "WAITING",
+        TaskStatus::COMPLETED => "COMPLETED",
+    }
+}
+
+/// A snapshot of the scheduler, returned by `Scheduler::stats`.
+///
+/// Displays as a table with one row per task, the active task first.
+#[derive(Debug)]
+pub struct SchedulerStats {
+    /// Name of the scheduling policy
+    pub policy: &'static str,
+    /// Clock tick the snapshot was taken at
+    pub now: usize,
+    /// Number of context switches since boot
+    pub context_switches: usize,
+    pub tasks: Vec<TaskSnapshot>,
+}
+
+impl fmt::Display for SchedulerStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "policy: {} uptime: {} tasks: {} switches: {}",
+            self.policy,
+            self.now,
+            self.tasks.len(),
+            self.context_switches
+        )?;
+        writeln!(
+            f,
+            "{:>5} {:<16} {:<9} {:>4} {:>4} {:>4} {:>8} {:>8} {:>6} {:>6} {:>8} {:>8} {:>6} {:>6}",
+            "ID",
+            "NAME",
+            "STATUS",
+            "PRI",
+            "NI",
+            "%CPU",
+            "USER",
+            "KERNEL",
+            "VOL",
+            "INVOL",
+            "READY",
+            "WAIT",
+            "STACK",
+            "PEAK"
+        )?;
+
+        for task in &self.tasks {
+            let cpu = if self.now == 0 {
+                0
+            } else {
+                task.cpu_ticks() * 100 / self.now
+            };
+
+            // Long names are cut short to keep the columns aligned
+            let name = match task.name.char_indices().nth(16) {
+                Some((end, _)) => &task.name[..end],
+                None => &task.name[..],
+            };
+
+            write!(
+                f,
+                "{:>5} {:<16} {:<9} {:>4} {:>4} {:>4} {:>8} {:>8} {:>6} {:>6} {:>8} {:>8}",
+                task.id,
+                name,
+                status_name(task.status),
+                task.priority.level(),
+                task.nice,
+                cpu,
+                task.stats.user_ticks,
+                task.stats.kernel_ticks,
+                task.stats.voluntary_switches,
+                task.stats.involuntary_switches,
+                task.stats.ready_ticks,
+                task.stats.waiting_ticks
+            )?;
+
+            for bytes in &[task.stack_usage, task.stack_high_water_mark] {
+                match *bytes {
+                    Some(bytes) => write!(f, " {:>6}", bytes)?,
+                    None => write!(f, " {:>6}", "-")?,
+                }
+            }
+            writeln!(f, "")?;
+        }
+
+        Ok(())
+    }
+}
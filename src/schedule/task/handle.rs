@@ -0,0 +1,129 @@
+use alloc::arc::Arc;
+
+use core::fmt;
+
+use sync_opsys::IrqSpinLock;
+
+use cpu;
+use kernel::kget;
+use schedule::{wait_event, WaitQueue};
+
+use super::TaskStatus;
+use super::task::exit;
+
+/// Exit code of a task that was killed
+pub const EXIT_KILLED: i32 = -1;
+
+/// The exit code of a task, shared by the task and its `TaskHandle`s so it outlives the task
+pub struct TaskExit {
+    code: IrqSpinLock<Option<i32>>,
+    /// Tasks waiting in `TaskHandle::join`
+    joiners: WaitQueue,
+}
+
+impl TaskExit {
+    /// Construct a `TaskExit` for a task that has not exited
+    pub fn new() -> TaskExit {
+        TaskExit {
+            code: IrqSpinLock::new(None),
+            joiners: WaitQueue::new(),
+        }
+    }
+
+    /// Returns the exit code, `None` if the task has not exited
+    pub fn code(&self) -> Option<i32> {
+        *self.code.lock()
+    }
+
+    /// Record the exit code and wake the joining tasks
+    pub fn set(&self, code: i32) {
+        *self.code.lock() = Some(code);
+        self.joiners.wake_all();
+    }
+}
+
+impl fmt::Debug for TaskExit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TaskExit")
+            .field("code", &self.code())
+            .finish()
+    }
+}
+
+/// A handle to a task, returned when it is created.
+///
+/// The handle may be used to wait for the task to exit and read its exit code, or to kill it.
+/// It stays valid after the task has exited and been dropped.
+#[derive(Debug, Clone)]
+pub struct TaskHandle {
+    id: u32,
+    exit: Arc<TaskExit>,
+}
+
+impl TaskHandle {
+    /// Construct a handle to the task with `id`, sharing its `exit`
+    pub fn new(id: u32, exit: Arc<TaskExit>) -> TaskHandle {
+        TaskHandle { id: id, exit: exit }
+    }
+
+    /// Returns the id of the task
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Returns the status of the task, `COMPLETED` once it has exited or been killed
+    pub fn status(&self) -> TaskStatus {
+        cpu::without_interrupts(|| {
+            if self.exit.code().is_some() {
+                return TaskStatus::COMPLETED;
+            }
+
+            let scheduler = unsafe { &*kget().scheduler.get() };
+            scheduler
+                .tasks()
+                .find(|t| t.id() == self.id)
+                .map_or(TaskStatus::COMPLETED, |t| t.get_status())
+        })
+    }
+
+    /// Returns the exit code of the task, `None` if it has not exited
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit.code()
+    }
+
+    /// Block the active task until the task exits, returning its exit code.
+    ///
+    /// The exit code of a killed task is `EXIT_KILLED`.
+    pub fn join(&self) -> i32 {
+        let scheduler = unsafe { &*kget().scheduler.get() };
+        let active = scheduler.get_active_task().map(|t| t.id());
+        assert!(
+            active != Some(self.id) || self.exit.code().is_some(),
+            "Task {} can't join itself",
+            self.id
+        );
+
+        wait_event(&self.exit.joiners, || self.exit.code().is_some());
+        self.exit.code().unwrap()
+    }
+
+    /// Kill the task, returning false if it has already exited or is a system task.
+    ///
+    /// A killed task is removed from the scheduler and dropped without running any further, so any
+    /// locks it holds are never released. A task killing itself exits with `EXIT_KILLED`.
+    pub fn kill(&self) -> bool {
+        // The id may have been given to a new task once this one exited
+        if self.exit.code().is_some() {
+            return false;
+        }
+
+        let scheduler = unsafe { &mut *kget().scheduler.get() };
+        if scheduler.get_active_task().map(|t| t.id()) == Some(self.id) {
+            exit(EXIT_KILLED);
+        }
+
+        cpu::without_interrupts(|| {
+            self.exit.code().is_none() && scheduler.kill_task(self.id)
+        })
+    }
+}
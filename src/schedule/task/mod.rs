@@ -1,5 +1,7 @@
+mod handle;
 mod task;
 mod task_context;
+mod tid;
 
 /// Task ID for the system idle task
 pub const TID_SYSTEMIDLE: u32 = 0;
@@ -7,7 +9,9 @@ pub const TID_SYSTEMIDLE: u32 = 0;
 /// Task ID for the `BottomHalf` processing daemon
 pub const TID_BOTTOMHALFD: u32 = 1;
 
-pub use self::task::Task;
-pub use self::task::TaskStatus;
-pub use self::task::TaskPriority;
+pub use self::handle::{TaskExit, TaskHandle, EXIT_KILLED};
+pub use self::task::{exit, Task, TaskEntry};
+pub use self::task::{TaskStats, TaskStatus};
+pub use self::task::{TaskPriority, MAX_NICE, MIN_NICE, PRIORITY_LEVELS};
 pub use self::task_context::TaskContext;
+pub use self::tid::TidAllocator;
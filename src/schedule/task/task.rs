@@ -1,6 +1,28 @@
 use super::*;
 
-use memory::Stack;
+use alloc::arc::Arc;
+use alloc::boxed::{Box, FnBox};
+use alloc::string::String;
+
+use core::{cmp, fmt};
+
+use cpu;
+use cpu::fpu::FpuState;
+use memory::{AddressSpace, Stack};
+
+use super::handle::{TaskExit, TaskHandle};
+
+/// Entry point of a task, returning its exit code
+pub type TaskEntry = Box<FnBox() -> i32 + Send>;
+
+/// Wraps a `TaskEntry` as closures don't implement `Debug`
+struct Entry(TaskEntry);
+
+impl fmt::Debug for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Entry")
+    }
+}
 
 /// Status of a kernel task
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -13,13 +35,59 @@ pub enum TaskStatus {
     COMPLETED,
 }
 
-/// Priority of a kernel task
-#[derive(Debug, Copy, Clone, PartialEq)]
-pub enum TaskPriority {
+/// CPU time and scheduling statistics of a task, times are in clock ticks
+#[derive(Debug, Copy, Clone, Default)]
+pub struct TaskStats {
+    /// Clock ticks that interrupted the task in user mode
+    pub user_ticks: usize,
+    /// Clock ticks that interrupted the task in kernel mode
+    pub kernel_ticks: usize,
+    /// Times the task was switched out as it was WAITING or COMPLETED
+    pub voluntary_switches: usize,
+    /// Times the task was switched out while still READY
+    pub involuntary_switches: usize,
+    /// Time spent READY to run but not running
+    pub ready_ticks: usize,
+    /// Time spent WAITING
+    pub waiting_ticks: usize,
+}
+
+/// Number of distinct task priorities
+pub const PRIORITY_LEVELS: usize = 32;
+
+/// Maximum number of levels a task is boosted above its base priority
+const MAX_BOOST: u8 = 4;
+
+/// Lowest nice value, giving the largest share of the CPU
+pub const MIN_NICE: i8 = -20;
+
+/// Highest nice value, giving the smallest share of the CPU
+pub const MAX_NICE: i8 = 19;
+
+/// Priority of a kernel task, tasks with higher priorities are run first
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TaskPriority(u8);
+
+impl TaskPriority {
     /// Highest priority, meant for interrupt handlers
-    IRQ,
-    /// Normal priority, meant for all other kernel tasks
-    NORMAL,
+    pub const IRQ: TaskPriority = TaskPriority(PRIORITY_LEVELS as u8 - 1);
+    /// Highest priority of other tasks, including any boost
+    pub const MAX: TaskPriority = TaskPriority(PRIORITY_LEVELS as u8 - 2);
+    /// Normal priority, meant for most kernel tasks
+    pub const NORMAL: TaskPriority = TaskPriority(16);
+    /// Lowest priority, meant for background work
+    pub const MIN: TaskPriority = TaskPriority(0);
+
+    /// Construct a `TaskPriority` of `level`, which must be less than `PRIORITY_LEVELS`
+    pub fn new(level: usize) -> TaskPriority {
+        assert!(level < PRIORITY_LEVELS, "invalid priority {}", level);
+        TaskPriority(level as u8)
+    }
+
+    /// Returns the numeric level of this priority
+    pub fn level(&self) -> usize {
+        self.0 as usize
+    }
 }
 
 /// A task to be run by the kernel.
@@ -28,10 +96,31 @@ pub enum TaskPriority {
 #[derive(Debug)]
 pub struct Task {
     id: u32,
+    /// Name shown in debugging output
+    name: String,
     context: TaskContext,
     status: TaskStatus,
     priority: TaskPriority,
+    /// Levels added to `priority` while the task keeps blocking before its quantum expires
+    boost: u8,
+    /// Set when the task has been READY too long, it is then run once at `TaskPriority::MAX`
+    starving: bool,
+    /// Clock tick at which the task was last queued to run
+    ready_since: usize,
+    /// Nice value from -20 to 19, lower values receive a larger share of the CPU
+    nice: i8,
+    /// Runtime weighted by `nice`, used by the fair scheduling policy
+    vruntime: u64,
+    stats: TaskStats,
+    /// Clock tick at which the task was last queued to run or started waiting
+    queued_since: usize,
     stack: Stack,
+    address_space: Option<Arc<AddressSpace>>,
+    /// Taken by `execute` when the task first runs
+    entry: Option<Entry>,
+    exit: Arc<TaskExit>,
+    /// Saved FPU registers, allocated when the task first uses the FPU
+    fpu_state: Option<FpuState>,
 }
 
 impl Task {
@@ -41,24 +130,37 @@ impl Task {
     pub fn default(id: u32) -> Task {
         Task {
             id: id,
+            name: String::new(),
             context: TaskContext::new(),
             status: TaskStatus::READY,
             priority: TaskPriority::NORMAL,
+            boost: 0,
+            starving: false,
+            ready_since: 0,
+            nice: 0,
+            vruntime: 0,
+            stats: TaskStats::default(),
+            queued_since: 0,
             stack: Stack {
                 start_address: 0,
                 size: 0,
             },
+            address_space: None,
+            entry: None,
+            exit: Arc::new(TaskExit::new()),
+            fpu_state: None,
         }
     }
 
-    /// Create a new task with a stack and function to run
+    /// Create a new task with a stack and entry point to run
     ///
     /// The CS and RFLAGS registers are hardcoded with working values. RIP is set to the address of
-    /// the `execute` function with the first argument (RDI) holding the adress of `fun`.
+    /// the `execute` function, which takes `entry` from the task once it runs.
     pub fn new(
         id: u32,
+        name: &str,
         stack: Stack,
-        fun: fn(),
+        entry: TaskEntry,
         priority: TaskPriority,
         status: TaskStatus,
     ) -> Task {
@@ -67,18 +169,28 @@ impl Task {
         context.rflags = 582;
         context.rsp = stack.top() as u64;
 
-        // Assign the entry point of the task to the execute function, passing the 'fun' function
-        // as an argument in the rdi register.
+        // Assign the entry point of the task to the execute function
         context.rip = (execute as *const ()) as u64;
-        context.rdi = (fun as *const ()) as u64;
 
         // Create the task
         Task {
             id: id,
+            name: String::from(name),
             context: context,
             status: status,
             priority: priority,
+            boost: 0,
+            starving: false,
+            ready_since: 0,
+            nice: 0,
+            vruntime: 0,
+            stats: TaskStats::default(),
+            queued_since: 0,
             stack: stack,
+            address_space: None,
+            entry: Some(Entry(entry)),
+            exit: Arc::new(TaskExit::new()),
+            fpu_state: None,
         }
     }
 
@@ -102,20 +214,204 @@ impl Task {
         self.status
     }
 
-    /// Return the current Task priority
+    /// Return the priority the Task is scheduled at.
+    ///
+    /// This is the base priority raised by any boost, but never above `TaskPriority::MAX` unless
+    /// the base priority is `IRQ`.
     pub fn get_priority(&self) -> TaskPriority {
+        if self.priority == TaskPriority::IRQ {
+            return TaskPriority::IRQ;
+        }
+        if self.starving {
+            return TaskPriority::MAX;
+        }
+
+        cmp::min(TaskPriority(self.priority.0 + self.boost), TaskPriority::MAX)
+    }
+
+    /// Return the base priority of the Task, ignoring any boost
+    pub fn base_priority(&self) -> TaskPriority {
         self.priority
     }
 
+    /// Change the base priority of the Task
+    pub fn set_priority(&mut self, priority: TaskPriority) {
+        self.priority = priority;
+    }
+
+    /// Boost the priority of the Task, as it blocked before using its whole quantum
+    pub fn raise_boost(&mut self) {
+        self.boost = cmp::min(self.boost + 1, MAX_BOOST);
+    }
+
+    /// Reduce any boost to the priority of the Task, as it used its whole quantum
+    pub fn lower_boost(&mut self) {
+        self.boost = self.boost.saturating_sub(1);
+    }
+
+    /// Mark the Task as starved of CPU time, or clear the mark once it has run
+    pub fn set_starving(&mut self, starving: bool) {
+        self.starving = starving;
+    }
+
+    /// Return the clock tick at which the Task was last queued to run
+    pub fn ready_since(&self) -> usize {
+        self.ready_since
+    }
+
+    /// Record that the Task was queued to run at clock tick `now`
+    pub fn set_ready_since(&mut self, now: usize) {
+        self.ready_since = now;
+    }
+
+    /// Return the nice value of the Task
+    pub fn nice(&self) -> i8 {
+        self.nice
+    }
+
+    /// Change the nice value of the Task, clamped to the range -20 to 19
+    pub fn set_nice(&mut self, nice: i8) {
+        self.nice = cmp::max(MIN_NICE, cmp::min(nice, MAX_NICE));
+    }
+
+    /// Return the weighted runtime of the Task
+    pub fn vruntime(&self) -> u64 {
+        self.vruntime
+    }
+
+    /// Change the weighted runtime of the Task
+    pub fn set_vruntime(&mut self, vruntime: u64) {
+        self.vruntime = vruntime;
+    }
+
+    /// Return the CPU time and scheduling statistics of the Task
+    pub fn stats(&self) -> &TaskStats {
+        &self.stats
+    }
+
+    /// Count a clock tick that interrupted the running Task, in user mode if `user`
+    pub fn account_tick(&mut self, user: bool) {
+        if user {
+            self.stats.user_ticks += 1;
+        } else {
+            self.stats.kernel_ticks += 1;
+        }
+    }
+
+    /// Count the Task being switched out, voluntarily unless it is still READY
+    pub fn account_switch(&mut self) {
+        if self.status == TaskStatus::READY {
+            self.stats.involuntary_switches += 1;
+        } else {
+            self.stats.voluntary_switches += 1;
+        }
+    }
+
+    /// Record that the Task was queued to run or started waiting at clock tick `now`
+    pub fn account_queued(&mut self, now: usize) {
+        self.queued_since = now;
+    }
+
+    /// Add the time since `account_queued` to the READY or WAITING time of the Task, according to
+    /// its current status.
+    ///
+    /// Called as the Task is switched to, or its status is changed.
+    pub fn account_dequeued(&mut self, now: usize) {
+        let ticks = now - self.queued_since;
+        match self.status {
+            TaskStatus::READY => self.stats.ready_ticks += ticks,
+            TaskStatus::WAITING => self.stats.waiting_ticks += ticks,
+            TaskStatus::COMPLETED => {}
+        }
+        self.queued_since = now;
+    }
+
     /// Return the id of the Task
     pub fn id(&self) -> u32 {
         self.id
     }
+
+    /// Return the name of the Task
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Change the name of the Task
+    pub fn set_name(&mut self, name: &str) {
+        self.name = String::from(name);
+    }
+
+    /// Return the bytes of stack the Task was using when it was last switched out.
+    ///
+    /// Returns `None` if the Task has no stack of its own, as for the boot stack of the idle task,
+    /// or if it was last switched out in user mode.
+    pub fn stack_usage(&self) -> Option<usize> {
+        let rsp = self.context.rsp as usize;
+        if self.stack.size == 0 || rsp < self.stack.start_address || rsp > self.stack.top() {
+            return None;
+        }
+
+        Some(self.stack.top() - rsp)
+    }
+
+    /// Return the most bytes of stack the Task has used, `None` if it has no stack of its own
+    pub fn stack_high_water_mark(&self) -> Option<usize> {
+        if self.stack.size == 0 {
+            return None;
+        }
+
+        Some(self.stack.high_water_mark())
+    }
+
+    /// Return false if the Task has overflowed its stack, overwriting the canary at the bottom
+    pub fn stack_canary_intact(&self) -> bool {
+        self.stack.canary_intact()
+    }
+
+    /// Run this Task in `address_space` rather than the kernel address space
+    pub fn set_address_space(&mut self, address_space: Arc<AddressSpace>) {
+        self.address_space = Some(address_space);
+    }
+
+    /// Return the address space of the Task, `None` if it runs in the kernel address space
+    pub fn address_space(&self) -> Option<&Arc<AddressSpace>> {
+        self.address_space.as_ref()
+    }
+
+    /// Take the address space of the Task, which must not run again
+    pub fn take_address_space(&mut self) -> Option<Arc<AddressSpace>> {
+        self.address_space.take()
+    }
+
+    /// Return the saved FPU registers of the Task, creating them if it has not used the FPU
+    pub fn fpu_state_mut(&mut self) -> &mut FpuState {
+        if self.fpu_state.is_none() {
+            self.fpu_state = Some(FpuState::new());
+        }
+
+        self.fpu_state.as_mut().unwrap()
+    }
+
+    /// Returns a new handle to this Task
+    pub fn handle(&self) -> TaskHandle {
+        TaskHandle::new(self.id, self.exit.clone())
+    }
+
+    /// Make the Task COMPLETED with exit code `code`, waking any tasks joining it
+    pub fn complete(&mut self, code: i32) {
+        self.status = TaskStatus::COMPLETED;
+        self.exit.set(code);
+    }
+
+    /// Take the entry point of the Task, `None` once it has been taken
+    fn take_entry(&mut self) -> Option<TaskEntry> {
+        self.entry.take().map(|Entry(entry)| entry)
+    }
 }
 
 impl Drop for Task {
     fn drop(&mut self) {
-        kprintln!("Task::Drop");
+        ktrace!("Task::Drop {}", self.id);
 
         use kernel::kget;
         let mm = unsafe { &mut *kget().memory_manager.get() };
@@ -125,21 +421,34 @@ impl Drop for Task {
     }
 }
 
-/// Wraps execution of a function with safe thread termination
-fn execute(fun: fn()) {
+/// Runs the entry point of the active task, then exits with the code it returns
+fn execute() -> ! {
     use kernel::kget;
 
-    // Execute the function
-    fun();
-
-    // Get the 'active' task
     let scheduler = unsafe { &mut *kget().scheduler.get() };
-    let task = scheduler.get_active_task_mut().unwrap();
+    let entry = scheduler
+        .get_active_task_mut()
+        .and_then(|t| t.take_entry())
+        .expect("Task has no entry point");
 
-    // Set it to not active (COMPLETED)
-    task.set_status(TaskStatus::COMPLETED);
+    let code = entry();
+    exit(code);
+}
+
+/// Exit the active task with `code`.
+///
+/// The task is made COMPLETED and switched out at the next reschedule, which drops it.
+pub fn exit(code: i32) -> ! {
+    use kernel::kget;
+
+    let scheduler = unsafe { &mut *kget().scheduler.get() };
+    cpu::without_interrupts(|| {
+        scheduler
+            .get_active_task_mut()
+            .expect("No active task to exit")
+            .complete(code);
+        scheduler.set_need_resched();
+    });
 
-    // TODO set scheduler to reschedule and halt! instead of hang!. Consider unreachable! after
-    // the halt
     hang!();
 }
@@ -0,0 +1,59 @@
+use alloc::btree_set::BTreeSet;
+
+use super::TID_BOTTOMHALFD;
+
+/// First id given to tasks created after the system tasks
+const FIRST_TID: u32 = TID_BOTTOMHALFD + 1;
+
+/// Ids are allocated below this, wrapping around to `FIRST_TID`
+const MAX_TID: u32 = 32768;
+
+/// Allocator of task ids.
+///
+/// Ids are handed out in increasing order, wrapping around once `MAX_TID` is reached and skipping
+/// any still in use, so a freed id is not reused until every other id has been.
+///
+/// An id is freed once its task has exited. Anything that must not mistake a new task for an
+/// exited one with the same id, such as a `WaitQueue`, holds a `TaskHandle` instead, which
+/// records the exit.
+pub struct TidAllocator {
+    used: BTreeSet<u32>,
+    next: u32,
+}
+
+impl TidAllocator {
+    /// Construct a `TidAllocator` with every id free, the ids of the system tasks are never
+    /// allocated
+    pub fn new() -> TidAllocator {
+        TidAllocator {
+            used: BTreeSet::new(),
+            next: FIRST_TID,
+        }
+    }
+
+    /// Allocate an id, returning `None` if every id is in use
+    pub fn allocate(&mut self) -> Option<u32> {
+        if self.used.len() == (MAX_TID - FIRST_TID) as usize {
+            return None;
+        }
+
+        loop {
+            let id = self.next;
+            self.next = if id + 1 == MAX_TID { FIRST_TID } else { id + 1 };
+
+            if self.used.insert(id) {
+                return Some(id);
+            }
+        }
+    }
+
+    /// Free `id` so it may be allocated again
+    pub fn free(&mut self, id: u32) {
+        self.used.remove(&id);
+    }
+
+    /// Returns true if `id` is allocated
+    pub fn is_used(&self, id: u32) -> bool {
+        self.used.contains(&id)
+    }
+}
@@ -0,0 +1,154 @@
+use alloc::linked_list::LinkedList;
+
+use spin::Mutex;
+
+use cpu;
+use kernel::kget;
+
+use super::Scheduler;
+use super::task::{TaskHandle, TaskStatus};
+
+/// A queue of tasks WAITING for an event.
+///
+/// Tasks block on the queue with `wait_on` or `wait_event`, and are made READY by `wake_one` or
+/// `wake_all` once the event happens. Waking may be done from interrupt handlers.
+///
+/// The queue is only accessed with interrupts disabled, so an interrupt handler can't find it
+/// locked by the task it interrupted.
+///
+/// Tasks are held by `TaskHandle` rather than id, as a task may exit while waiting, for example if
+/// it is killed, and its id be reused by an unrelated task.
+pub struct WaitQueue {
+    /// The waiting tasks, in the order they started waiting
+    tasks: Mutex<LinkedList<TaskHandle>>,
+}
+
+impl WaitQueue {
+    /// Construct an empty `WaitQueue`
+    pub fn new() -> WaitQueue {
+        WaitQueue {
+            tasks: Mutex::new(LinkedList::new()),
+        }
+    }
+
+    /// Returns true if no tasks are waiting
+    pub fn is_empty(&self) -> bool {
+        cpu::without_interrupts(|| self.tasks.lock().is_empty())
+    }
+
+    /// Make the task that has waited longest READY, returning false if no task was waiting
+    pub fn wake_one(&self) -> bool {
+        cpu::without_interrupts(|| {
+            let scheduler = unsafe { &mut *kget().scheduler.get() };
+            let mut tasks = self.tasks.lock();
+
+            // Skip any tasks that stopped waiting for another reason
+            while let Some(task) = tasks.pop_front() {
+                if wake(scheduler, &task) {
+                    return true;
+                }
+            }
+            false
+        })
+    }
+
+    /// Make every waiting task READY, returning the number woken
+    pub fn wake_all(&self) -> usize {
+        cpu::without_interrupts(|| {
+            let scheduler = unsafe { &mut *kget().scheduler.get() };
+            let mut tasks = self.tasks.lock();
+
+            let mut woken = 0;
+            while let Some(task) = tasks.pop_front() {
+                if wake(scheduler, &task) {
+                    woken += 1;
+                }
+            }
+            woken
+        })
+    }
+
+    /// Add the active task to the queue and make it WAITING. Interrupts must be disabled.
+    fn prepare_to_wait(&self) {
+        let scheduler = unsafe { &mut *kget().scheduler.get() };
+        let task = scheduler
+            .wait_active_task()
+            .expect("No active task to wait");
+
+        self.tasks.lock().push_back(task);
+    }
+}
+
+/// Make `task` READY if it is WAITING, returning true if it was.
+///
+/// A task that has exited is skipped, its id may now belong to another task.
+fn wake(scheduler: &mut Scheduler, task: &TaskHandle) -> bool {
+    task.exit_code().is_none() && scheduler.wake_task(task.id())
+}
+
+/// Block the active task on `queue` until it is woken.
+pub fn wait_on(queue: &WaitQueue) {
+    cpu::without_interrupts(|| queue.prepare_to_wait());
+    sleep();
+}
+
+/// Block the active task on `queue` until it is woken, calling `release` once it has joined the
+/// queue.
+///
+/// The task joins the queue before `release` is called, so it can't miss a wake that follows it,
+/// for example from another task that takes a mutex `release` unlocks.
+pub fn wait_on_releasing<F: FnOnce()>(queue: &WaitQueue, release: F) {
+    cpu::without_interrupts(|| {
+        queue.prepare_to_wait();
+        release();
+    });
+    sleep();
+}
+
+/// Block the active task on `queue` until `condition` returns true.
+///
+/// `condition` is checked with interrupts disabled before each wait, so it can't become true
+/// between the check and the task joining the queue. It is checked again every time the task
+/// is woken.
+pub fn wait_event<F: FnMut() -> bool>(queue: &WaitQueue, mut condition: F) {
+    loop {
+        let done = cpu::without_interrupts(|| {
+            if condition() {
+                return true;
+            }
+            queue.prepare_to_wait();
+            false
+        });
+
+        if done {
+            return;
+        }
+        sleep();
+    }
+}
+
+/// Halt until the active task is no longer WAITING.
+///
+/// The task is switched out at the first reschedule, halting just waits for that to happen. If no
+/// other task is READY the task stays active and keeps halting until it is woken.
+fn sleep() {
+    assert!(
+        cpu::interrupts_enabled(),
+        "Waiting with interrupts disabled would never wake"
+    );
+
+    let scheduler = unsafe { &*kget().scheduler.get() };
+    loop {
+        unsafe { ::x86::irq::disable() };
+
+        let waiting = scheduler
+            .get_active_task()
+            .map_or(false, |t| t.get_status() == TaskStatus::WAITING);
+        if !waiting {
+            unsafe { ::x86::irq::enable() };
+            return;
+        }
+
+        cpu::enable_interrupts_and_halt();
+    }
+}
@@ -0,0 +1,57 @@
+use schedule::{wait_on_releasing, WaitQueue};
+
+use super::mutex::MutexGuard;
+
+/// A condition variable, blocking tasks until another task notifies them of a change to the data
+/// protected by a `Mutex`.
+pub struct CondVar {
+    queue: WaitQueue,
+}
+
+impl CondVar {
+    /// Construct a `CondVar` with no waiting tasks
+    pub fn new() -> CondVar {
+        CondVar {
+            queue: WaitQueue::new(),
+        }
+    }
+
+    /// Release the lock held by `guard` and block the active task until notified, then take the
+    /// lock again.
+    ///
+    /// The task may be woken by other events, see `wait_while`.
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = MutexGuard::mutex(&guard);
+
+        // The task starts waiting before the lock is released, so a notification from a task
+        // that then takes the lock can't be missed
+        wait_on_releasing(&self.queue, move || drop(guard));
+
+        mutex.lock()
+    }
+
+    /// Block the active task while `condition` returns true for the data protected by `guard`
+    pub fn wait_while<'a, T, F>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: F,
+    ) -> MutexGuard<'a, T>
+    where
+        F: FnMut(&mut T) -> bool,
+    {
+        while condition(&mut *guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    /// Wake the task that has waited longest, returning false if no task was waiting
+    pub fn notify_one(&self) -> bool {
+        self.queue.wake_one()
+    }
+
+    /// Wake every waiting task, returning the number woken
+    pub fn notify_all(&self) -> usize {
+        self.queue.wake_all()
+    }
+}
@@ -0,0 +1,83 @@
+use alloc::btree_map::BTreeMap;
+
+use spin::Mutex;
+
+use cpu;
+
+/// Which task owns each lock and which lock each task is waiting for, keyed by lock address
+struct WaitGraph {
+    owners: BTreeMap<usize, u32>,
+    waiting: BTreeMap<u32, usize>,
+}
+
+static GRAPH: Mutex<Option<WaitGraph>> = Mutex::new(None);
+
+fn with_graph<F: FnOnce(&mut WaitGraph) -> R, R>(f: F) -> R {
+    cpu::without_interrupts(|| {
+        let mut graph = GRAPH.lock();
+        if graph.is_none() {
+            *graph = Some(WaitGraph {
+                owners: BTreeMap::new(),
+                waiting: BTreeMap::new(),
+            });
+        }
+        f(graph.as_mut().unwrap())
+    })
+}
+
+/// Record that `task` has taken the lock at `lock`
+pub fn acquired(lock: usize, task: u32) {
+    with_graph(|graph| {
+        graph.waiting.remove(&task);
+        graph.owners.insert(lock, task);
+    });
+}
+
+/// Record that `task` has stopped waiting without owning the lock it waited for
+pub fn stopped_waiting(task: u32) {
+    with_graph(|graph| {
+        graph.waiting.remove(&task);
+    });
+}
+
+/// Record that the lock at `lock` has been released
+pub fn released(lock: usize) {
+    with_graph(|graph| {
+        graph.owners.remove(&lock);
+    });
+}
+
+/// Record that `task` is about to wait for the lock at `lock`.
+///
+/// Panics if the owner of the lock is, through the locks their owners are waiting for, waiting for
+/// `task`. None of these tasks could ever run again.
+pub fn waiting(lock: usize, task: u32) {
+    with_graph(|graph| {
+        let mut next = lock;
+
+        // Each task waits for at most one lock, so a chain longer than the number of owners
+        // would revisit a task
+        for _ in 0..graph.owners.len() {
+            let owner = match graph.owners.get(&next) {
+                Some(&owner) => owner,
+                None => break,
+            };
+
+            if owner == task {
+                panic!(
+                    "Deadlock: task {} waiting for lock {:#x} held by itself or a task waiting \
+                     on it",
+                    task,
+                    lock
+                );
+            }
+
+            next = match graph.waiting.get(&owner) {
+                Some(&next) => next,
+                None => break,
+            };
+        }
+
+        graph.waiting.insert(task, lock);
+    });
+}
@@ -0,0 +1,32 @@
+//! Blocking synchronization primitives for kernel tasks.
+//!
+//! Unlike `spin::Mutex`, tasks that can't take a lock here are made WAITING on a `WaitQueue` until
+//! it is released, so they don't use CPU time while they wait. As they block, these locks must
+//! only be used by tasks and never from interrupt handlers.
+//!
+//! Debug builds record the owners of locks and the locks tasks are waiting for, and panic if
+//! waiting for a lock would deadlock.
+
+mod condvar;
+mod mutex;
+mod rwlock;
+mod semaphore;
+
+#[cfg(debug_assertions)]
+mod deadlock;
+
+pub use self::condvar::CondVar;
+pub use self::mutex::{Mutex, MutexGuard};
+pub use self::rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use self::semaphore::Semaphore;
+
+use kernel::kget;
+
+/// Returns the id of the active task, which is the task taking a lock
+fn current_task() -> u32 {
+    let scheduler = unsafe { &*kget().scheduler.get() };
+    scheduler
+        .get_active_task()
+        .map(|t| t.id())
+        .expect("Blocking lock used outside of a task")
+}
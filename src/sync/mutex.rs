@@ -0,0 +1,132 @@
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+
+use spin;
+
+use cpu;
+use schedule::{wait_event, WaitQueue};
+
+use super::current_task;
+
+#[cfg(debug_assertions)]
+use super::deadlock;
+
+/// A mutual exclusion lock that blocks the tasks waiting for it.
+///
+/// The lock is owned by the task that took it, which must also release it by dropping the
+/// `MutexGuard`.
+pub struct Mutex<T> {
+    /// Id of the task holding the lock
+    owner: spin::Mutex<Option<u32>>,
+    queue: WaitQueue,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for Mutex<T> {}
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    /// Construct an unlocked `Mutex` protecting `data`
+    pub fn new(data: T) -> Mutex<T> {
+        Mutex {
+            owner: spin::Mutex::new(None),
+            queue: WaitQueue::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Take the lock, blocking the active task until it is available.
+    ///
+    /// In debug builds this panics if waiting would deadlock, including if the active task
+    /// already holds the lock.
+    pub fn lock(&self) -> MutexGuard<T> {
+        let id = current_task();
+
+        if !self.try_acquire(id) {
+            #[cfg(debug_assertions)]
+            deadlock::waiting(self.address(), id);
+
+            wait_event(&self.queue, || self.try_acquire(id));
+        }
+
+        #[cfg(debug_assertions)]
+        deadlock::acquired(self.address(), id);
+
+        MutexGuard { mutex: self }
+    }
+
+    /// Take the lock if it is available, without blocking
+    pub fn try_lock(&self) -> Option<MutexGuard<T>> {
+        let id = current_task();
+        if !self.try_acquire(id) {
+            return None;
+        }
+
+        #[cfg(debug_assertions)]
+        deadlock::acquired(self.address(), id);
+
+        Some(MutexGuard { mutex: self })
+    }
+
+    /// Returns the id of the task holding the lock
+    pub fn owner(&self) -> Option<u32> {
+        cpu::without_interrupts(|| *self.owner.lock())
+    }
+
+    fn try_acquire(&self, id: u32) -> bool {
+        cpu::without_interrupts(|| {
+            let mut owner = self.owner.lock();
+            if owner.is_some() {
+                return false;
+            }
+
+            *owner = Some(id);
+            true
+        })
+    }
+
+    fn unlock(&self) {
+        #[cfg(debug_assertions)]
+        deadlock::released(self.address());
+
+        cpu::without_interrupts(|| *self.owner.lock() = None);
+        self.queue.wake_one();
+    }
+
+    #[cfg(debug_assertions)]
+    fn address(&self) -> usize {
+        self as *const Mutex<T> as usize
+    }
+}
+
+/// Access to the data protected by a `Mutex`, the lock is released when this is dropped
+pub struct MutexGuard<'a, T: 'a> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<'a, T> MutexGuard<'a, T> {
+    /// Returns the `Mutex` locked by `guard`
+    pub fn mutex(guard: &MutexGuard<'a, T>) -> &'a Mutex<T> {
+        guard.mutex
+    }
+}
+
+impl<'a, T> Deref for MutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for MutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<'a, T> Drop for MutexGuard<'a, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
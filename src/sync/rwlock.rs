@@ -0,0 +1,217 @@
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+
+use spin;
+
+use cpu;
+use schedule::{wait_event, WaitQueue};
+
+use super::current_task;
+
+#[cfg(debug_assertions)]
+use super::deadlock;
+
+struct State {
+    readers: usize,
+    /// Id of the task holding the write lock
+    writer: Option<u32>,
+    /// Number of tasks waiting for the write lock, new readers wait while this is not zero
+    writers_waiting: usize,
+}
+
+/// A reader-writer lock that blocks the tasks waiting for it.
+///
+/// Any number of tasks may hold the read lock, or a single task the write lock. Waiting writers
+/// are favoured over new readers, so a stream of readers can't keep a writer waiting forever.
+///
+/// Only the writer is tracked for deadlock detection in debug builds.
+pub struct RwLock<T> {
+    state: spin::Mutex<State>,
+    queue: WaitQueue,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for RwLock<T> {}
+unsafe impl<T: Send + Sync> Sync for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    /// Construct an unlocked `RwLock` protecting `data`
+    pub fn new(data: T) -> RwLock<T> {
+        RwLock {
+            state: spin::Mutex::new(State {
+                readers: 0,
+                writer: None,
+                writers_waiting: 0,
+            }),
+            queue: WaitQueue::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Take the read lock, blocking the active task while there is or may soon be a writer
+    pub fn read(&self) -> RwLockReadGuard<T> {
+        if !self.try_acquire_read() {
+            #[cfg(debug_assertions)]
+            deadlock::waiting(self.address(), current_task());
+
+            wait_event(&self.queue, || self.try_acquire_read());
+
+            #[cfg(debug_assertions)]
+            deadlock::stopped_waiting(current_task());
+        }
+
+        RwLockReadGuard { lock: self }
+    }
+
+    /// Take the read lock if it is available, without blocking
+    pub fn try_read(&self) -> Option<RwLockReadGuard<T>> {
+        if self.try_acquire_read() {
+            Some(RwLockReadGuard { lock: self })
+        } else {
+            None
+        }
+    }
+
+    /// Take the write lock, blocking the active task while there are readers or another writer
+    pub fn write(&self) -> RwLockWriteGuard<T> {
+        let id = current_task();
+
+        if !self.try_acquire_write(id, false) {
+            cpu::without_interrupts(|| self.state.lock().writers_waiting += 1);
+
+            #[cfg(debug_assertions)]
+            deadlock::waiting(self.address(), id);
+
+            wait_event(&self.queue, || self.try_acquire_write(id, true));
+        }
+
+        #[cfg(debug_assertions)]
+        deadlock::acquired(self.address(), id);
+
+        RwLockWriteGuard { lock: self }
+    }
+
+    /// Take the write lock if it is available, without blocking
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<T>> {
+        let id = current_task();
+        if !self.try_acquire_write(id, false) {
+            return None;
+        }
+
+        #[cfg(debug_assertions)]
+        deadlock::acquired(self.address(), id);
+
+        Some(RwLockWriteGuard { lock: self })
+    }
+
+    /// Returns the id of the task holding the write lock
+    pub fn writer(&self) -> Option<u32> {
+        cpu::without_interrupts(|| self.state.lock().writer)
+    }
+
+    /// Returns the number of tasks holding the read lock
+    pub fn readers(&self) -> usize {
+        cpu::without_interrupts(|| self.state.lock().readers)
+    }
+
+    fn try_acquire_read(&self) -> bool {
+        cpu::without_interrupts(|| {
+            let mut state = self.state.lock();
+            if state.writer.is_some() || state.writers_waiting > 0 {
+                return false;
+            }
+
+            state.readers += 1;
+            true
+        })
+    }
+
+    /// Take the write lock for `id`, `waiting` is true if the task is counted in `writers_waiting`
+    fn try_acquire_write(&self, id: u32, waiting: bool) -> bool {
+        cpu::without_interrupts(|| {
+            let mut state = self.state.lock();
+            if state.writer.is_some() || state.readers > 0 {
+                return false;
+            }
+
+            state.writer = Some(id);
+            if waiting {
+                state.writers_waiting -= 1;
+            }
+            true
+        })
+    }
+
+    fn read_unlock(&self) {
+        let readers = cpu::without_interrupts(|| {
+            let mut state = self.state.lock();
+            state.readers -= 1;
+            state.readers
+        });
+
+        if readers == 0 {
+            self.queue.wake_all();
+        }
+    }
+
+    fn write_unlock(&self) {
+        #[cfg(debug_assertions)]
+        deadlock::released(self.address());
+
+        cpu::without_interrupts(|| self.state.lock().writer = None);
+
+        // Wake everyone, readers that find another writer waiting simply wait again
+        self.queue.wake_all();
+    }
+
+    #[cfg(debug_assertions)]
+    fn address(&self) -> usize {
+        self as *const RwLock<T> as usize
+    }
+}
+
+/// Shared access to the data protected by a `RwLock`, the read lock is released when this is
+/// dropped
+pub struct RwLockReadGuard<'a, T: 'a> {
+    lock: &'a RwLock<T>,
+}
+
+impl<'a, T> Deref for RwLockReadGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T> Drop for RwLockReadGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.read_unlock();
+    }
+}
+
+/// Exclusive access to the data protected by a `RwLock`, the write lock is released when this is
+/// dropped
+pub struct RwLockWriteGuard<'a, T: 'a> {
+    lock: &'a RwLock<T>,
+}
+
+impl<'a, T> Deref for RwLockWriteGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for RwLockWriteGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<'a, T> Drop for RwLockWriteGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.write_unlock();
+    }
+}
@@ -0,0 +1,52 @@
+use spin;
+
+use cpu;
+use schedule::{wait_event, WaitQueue};
+
+/// A counting semaphore that blocks the tasks waiting for it.
+///
+/// Unlike `Mutex` a semaphore has no owner, it may be released by a different task than acquired
+/// it, or from an interrupt handler.
+pub struct Semaphore {
+    count: spin::Mutex<usize>,
+    queue: WaitQueue,
+}
+
+impl Semaphore {
+    /// Construct a `Semaphore` that may be acquired `count` times before blocking
+    pub fn new(count: usize) -> Semaphore {
+        Semaphore {
+            count: spin::Mutex::new(count),
+            queue: WaitQueue::new(),
+        }
+    }
+
+    /// Decrement the count, blocking the active task while it is zero
+    pub fn acquire(&self) {
+        wait_event(&self.queue, || self.try_acquire());
+    }
+
+    /// Decrement the count if it is not zero, returning true if it was decremented
+    pub fn try_acquire(&self) -> bool {
+        cpu::without_interrupts(|| {
+            let mut count = self.count.lock();
+            if *count == 0 {
+                return false;
+            }
+
+            *count -= 1;
+            true
+        })
+    }
+
+    /// Increment the count, waking a task waiting to acquire the semaphore
+    pub fn release(&self) {
+        cpu::without_interrupts(|| *self.count.lock() += 1);
+        self.queue.wake_one();
+    }
+
+    /// Returns the current count
+    pub fn count(&self) -> usize {
+        cpu::without_interrupts(|| *self.count.lock())
+    }
+}
@@ -1,6 +1,10 @@
-use spin::Mutex;
+use sync_opsys::IrqSpinLock;
 use core::fmt;
 use io::Port;
+use memory::KERNEL_OFFSET;
+
+/// Higher half address of the text buffer at physical address 0xb8000
+const BUFFER_ADDRESS: usize = KERNEL_OFFSET + 0xb8000;
 
 #[allow(dead_code)]
 #[repr(u8)]
@@ -55,10 +59,10 @@ pub struct Writer {
     buffer: Unique<Buffer>,
 }
 
-pub static WRITER: Mutex<Writer> = Mutex::new(Writer {
+pub static WRITER: IrqSpinLock<Writer> = IrqSpinLock::new(Writer {
     column_position: 0,
     color_code: ColorCode::new(Color::White, Color::Black),
-    buffer: unsafe { Unique::new_unchecked(0xb8000 as *mut _) },
+    buffer: unsafe { Unique::new_unchecked(BUFFER_ADDRESS as *mut _) },
 });
 
 impl Writer {
@@ -143,33 +147,32 @@ impl ::core::fmt::Write for Writer {
     }
 }
 
-macro_rules! kprint {
-    ($($arg:tt)*) => ({
-            use core::fmt::Write;
-            let mut writer = $crate::vga_buffer::WRITER.lock();
-            writer.write_fmt(format_args!($($arg)*)).unwrap();
-    });
-}
-
-macro_rules! kprintln {
-    ($fmt:expr) => (kprint!(concat!($fmt, "\n")));
-    ($fmt:expr, $($arg:tt)*) => (kprint!(concat!($fmt, "\n"), $($arg)*));
-}
-
 pub fn clear_screen() {
+    // Only the VGA buffer is cleared, printing blank lines through `kprintln!` would also flood the
+    // serial console.
+    let mut writer = WRITER.lock();
     for _ in 0..BUFFER_HEIGHT {
-        kprintln!("");
+        writer.new_line();
+    }
+    writer.update_cursor();
+}
+
+/// Construct a new `Writer` printing in red.
+///
+/// # Safety
+/// The returned `Writer` is not synchronized with `WRITER`, output from the two may interleave.
+pub unsafe fn error_writer() -> Writer {
+    Writer {
+        column_position: 0,
+        color_code: ColorCode::new(Color::Red, Color::Black),
+        buffer: Unique::new_unchecked(BUFFER_ADDRESS as *mut _),
     }
 }
 
 pub unsafe fn print_error(fmt: fmt::Arguments) {
     use core::fmt::Write;
 
-    let mut writer = Writer {
-        column_position: 0,
-        color_code: ColorCode::new(Color::Red, Color::Black),
-        buffer: Unique::new_unchecked(0xb8000 as *mut _),
-    };
+    let mut writer = error_writer();
 
     let _ = writer.write_fmt(fmt);
     writer.new_line();